@@ -0,0 +1,432 @@
+//! Compilation of catalogues in build scripts
+//!
+//! [`Builder`] finds the PO files of a project, checks them like [`msgfmt --check`][msgfmt]
+//! (see the [`check`] module) and compiles them into `OUT_DIR`, either to MO files or to Rust
+//! source with static tables to include in the program. In `build.rs`:
+//!
+//! ```no_run
+//! extern crate translate_storage;
+//!
+//! use translate_storage::build::{Builder, Output};
+//!
+//! fn main() {
+//!     Builder::new("myapp").output(Output::Rust).run();
+//! }
+//! ```
+//!
+//! and in the program, the tables are loaded with [`Translator::from_table`]:
+//!
+//! ```ignore
+//! include!(concat!(env!("OUT_DIR"), "/myapp.rs"));
+//!
+//! let translators = CATALOGUES.iter()
+//!     .map(|&(lang, plural_forms, messages)| Translator::from_table(lang, plural_forms, messages));
+//! ```
+//!
+//! [`Builder`]: struct.Builder.html
+//! [`check`]: ../check/index.html
+//! [`Translator::from_table`]: ../translator/struct.Translator.html#method.from_table
+//! [msgfmt]: https://www.gnu.org/software/gettext/manual/html_node/msgfmt-Invocation.html
+
+use check;
+use locale::{Layout, Locales};
+use locale_config::LanguageRange;
+use mo::MoWriter;
+use po::PoReader;
+use std::env;
+use std::fmt::{self, Write as FmtWrite};
+use std::fs;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::process;
+use super::{CatalogueReader, CatalogueWriter, Message};
+
+/// Form of the compiled catalogues.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Output {
+    /// MO files, `locale/<lang>/LC_MESSAGES/<domain>.mo` in the output directory, which can be
+    /// found with [`Layout::Gettext`] at runtime.
+    ///
+    /// [`Layout::Gettext`]: ../locale/enum.Layout.html#variant.Gettext
+    Mo,
+    /// Rust source, `<domain>.rs` in the output directory, defining static `CATALOGUES` with
+    /// tuples of language, `Plural-Forms` and messages for [`Translator::from_table`].
+    ///
+    /// [`Translator::from_table`]: ../translator/struct.Translator.html#method.from_table
+    Rust,
+}
+
+/// Problem that fails the build.
+///
+/// Parameters are the catalogue, line in it (0 if the problem does not concern a particular
+/// line) and the description.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Failure {
+    pub path: PathBuf,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:", self.path.display())?;
+        if self.line > 0 {
+            write!(f, "{}:", self.line)?;
+        }
+        write!(f, " {}", self.message)
+    }
+}
+
+/// Catalogue compiler for build scripts.
+///
+/// By default, it compiles `po/<lang>.po` in the package directory (`CARGO_MANIFEST_DIR`) to MO
+/// files in `OUT_DIR`, failing on any problem found by the [`check`] module.
+///
+/// [`check`]: ../check/index.html
+#[derive(Clone,Debug)]
+pub struct Builder {
+    _domain: String,
+    _source: Option<PathBuf>,
+    _layout: Layout,
+    _out_dir: Option<PathBuf>,
+    _output: Output,
+    _check: bool,
+}
+
+impl Builder {
+    pub fn new(domain: &str) -> Self {
+        Builder {
+            _domain: domain.to_owned(),
+            _source: None,
+            _layout: Layout::Po,
+            _out_dir: None,
+            _output: Output::Mo,
+            _check: true,
+        }
+    }
+
+    /// Set directory with the catalogues and its layout. Relative directory is relative to the
+    /// package directory.
+    pub fn source<P: AsRef<Path>>(mut self, dir: P, layout: Layout) -> Self {
+        self._source = Some(dir.as_ref().to_owned());
+        self._layout = layout;
+        self
+    }
+
+    /// Set output directory instead of `OUT_DIR`.
+    pub fn out_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self._out_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    pub fn output(mut self, output: Output) -> Self {
+        self._output = output;
+        self
+    }
+
+    /// Set whether problems found by the consistency checks fail the build. Catalogues that
+    /// cannot be read always do.
+    pub fn check(mut self, check: bool) -> Self {
+        self._check = check;
+        self
+    }
+
+    fn source_dir(&self) -> Result<PathBuf, Failure> {
+        let dir = self._source.clone().unwrap_or_else(|| PathBuf::from("po"));
+        match env::var_os("CARGO_MANIFEST_DIR") {
+            Some(base) => Ok(Path::new(&base).join(dir)),
+            None if dir.is_absolute() => Ok(dir),
+            None => Err(failure(&dir, 0, "CARGO_MANIFEST_DIR not set")),
+        }
+    }
+
+    /// Find the catalogues to compile, with the names of their languages as given by the
+    /// directory layout and the languages parsed from them. Only PO files are compiled, even
+    /// where MO files exist beside them.
+    pub fn inputs(&self) -> Result<Vec<(String, LanguageRange<'static>, PathBuf)>, Failure> {
+        let dir = self.source_dir()?;
+        let locales = Locales::scan_files(&dir, self._layout.clone(), &["po"])
+            .map_err(|e| failure(&dir, 0, e))?;
+        Ok(locales.catalogues().iter()
+            .map(|c| (language_name(&c.1, &self._layout), c.0.clone(), c.1.clone()))
+            .collect())
+    }
+
+    /// Check and compile all catalogues. Returns the files written.
+    ///
+    /// All the catalogues are checked before anything is written, and all the problems found
+    /// are returned.
+    pub fn compile(&self) -> Result<Vec<PathBuf>, Vec<Failure>> {
+        let out_dir = self._out_dir.clone().or_else(|| env::var_os("OUT_DIR").map(PathBuf::from))
+            .ok_or_else(|| vec![failure(Path::new("OUT_DIR"), 0, "not set")])?;
+        let inputs = self.inputs().map_err(|f| vec![f])?;
+        let mut failures = Vec::new();
+        let mut sources = Vec::new();
+        for (name, lang, path) in inputs {
+            match fs::read(&path) {
+                Ok(bytes) => {
+                    failures.extend(self.check_po(&path, &bytes));
+                    sources.push((name, lang, path, bytes));
+                }
+                Err(e) => failures.push(failure(&path, 0, e)),
+            }
+        }
+        if !failures.is_empty() {
+            return Err(failures);
+        }
+        match self._output {
+            Output::Mo => sources.iter()
+                .map(|(name, lang, path, bytes)| {
+                    let dir = out_dir.join("locale").join(name).join("LC_MESSAGES");
+                    let out = dir.join(format!("{}.mo", self._domain));
+                    fs::create_dir_all(&dir).map_err(|e| failure(&out, 0, e))?;
+                    write_mo(bytes, lang, &out).map_err(|e| failure(path, 0, e))?;
+                    Ok(out)
+                })
+                .collect::<Result<Vec<_>, _>>().map_err(|f| vec![f]),
+            Output::Rust => {
+                let out = out_dir.join(format!("{}.rs", self._domain));
+                let mut code = String::from("// Generated by translate-storage. Do not edit.\n\n\
+                    pub static CATALOGUES: &[(&str, &str, &[(Option<&str>, &str, &[&str])])] = &[\n");
+                for (_, lang, path, bytes) in &sources {
+                    write_table(bytes, lang, &mut code).map_err(|e| vec![failure(path, 0, e)])?;
+                }
+                code.push_str("];\n");
+                fs::create_dir_all(&out_dir).map_err(|e| vec![failure(&out_dir, 0, e)])?;
+                fs::write(&out, code).map_err(|e| vec![failure(&out, 0, e)])?;
+                Ok(vec![out])
+            }
+        }
+    }
+
+    /// Compile the catalogues from `build.rs`.
+    ///
+    /// Tells cargo to rerun the build script when the catalogues or the directory with them
+    /// change, and if the compilation fails, prints the problems and exits with error.
+    pub fn run(&self) {
+        let res = self.source_dir().map_err(|f| vec![f]).and_then(|dir| {
+            println!("cargo:rerun-if-changed={}", dir.display());
+            for (_, _, path) in self.inputs().map_err(|f| vec![f])? {
+                println!("cargo:rerun-if-changed={}", path.display());
+            }
+            self.compile()
+        });
+        if let Err(failures) = res {
+            for f in &failures {
+                eprintln!("error: {}", f);
+            }
+            eprintln!("error: {} problem(s) in translation catalogues", failures.len());
+            process::exit(1);
+        }
+    }
+
+    // Only targets of the units that are compiled are checked, which leaves out fuzzy ones like
+    // `write_mo` and `write_table` do.
+    fn check_po(&self, path: &Path, bytes: &[u8]) -> Vec<Failure> {
+        match check::check_reader(PoReader::new(bytes)) {
+            Ok(_) if !self._check => Vec::new(),
+            Ok(diags) => diags.into_iter().map(|d| failure(path, d.line, d.problem)).collect(),
+            Err(e) => vec![failure(path, 0, e)],
+        }
+    }
+}
+
+fn failure<M: ToString>(path: &Path, line: usize, message: M) -> Failure {
+    Failure { path: path.to_owned(), line, message: message.to_string() }
+}
+
+// Name of the language directory or file, which may be in Unix format the gettext runtime uses.
+fn language_name(path: &Path, layout: &Layout) -> String {
+    let name = match *layout {
+        Layout::Gettext(_) => path.parent().and_then(Path::parent).and_then(Path::file_name),
+        Layout::Po => path.file_stem(),
+    };
+    name.map_or(String::new(), |n| n.to_string_lossy().into_owned())
+}
+
+fn write_mo(po: &[u8], lang: &LanguageRange, out: &Path) -> Result<(), ::Error> {
+    let mut reader = PoReader::new(po);
+    let file = fs::File::create(out).map_err(|e| ::Error::Io(0, e))?;
+    let mut writer = MoWriter::new(BufWriter::new(file));
+    writer.write_header(lang, reader.header_unit())?;
+    for unit in &mut reader {
+        writer.write_unit(&unit?)?;
+    }
+    writer.finish()
+}
+
+// The language is the one from the file name, like the directory of the MO file, rather than from
+// the `Language` header field, which may be missing.
+fn write_table(po: &[u8], lang: &LanguageRange, code: &mut String) -> Result<(), ::Error> {
+    let mut reader = PoReader::new(po);
+    let plural_forms = reader.property("Plural-Forms").unwrap_or("").to_owned();
+    let plurals = reader.header().plural_forms()?.unwrap_or_default()
+        .categories(lang);
+    writeln!(code, "    ({:?}, {:?}, &[", lang.as_ref(), plural_forms).unwrap();
+    for unit in &mut reader {
+        let unit = unit?;
+        if !unit.is_translated() || unit.is_obsolete() || unit.target().is_blank() {
+            continue;
+        }
+        let translations: Vec<&str> = match *unit.target() {
+            Message::Plural(ref map) =>
+                plurals.iter().map(|c| map.get(c).map_or("", String::as_str)).collect(),
+            ref m => vec![m.key()],
+        };
+        writeln!(code, "        ({:?}, {:?}, &{:?}),",
+                 unit.context(), unit.source().key(), translations).unwrap();
+    }
+    code.push_str("    ]),\n");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ::locale::{Layout, Locales};
+    use ::locale_config::{LanguageRange, Locale};
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use super::{Builder, Output};
+
+    static CS_PO: &str = r###"msgid ""
+msgstr ""
+"Project-Id-Version: test 1.0\n"
+"PO-Revision-Date: 2018-01-07 21:10+0100\n"
+"Last-Translator: Jan Hudec <bulb@ucw.cz>\n"
+"Language-Team: Czech\n"
+"Language: cs\n"
+"MIME-Version: 1.0\n"
+"Content-Type: text/plain; charset=UTF-8\n"
+"Content-Transfer-Encoding: 8bit\n"
+"Plural-Forms: nplurals=3; plural=(n==1) ? 0 : (n>=2 && n<=4) ? 1 : 2;\n"
+
+msgid "Open"
+msgstr "Otevřít"
+
+#, fuzzy
+msgid "Save"
+msgstr "Uložit"
+
+#, c-format
+msgid "%d file"
+msgid_plural "%d files"
+msgstr[0] "%d soubor"
+msgstr[1] "%d soubory"
+msgstr[2] "%d souborů"
+"###;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("translate-storage-build-{}-{}", name, ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("po")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn compile_mo() {
+        let dir = temp_dir("mo");
+        fs::write(dir.join("po/cs.po"), CS_PO).unwrap();
+        fs::write(dir.join("po/app.pot"), "").unwrap();
+        let out = dir.join("out");
+        let builder = Builder::new("app").source(dir.join("po"), Layout::Po).out_dir(&out);
+        assert_eq!(vec![("cs".to_owned(), LanguageRange::new("cs").unwrap(), dir.join("po/cs.po"))],
+                   builder.inputs().unwrap());
+        assert_eq!(vec![out.join("locale/cs/LC_MESSAGES/app.mo")], builder.compile().unwrap());
+
+        let locales = Locales::scan(out.join("locale"), Layout::Gettext("app".to_owned())).unwrap();
+        let chain = locales.load(&Locale::new("cs-CZ").unwrap()).unwrap();
+        assert_eq!("Otevřít", chain.gettext("Open"));
+        assert_eq!("Save", chain.gettext("Save"));
+        assert_eq!("%d souborů", chain.ngettext("%d file", "%d files", 5));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compile_rust() {
+        let dir = temp_dir("rust");
+        fs::write(dir.join("po/cs.po"), CS_PO).unwrap();
+        let out = dir.join("out");
+        let files = Builder::new("app").source(dir.join("po"), Layout::Po).out_dir(&out)
+            .output(Output::Rust).compile().unwrap();
+        assert_eq!(vec![out.join("app.rs")], files);
+        assert_eq!(r#"// Generated by translate-storage. Do not edit.
+
+pub static CATALOGUES: &[(&str, &str, &[(Option<&str>, &str, &[&str])])] = &[
+    ("cs", "nplurals=3; plural=(n==1) ? 0 : (n>=2 && n<=4) ? 1 : 2;", &[
+        (None, "Open", &["Otevřít"]),
+        (None, "%d file", &["%d soubor", "%d soubory", "%d souborů"]),
+    ]),
+];
+"#, fs::read_to_string(&files[0]).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compile_without_language() {
+        let dir = temp_dir("language");
+        fs::write(dir.join("po/cs_CZ.po"), CS_PO.replace("\"Language: cs\\n\"\n", "")).unwrap();
+        let out = dir.join("out");
+        let builder = Builder::new("app").source(dir.join("po"), Layout::Po).out_dir(&out).check(false);
+        let files = builder.clone().output(Output::Rust).compile().unwrap();
+        assert!(fs::read_to_string(&files[0]).unwrap().contains("    (\"cs-CZ\", \"nplurals=3;"));
+
+        assert_eq!(vec![out.join("locale/cs_CZ/LC_MESSAGES/app.mo")], builder.compile().unwrap());
+        let locales = Locales::scan(out.join("locale"), Layout::Gettext("app".to_owned())).unwrap();
+        let chain = locales.load(&Locale::new("cs-CZ").unwrap()).unwrap();
+        assert_eq!("%d souborů", chain.ngettext("%d file", "%d files", 5));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compile_invalid() {
+        let dir = temp_dir("invalid");
+        fs::write(dir.join("po/cs.po"), CS_PO.replace("%d soubory", "%s soubory")).unwrap();
+        fs::write(dir.join("po/de.po"), "msgid \"Open\"\nmsgstr \"Öffnen\n").unwrap();
+        let out = dir.join("out");
+        let builder = Builder::new("app").source(dir.join("po"), Layout::Po).out_dir(&out);
+        let failures: Vec<String> = builder.compile().unwrap_err().iter()
+            .map(|f| f.to_string().replace(&dir.display().to_string(), "")).collect();
+        assert_eq!(2, failures.len());
+        assert!(failures[0].starts_with("/po/cs.po:21: "), "{}", failures[0]);
+        assert!(failures[1].starts_with("/po/de.po: Parse error at line 2"), "{}", failures[1]);
+        assert!(!out.exists());
+
+        fs::remove_file(dir.join("po/de.po")).unwrap();
+        assert!(builder.clone().check(false).compile().is_ok());
+
+        fs::write(dir.join("po/cs.po"), CS_PO.replace("plural=(n==1)", "plural=(n==1")).unwrap();
+        let failures = builder.check(false).output(Output::Rust).compile().unwrap_err();
+        assert_eq!(1, failures.len());
+        assert!(!out.join("app.rs").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compile_broken_fuzzy() {
+        let dir = temp_dir("fuzzy");
+        let po = format!("{}\n#, fuzzy, c-format\nmsgid \"%d folder\"\nmsgstr \"\\n%s složka\"\n",
+                         CS_PO);
+        fs::write(dir.join("po/cs.po"), po).unwrap();
+        let out = dir.join("out");
+        let files = Builder::new("app").source(dir.join("po"), Layout::Po).out_dir(&out)
+            .output(Output::Rust).compile().unwrap();
+        assert!(!fs::read_to_string(&files[0]).unwrap().contains("složka"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compile_gettext_layout() {
+        let dir = temp_dir("gettext");
+        let src = dir.join("po/cs/LC_MESSAGES");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("app.po"), CS_PO).unwrap();
+        fs::write(src.join("app.mo"), "").unwrap();
+        let builder = Builder::new("app").source(dir.join("po"), Layout::Gettext("app".to_owned()))
+            .out_dir(dir.join("out"));
+        assert_eq!(vec![("cs".to_owned(), LanguageRange::new("cs").unwrap(), src.join("app.po"))],
+                   builder.inputs().unwrap());
+        assert_eq!(1, builder.compile().unwrap().len());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,351 @@
+//! In-memory catalogue
+//!
+//! The readers and writers stream the units, which is enough for conversion, but most other
+//! tasks need to look the units up. [`Catalogue`] holds the metadata and the units in their
+//! original order, indexed by context and source.
+//!
+//! [`Catalogue`]: struct.Catalogue.html
+
+use locale_config::LanguageRange;
+use po::PoHeader;
+use std::collections::HashMap;
+use std::slice;
+use super::{CatalogueReader, CatalogueWriter, Error, Origin, Unit};
+
+// Obsolete units are indexed separately, as a removed message that comes back has both an active
+// and an obsolete unit.
+type Key = (bool, Option<String>, String);
+
+fn key_of(unit: &Unit) -> Key {
+    (unit.is_obsolete(), unit.context().clone(), unit.source().key().to_owned())
+}
+
+fn key(obsolete: bool, context: Option<&str>, source: &str) -> Key {
+    (obsolete, context.map(str::to_owned), source.to_owned())
+}
+
+/// Catalogue of units with metadata.
+///
+/// Units are kept in order and identified by context and source, like in gettext: for plural
+/// messages, only the One variant counts (see [`Message::key`]). Each key can occur only once
+/// among the active units and once among the obsolete ones, like in gettext. Lookup by context
+/// and source finds the active unit if there is one.
+///
+/// The metadata are kept as gettext header fields, since all the formats can carry those. The
+/// rest of the header entry read with the catalogue, like its state and notes, is written back
+/// unchanged.
+///
+/// [`Message::key`]: ../enum.Message.html#method.key
+#[derive(Clone,Debug)]
+pub struct Catalogue {
+    _target_language: LanguageRange<'static>,
+    _source_language: Option<LanguageRange<'static>>,
+    _header: PoHeader,
+    _header_unit: Option<Unit>,
+    _units: Vec<Unit>,
+    _index: HashMap<Key, usize>,
+}
+
+impl Catalogue {
+    /// Create empty catalogue for given target language.
+    pub fn new(target_language: LanguageRange<'static>) -> Self {
+        Catalogue {
+            _target_language: target_language,
+            _source_language: None,
+            _header: PoHeader::default(),
+            _header_unit: None,
+            _units: Vec::new(),
+            _index: HashMap::new(),
+        }
+    }
+
+    // Catalogue with the metadata and header entry of this one, but no units.
+    pub(crate) fn without_units(&self) -> Self {
+        Catalogue {
+            _target_language: self._target_language.clone(),
+            _source_language: self._source_language.clone(),
+            _header: self._header.clone(),
+            _header_unit: self._header_unit.clone(),
+            _units: Vec::new(),
+            _index: HashMap::new(),
+        }
+    }
+
+    /// Load all units and metadata from a reader.
+    ///
+    /// Fails on the first error from the reader, or if two units have the same context and
+    /// source.
+    pub fn from_reader<R: CatalogueReader>(mut reader: R) -> Result<Self, Error> {
+        let mut cat = Catalogue::new(reader.target_language().clone());
+        cat._source_language = reader.source_language().cloned();
+        match reader.header_unit() {
+            Some(unit) => {
+                cat._header = PoHeader::from_unit(unit);
+                cat._header_unit = Some(unit.clone());
+            }
+            None => for (k, v) in reader.properties() {
+                cat._header.set(k, v);
+            },
+        }
+        for unit in &mut reader {
+            let unit = unit?;
+            if cat._index.contains_key(&key_of(&unit)) {
+                return Err(Error::Invalid("duplicate context and source"));
+            }
+            cat.insert(unit);
+        }
+        Ok(cat)
+    }
+
+    /// Write the catalogue, including the header, and finish the writer.
+    pub fn write_to<W: CatalogueWriter>(&self, writer: &mut W) -> Result<(), Error> {
+        let header = match self._header_unit {
+            Some(ref unit) => {
+                let mut unit = unit.clone();
+                let current = self._header.to_unit();
+                unit._target = current._target;
+                unit._notes.retain(|n| n.0 != Origin::Translator);
+                unit._notes.splice(0..0, current._notes);
+                Some(unit)
+            }
+            None if self._header.fields().next().is_some() || !self._header.comments().is_empty() =>
+                Some(self._header.to_unit()),
+            None => None,
+        };
+        writer.write_header(&self._target_language, header.as_ref())?;
+        for unit in &self._units {
+            writer.write_unit(unit)?;
+        }
+        writer.finish()
+    }
+
+    pub fn target_language(&self) -> &LanguageRange<'static> { &self._target_language }
+
+    pub fn set_target_language(&mut self, language: LanguageRange<'static>) {
+        self._target_language = language;
+    }
+
+    pub fn source_language(&self) -> Option<&LanguageRange<'static>> {
+        self._source_language.as_ref()
+    }
+
+    pub fn set_source_language(&mut self, language: Option<LanguageRange<'static>>) {
+        self._source_language = language;
+    }
+
+    /// Get the header fields.
+    pub fn header(&self) -> &PoHeader { &self._header }
+
+    /// Get the header fields for modification.
+    pub fn header_mut(&mut self) -> &mut PoHeader { &mut self._header }
+
+    /// Number of units, including obsolete ones.
+    pub fn len(&self) -> usize { self._units.len() }
+
+    pub fn is_empty(&self) -> bool { self._units.is_empty() }
+
+    /// Check whether there is a unit with given context and source.
+    pub fn contains(&self, context: Option<&str>, source: &str) -> bool {
+        self.position(context, source).is_some()
+    }
+
+    /// Get position of unit with given context and source, preferring the active one.
+    pub fn position(&self, context: Option<&str>, source: &str) -> Option<usize> {
+        self._index.get(&key(false, context, source))
+            .or_else(|| self._index.get(&key(true, context, source)))
+            .cloned()
+    }
+
+    /// Get unit by context and source.
+    pub fn get(&self, context: Option<&str>, source: &str) -> Option<&Unit> {
+        self.position(context, source).map(|i| &self._units[i])
+    }
+
+    /// Insert a unit.
+    ///
+    /// If there already is a unit with the same context and source, both active or both obsolete,
+    /// it is replaced in place and returned. Otherwise the unit is appended at the end.
+    pub fn insert(&mut self, unit: Unit) -> Option<Unit> {
+        let k = key_of(&unit);
+        match self._index.get(&k) {
+            Some(&i) => Some(::std::mem::replace(&mut self._units[i], unit)),
+            None => {
+                self._index.insert(k, self._units.len());
+                self._units.push(unit);
+                None
+            }
+        }
+    }
+
+    /// Replace unit with given context and source, preferring the active one, keeping its
+    /// position.
+    ///
+    /// The new unit may have different context and source, but it must not collide with another
+    /// unit. Returns the replaced unit.
+    pub fn replace(&mut self, context: Option<&str>, source: &str, unit: Unit)
+            -> Result<Unit, Error> {
+        let i = self.position(context, source)
+            .ok_or(Error::Invalid("context and source not in catalogue"))?;
+        let k = key_of(&unit);
+        if self._index.get(&k).is_some_and(|&j| j != i) {
+            return Err(Error::Invalid("duplicate context and source"));
+        }
+        let old = ::std::mem::replace(&mut self._units[i], unit);
+        self._index.remove(&key_of(&old));
+        self._index.insert(k, i);
+        Ok(old)
+    }
+
+    /// Remove unit with given context and source, preferring the active one. The following units
+    /// move up.
+    pub fn remove(&mut self, context: Option<&str>, source: &str) -> Option<Unit> {
+        let i = self.position(context, source)?;
+        self._index.remove(&key_of(&self._units[i]));
+        for pos in self._index.values_mut() {
+            if *pos > i {
+                *pos -= 1;
+            }
+        }
+        Some(self._units.remove(i))
+    }
+
+    /// Iterate over all units in order, including obsolete ones.
+    pub fn iter(&self) -> slice::Iter<'_, Unit> {
+        self._units.iter()
+    }
+
+    /// Iterate over units that are not obsolete.
+    pub fn active(&self) -> impl Iterator<Item = &Unit> {
+        self._units.iter().filter(|u| !u.is_obsolete())
+    }
+
+    /// Iterate over obsolete units.
+    pub fn obsolete(&self) -> impl Iterator<Item = &Unit> {
+        self._units.iter().filter(|u| u.is_obsolete())
+    }
+}
+
+impl<'a> IntoIterator for &'a Catalogue {
+    type Item = &'a Unit;
+    type IntoIter = slice::Iter<'a, Unit>;
+    fn into_iter(self) -> Self::IntoIter {
+        self._units.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{Error, Message, State, UnitBuilder};
+    use ::po::{PoReader, PoWriter};
+    use super::Catalogue;
+
+    static SAMPLE_PO: &str = r###"# Czech translation
+msgid ""
+msgstr ""
+"Project-Id-Version: translate-storage test\n"
+"Language: cs\n"
+"MIME-Version: 1.0\n"
+"Content-Type: text/plain; charset=UTF-8\n"
+"Content-Transfer-Encoding: 8bit\n"
+"Plural-Forms: nplurals=3; plural=(n==1) ? 0 : (n>=2 && n<=4) ? 1 : 2;\n"
+
+msgid "Open"
+msgstr "Otevřít"
+
+msgctxt "menu"
+msgid "Open"
+msgstr "Otevřít…"
+
+msgid "%d file"
+msgid_plural "%d files"
+msgstr[0] "%d soubor"
+msgstr[1] "%d soubory"
+msgstr[2] "%d souborů"
+
+#~ msgid "Close"
+#~ msgstr "Zavřít"
+"###;
+
+    #[test]
+    fn load_and_lookup() {
+        let cat = Catalogue::from_reader(PoReader::new(SAMPLE_PO.as_bytes())).unwrap();
+        assert_eq!("cs", cat.target_language().as_ref());
+        assert_eq!(Some("translate-storage test"), cat.header().project_id_version());
+        assert_eq!(4, cat.len());
+        assert_eq!(3, cat.active().count());
+        assert_eq!(vec!["Close"], cat.obsolete().map(|u| u.source().key()).collect::<Vec<_>>());
+        assert_eq!(Some("Otevřít"), cat.get(None, "Open").unwrap().target().singular());
+        assert_eq!(Some("Otevřít…"), cat.get(Some("menu"), "Open").unwrap().target().singular());
+        assert!(cat.get(None, "%d file").unwrap().source().is_plural());
+        assert!(cat.get(None, "%d files").is_none());
+        assert_eq!(Some(2), cat.position(None, "%d file"));
+
+        let dup = format!("{}\nmsgid \"Open\"\nmsgstr \"Znovu\"\n", SAMPLE_PO);
+        match Catalogue::from_reader(PoReader::new(dup.as_bytes())) {
+            Err(Error::Invalid(_)) => (),
+            x => panic!("Unexpected {:?}", x),
+        }
+    }
+
+    #[test]
+    fn active_and_obsolete() {
+        let po = format!("{}\n#~ msgid \"Open\"\n#~ msgstr \"Otevři\"\n", SAMPLE_PO);
+        let mut cat = Catalogue::from_reader(PoReader::new(po.as_bytes())).unwrap();
+        assert_eq!(5, cat.len());
+        assert_eq!(Some(0), cat.position(None, "Open"));
+        assert!(!cat.get(None, "Open").unwrap().is_obsolete());
+
+        assert_eq!(Some("Otevřít"), cat.remove(None, "Open").unwrap().target().singular());
+        assert_eq!(Some(3), cat.position(None, "Open"));
+        assert!(cat.get(None, "Open").unwrap().is_obsolete());
+
+        let dup = format!("{}#~ msgid \"Open\"\n#~ msgstr \"Znovu\"\n", po);
+        match Catalogue::from_reader(PoReader::new(dup.as_bytes())) {
+            Err(Error::Invalid(_)) => (),
+            x => panic!("Unexpected {:?}", x),
+        }
+    }
+
+    #[test]
+    fn modify() {
+        let mut cat = Catalogue::from_reader(PoReader::new(SAMPLE_PO.as_bytes())).unwrap();
+        let save = UnitBuilder::new("Save").target("Uložit").build().unwrap();
+        assert!(cat.insert(save).is_none());
+        let open = UnitBuilder::new("Open").target("Otevři").state(State::NeedsWork).build().unwrap();
+        assert_eq!(Some("Otevřít"), cat.insert(open).unwrap().target().singular());
+        assert_eq!(Some(0), cat.position(None, "Open"));
+
+        let quit = UnitBuilder::new("Quit").target("Konec").build().unwrap();
+        match cat.replace(None, "Save", quit.clone()) {
+            Ok(old) => assert_eq!(Some("Uložit"), old.target().singular()),
+            x => panic!("Unexpected {:?}", x),
+        }
+        assert!(cat.get(None, "Save").is_none());
+        assert_eq!(Some(4), cat.position(None, "Quit"));
+        assert!(cat.replace(None, "Save", quit.clone()).is_err());
+        assert!(cat.replace(Some("menu"), "Open", quit).is_err());
+
+        assert!(cat.remove(Some("menu"), "Open").is_some());
+        assert!(cat.remove(Some("menu"), "Open").is_none());
+        assert_eq!(Some(3), cat.position(None, "Quit"));
+        assert_eq!(vec!["Open", "%d file", "Close", "Quit"],
+                   cat.iter().map(|u| u.source().key()).collect::<Vec<_>>());
+        assert_eq!(Message::from("Konec"), *cat.get(None, "Quit").unwrap().target());
+    }
+
+    #[test]
+    fn write_catalogue() {
+        let cat = Catalogue::from_reader(PoReader::new(SAMPLE_PO.as_bytes())).unwrap();
+        let mut writer = PoWriter::new(Vec::new());
+        cat.write_to(&mut writer).unwrap();
+        assert_eq!(SAMPLE_PO, String::from_utf8(writer.into_inner()).unwrap());
+
+        let fuzzy = SAMPLE_PO.replace("# Czech translation\n", "# Czech translation\n#, fuzzy\n");
+        let mut cat = Catalogue::from_reader(PoReader::new(fuzzy.as_bytes())).unwrap();
+        cat.header_mut().set_comments(vec!["Czech translation".to_owned(), "Updated".to_owned()]);
+        let mut writer = PoWriter::new(Vec::new());
+        cat.write_to(&mut writer).unwrap();
+        assert_eq!(fuzzy.replace("# Czech translation\n", "# Czech translation\n# Updated\n"),
+                   String::from_utf8(writer.into_inner()).unwrap());
+    }
+}
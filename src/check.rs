@@ -0,0 +1,393 @@
+//! Consistency checks of translations
+//!
+//! Checks that catch translations that would break the program, like [`msgfmt --check`][msgfmt]
+//! does:
+//!
+//!  - The header has the required fields, filled in rather than left from the template.
+//!  - Source and target both begin, and both end, with a newline, or neither does.
+//!  - Plural translations have variants for the plural forms of the catalogue, as given by the
+//!    `Plural-Forms` header field and the language.
+//!  - Format strings in the target are compatible with the source, for the languages the unit is
+//!    marked as format string in (see the [`format`] module).
+//!  - Each context and source occur only once.
+//!
+//! Like msgfmt, only the units that would be compiled are checked for plural forms, newlines and
+//! format strings: those that are not obsolete, have non-blank target and are not fuzzy (their
+//! state is `Final`), unless [`Checker::use_fuzzy`] is set. Duplicates are found among all active
+//! units. Each problem is reported as a [`Diagnostic`] with line of the unit, if the reader knows
+//! it.
+//!
+//! [`format`]: ../format/index.html
+//! [`Diagnostic`]: struct.Diagnostic.html
+//! [`Checker::use_fuzzy`]: struct.Checker.html#method.use_fuzzy
+//! [msgfmt]: https://www.gnu.org/software/gettext/manual/html_node/msgfmt-Invocation.html
+
+use catalogue::Catalogue;
+use format::{self, Mismatch};
+use locale_config::LanguageRange;
+use po::PoHeader;
+use std::collections::HashMap;
+use std::fmt;
+use super::{CatalogueReader, Count, Error, Message, State, Unit};
+
+/// Header fields required by `msgfmt --check-header`, with the values a template has for them.
+const REQUIRED_FIELDS: [(&str, &str); 8] = [
+    ("Project-Id-Version", "PACKAGE VERSION"),
+    ("PO-Revision-Date", "YEAR-MO-DA"),
+    ("Last-Translator", "FULL NAME"),
+    ("Language-Team", "LANGUAGE"),
+    ("Language", ""),
+    ("MIME-Version", ""),
+    ("Content-Type", "text/plain; charset=CHARSET"),
+    ("Content-Transfer-Encoding", "ENCODING"),
+];
+
+/// Kind of problem found.
+///
+/// Where a problem concerns one variant of a plural message, it is given as `Some(Count)`.
+#[derive(Clone,Debug,Eq,PartialEq)]
+pub enum Problem {
+    /// Required header field is missing.
+    MissingField(&'static str),
+    /// Header field still has the value from the template.
+    DefaultField(&'static str),
+    /// Header field cannot be parsed.
+    InvalidField(&'static str),
+    /// Only one of the source and target begins with a newline.
+    LeadingNewline(Option<Count>),
+    /// Only one of the source and target ends with a newline.
+    TrailingNewline(Option<Count>),
+    /// Number of plural variants of the target does not match the plural rule. Parameters are
+    /// the expected and found number.
+    PluralForms(usize, usize),
+    /// One of source and target is plural and the other is not.
+    PluralMismatch,
+    /// Target format string is not compatible with the source. Parameters are the format
+    /// language, the plural variant and the mismatch found.
+    Format(String, Option<Count>, Mismatch),
+    /// Context and source were already defined. Parameter is the line of the first definition.
+    Duplicate(usize),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let variant = |c: &Option<Count>| c.map_or(String::new(), |c| format!(" ({:?} variant)", c));
+        match *self {
+            Problem::MissingField(field) => write!(f, "header field ‘{}’ missing", field),
+            Problem::DefaultField(field) =>
+                write!(f, "header field ‘{}’ still has the initial default value", field),
+            Problem::InvalidField(field) => write!(f, "header field ‘{}’ is invalid", field),
+            Problem::LeadingNewline(ref c) =>
+                write!(f, "source and target do not both begin with ‘\\n’{}", variant(c)),
+            Problem::TrailingNewline(ref c) =>
+                write!(f, "source and target do not both end with ‘\\n’{}", variant(c)),
+            Problem::PluralForms(exp, got) =>
+                write!(f, "wrong plural variants, expected {}, got {}", exp, got),
+            Problem::PluralMismatch => write!(f, "source and target do not both have plurals"),
+            Problem::Format(ref lang, ref c, ref m) => write!(f, "{}-format{}: {}", lang, variant(c), m),
+            Problem::Duplicate(0) => write!(f, "duplicate message definition"),
+            Problem::Duplicate(line) =>
+                write!(f, "duplicate message definition, first defined at line {}", line),
+        }
+    }
+}
+
+/// Problem found in a catalogue.
+///
+/// Problems of the header have empty source. The line is 0 where not known.
+#[derive(Clone,Debug,Eq,PartialEq)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub context: Option<String>,
+    pub source: String,
+    pub problem: Problem,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line > 0 {
+            write!(f, "line {}: ", self.line)?;
+        }
+        self.problem.fmt(f)
+    }
+}
+
+/// Checks units of one catalogue.
+///
+/// Create it from the catalogue header, pass it all the units with `check_unit` and finally
+/// call `finish` to get the problems of the header.
+#[derive(Clone,Debug)]
+pub struct Checker {
+    _header: PoHeader,
+    _plurals: Vec<Count>,
+    _single: Vec<Count>,
+    _plural_rule_valid: bool,
+    _has_plurals: bool,
+    _use_fuzzy: bool,
+    _seen: HashMap<(Option<String>, String), usize>,
+}
+
+impl Checker {
+    /// Create checker for catalogue with given header and target language.
+    pub fn new(header: &PoHeader, language: &LanguageRange) -> Self {
+        let rule = header.plural_forms();
+        let valid = rule.is_ok();
+        let rule = rule.ok().and_then(|r| r).unwrap_or_default();
+        Checker {
+            _header: header.clone(),
+            _plurals: rule.categories(language),
+            _single: rule.single_categories(language),
+            _plural_rule_valid: valid,
+            _has_plurals: false,
+            _use_fuzzy: false,
+            _seen: HashMap::new(),
+        }
+    }
+
+    /// Check also the targets of fuzzy units, like `msgfmt --use-fuzzy`, which compiles them.
+    pub fn use_fuzzy(mut self, use_fuzzy: bool) -> Self {
+        self._use_fuzzy = use_fuzzy;
+        self
+    }
+
+    /// Check a unit. Its target is only checked if the unit would be compiled.
+    pub fn check_unit(&mut self, unit: &Unit) -> Vec<Diagnostic> {
+        if unit.is_obsolete() {
+            return Vec::new();
+        }
+        let mut problems = Vec::new();
+        self._has_plurals |= unit.source().is_plural();
+        let key = (unit.context().clone(), unit.source().key().to_owned());
+        match self._seen.get(&key) {
+            Some(&line) => problems.push(Problem::Duplicate(line)),
+            None => { self._seen.insert(key, unit.line()); }
+        }
+        if self.is_used(unit) {
+            self.check_target(unit, &mut problems);
+        }
+        problems.into_iter().map(|problem| Diagnostic {
+            line: unit.line(),
+            context: unit.context().clone(),
+            source: unit.source().key().to_owned(),
+            problem,
+        }).collect()
+    }
+
+    // Whether the unit would be compiled, so its target must be checked.
+    fn is_used(&self, unit: &Unit) -> bool {
+        let state = match unit.state() {
+            State::Final => true,
+            State::NeedsWork => self._use_fuzzy,
+            State::Empty => false,
+        };
+        state && !unit.target().is_blank()
+    }
+
+    fn check_target(&self, unit: &Unit, problems: &mut Vec<Problem>) {
+        match (unit.source(), unit.target()) {
+            (Message::Plural(_), Message::Plural(target))
+                if target.len() != self._plurals.len()
+                    || self._plurals.iter().any(|c| !target.contains_key(c)) =>
+                problems.push(Problem::PluralForms(self._plurals.len(), target.len())),
+            (Message::Plural(_), Message::Singular(_))
+                | (Message::Singular(_), Message::Plural(_)) =>
+                problems.push(Problem::PluralMismatch),
+            _ => (),
+        }
+        for (count, source, target) in format::variants(unit) {
+            check_newlines(source, target, count, problems);
+        }
+        problems.extend(format::check_unit(unit, &self._single).into_iter()
+            .map(|(lang, count, m)| Problem::Format(lang, count, m)));
+    }
+
+    /// Check the header. Call after all units are checked, as `Plural-Forms` is only required
+    /// when there are plural units.
+    pub fn finish(self) -> Vec<Diagnostic> {
+        let mut problems = Vec::new();
+        for &(field, default) in &REQUIRED_FIELDS {
+            match self._header.get(field) {
+                None => problems.push(Problem::MissingField(field)),
+                Some(v) if !default.is_empty() && v.starts_with(default) =>
+                    problems.push(Problem::DefaultField(field)),
+                _ => (),
+            }
+        }
+        if !self._plural_rule_valid {
+            problems.push(Problem::InvalidField("Plural-Forms"));
+        } else if self._has_plurals && self._header.get("Plural-Forms").is_none() {
+            problems.push(Problem::MissingField("Plural-Forms"));
+        }
+        problems.into_iter().map(|problem| Diagnostic {
+            line: 0,
+            context: None,
+            source: String::new(),
+            problem,
+        }).collect()
+    }
+}
+
+fn check_newlines(source: &str, target: &str, count: Option<Count>, problems: &mut Vec<Problem>) {
+    if source.starts_with('\n') != target.starts_with('\n') {
+        problems.push(Problem::LeadingNewline(count));
+    }
+    if source.ends_with('\n') != target.ends_with('\n') {
+        problems.push(Problem::TrailingNewline(count));
+    }
+}
+
+/// Check all units and the header of a catalogue.
+pub fn check_catalogue(catalogue: &Catalogue) -> Vec<Diagnostic> {
+    let mut checker = Checker::new(catalogue.header(), catalogue.target_language());
+    let mut res: Vec<Diagnostic> = catalogue.iter().flat_map(|u| checker.check_unit(u)).collect();
+    res.extend(checker.finish());
+    res
+}
+
+/// Check all units and the header of a catalogue from a reader.
+///
+/// Unlike loading into [`Catalogue`], this finds duplicate units. Wrong number of plural forms
+/// and invalid `Plural-Forms` header, which the readers report as errors, are reported as
+/// diagnostics; other errors are returned.
+///
+/// [`Catalogue`]: ../catalogue/struct.Catalogue.html
+pub fn check_reader<R: CatalogueReader>(mut reader: R) -> Result<Vec<Diagnostic>, Error> {
+    let mut header = PoHeader::default();
+    for (k, v) in reader.properties() {
+        header.set(k, v);
+    }
+    let mut checker = Checker::new(&header, reader.target_language());
+    // the readers return the error of an invalid plural rule first, then continue with the units
+    let mut invalid_rule = header.plural_forms().is_err();
+    let mut res = Vec::new();
+    for unit in &mut reader {
+        match unit {
+            Err(Error::Parse(..)) if invalid_rule => (),
+            Ok(unit) => res.extend(checker.check_unit(&unit)),
+            Err(Error::PluralForms(line, exp, got)) => res.push(Diagnostic {
+                line,
+                context: None,
+                source: String::new(),
+                problem: Problem::PluralForms(exp, got),
+            }),
+            Err(e) => return Err(e),
+        }
+        invalid_rule = false;
+    }
+    res.extend(checker.finish());
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use ::catalogue::Catalogue;
+    use ::format::{Arg, Mismatch};
+    use ::po::PoReader;
+    use ::Count;
+    use super::{check_catalogue, check_reader, Checker, Problem};
+
+    static BROKEN_PO: &str = r###"msgid ""
+msgstr ""
+"Project-Id-Version: PACKAGE VERSION\n"
+"PO-Revision-Date: 2024-01-01 12:00+0100\n"
+"Last-Translator: Jan Novák <jan@example.org>\n"
+"Language-Team: Czech\n"
+"Language: cs\n"
+"Content-Type: text/plain; charset=UTF-8\n"
+"Content-Transfer-Encoding: 8bit\n"
+"Plural-Forms: nplurals=3; plural=(n==1) ? 0 : (n>=2 && n<=4) ? 1 : 2;\n"
+
+msgid "Fine"
+msgstr "Dobře"
+
+msgid "\nUsage: %s\n"
+msgstr "Použití: %s\n"
+
+#, c-format
+msgid "%s has %d files"
+msgstr "%s má %s souborů"
+
+#, c-format
+msgid "%d file"
+msgid_plural "%d files"
+msgstr[0] "jeden soubor"
+msgstr[1] "%d soubory"
+msgstr[2] "souborů"
+
+msgid "Two"
+msgid_plural "Twos"
+msgstr[0] "Dva"
+msgstr[1] "Dvě"
+
+#, c-format
+msgid "Fuzzy %d"
+msgstr ""
+
+msgid "Fine"
+msgstr "Znovu"
+
+#, fuzzy, c-format
+msgid "Fuzzy %s"
+msgstr "\nNejasné %d"
+"###;
+
+    #[test]
+    fn check_po() {
+        let found: Vec<(usize, Problem)> = check_reader(PoReader::new(BROKEN_PO.as_bytes())).unwrap()
+            .into_iter()
+            .map(|d| (d.line, d.problem))
+            .collect();
+        assert_eq!(vec![
+            (15, Problem::LeadingNewline(None)),
+            (19, Problem::Format("c".to_owned(), None,
+                                 Mismatch::Type(Arg::Index(2), "d".to_owned(), "s".to_owned()))),
+            (23, Problem::Format("c".to_owned(), Some(Count::Other),
+                                 Mismatch::Missing(Arg::Index(1)))),
+            (31, Problem::PluralForms(3, 2)),
+            (38, Problem::Duplicate(12)),
+            (0, Problem::DefaultField("Project-Id-Version")),
+            (0, Problem::MissingField("MIME-Version")),
+        ], found);
+    }
+
+    #[test]
+    fn check_invalid_plural_forms() {
+        let po = BROKEN_PO.replace("plural=(n==1) ?", "plural=(n==1 ?");
+        let found: Vec<(usize, Problem)> = check_reader(PoReader::new(po.as_bytes())).unwrap()
+            .into_iter()
+            .map(|d| (d.line, d.problem))
+            .collect();
+        assert_eq!((15, Problem::LeadingNewline(None)), found[0]);
+        assert_eq!(Some(&(0, Problem::InvalidField("Plural-Forms"))), found.last());
+    }
+
+    #[test]
+    fn check_loaded() {
+        let po = BROKEN_PO.replace("msgid \"Fine\"\nmsgstr \"Znovu\"\n", "")
+            .replace("msgstr[1] \"Dvě\"\n", "msgstr[1] \"Dvě\"\nmsgstr[2] \"Dvě\"\n");
+        let cat = Catalogue::from_reader(PoReader::new(po.as_bytes())).unwrap();
+        let found = check_catalogue(&cat);
+        assert_eq!(5, found.len());
+        assert_eq!("line 15: source and target do not both begin with ‘\\n’", found[0].to_string());
+        assert_eq!("line 23: c-format (Other variant): argument 1 missing in target",
+                   found[2].to_string());
+        assert_eq!("header field ‘MIME-Version’ missing", found[4].to_string());
+    }
+
+    #[test]
+    fn check_fuzzy() {
+        let po = BROKEN_PO.replace("msgid \"Fine\"\nmsgstr \"Znovu\"\n", "")
+            .replace("msgstr[1] \"Dvě\"\n", "msgstr[1] \"Dvě\"\nmsgstr[2] \"Dvě\"\n");
+        let cat = Catalogue::from_reader(PoReader::new(po.as_bytes())).unwrap();
+        let mut checker = Checker::new(cat.header(), cat.target_language()).use_fuzzy(true);
+        let found: Vec<Problem> = cat.iter().flat_map(|u| checker.check_unit(u))
+            .filter(|d| d.source == "Fuzzy %s")
+            .map(|d| d.problem)
+            .collect();
+        assert_eq!(vec![
+            Problem::LeadingNewline(None),
+            Problem::Format("c".to_owned(), None,
+                            Mismatch::Type(Arg::Index(1), "s".to_owned(), "d".to_owned())),
+        ], found);
+    }
+}
@@ -0,0 +1,640 @@
+//! Format string directives
+//!
+//! Messages marked as format strings (see [`Flag::Format`]) contain directives that are replaced
+//! by arguments at runtime. The translation must use the arguments with the same types as the
+//! source, or the program will misbehave or crash, so the directives are parsed and compared.
+//!
+//! Supported are C `printf` (`c-format`), Python `%` (`python-format`) and `str.format`
+//! (`python-brace-format`) and Rust `format!` (`rust-format`) strings.
+//!
+//! [`Flag::Format`]: ../enum.Flag.html#variant.Format
+
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+use super::{Count, Flag, Message, Unit};
+
+/// Format argument a directive refers to.
+#[derive(Clone,Debug,Eq,PartialEq,Ord,PartialOrd,Hash)]
+pub enum Arg {
+    /// Positional argument, counting from 1.
+    Index(usize),
+    /// Named argument.
+    Name(String),
+}
+
+impl fmt::Display for Arg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Arg::Index(i) => write!(f, "argument {}", i),
+            Arg::Name(ref name) => write!(f, "argument ‘{}’", name),
+        }
+    }
+}
+
+/// Problem with directives of a translation.
+#[derive(Clone,Debug,Eq,PartialEq)]
+pub enum Mismatch {
+    /// The source is not a valid format string. Parameter describes why.
+    InvalidSource(String),
+    /// The target is not a valid format string. Parameter describes why.
+    InvalidTarget(String),
+    /// The source uses an argument the target does not.
+    Missing(Arg),
+    /// The target uses an argument the source does not.
+    Extra(Arg),
+    /// The target uses an argument with a different type. Parameters are the argument and its
+    /// types in the source and target.
+    Type(Arg, String, String),
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Mismatch::InvalidSource(ref why) => write!(f, "invalid format string in source: {}", why),
+            Mismatch::InvalidTarget(ref why) => write!(f, "invalid format string in target: {}", why),
+            Mismatch::Missing(ref arg) => write!(f, "{} missing in target", arg),
+            Mismatch::Extra(ref arg) => write!(f, "{} not in source", arg),
+            Mismatch::Type(ref arg, ref src, ref tgt) =>
+                write!(f, "{} is ‘{}’ in source, but ‘{}’ in target", arg, src, tgt),
+        }
+    }
+}
+
+// Arguments collected while parsing a format string, each with its type.
+struct Args {
+    _list: Vec<(Arg, String)>,
+    _next: usize,
+    _numbering: Numbering,
+    _implicit: Option<bool>,
+}
+
+// What mixing of implicitly and explicitly numbered arguments a language allows.
+#[derive(Clone,Copy,PartialEq)]
+enum Numbering {
+    // All directives must be numbered or named, or none (C, Python `%`). An argument used several
+    // times must have the same type.
+    Exclusive,
+    // Numbered and automatically numbered must not be mixed, but names can be used with either
+    // (Python braces). An argument can be used with several types.
+    NoMixedIndex,
+    // Anything goes (Rust). An argument can be used with several types.
+    Free,
+}
+
+impl Args {
+    fn new(numbering: Numbering) -> Self {
+        Args { _list: Vec::new(), _next: 1, _numbering: numbering, _implicit: None }
+    }
+
+    // Add the explicit argument, or the next implicit one if `None`.
+    fn add(&mut self, arg: Option<Arg>, t: &str) -> Result<(), String> {
+        let counted = self._numbering == Numbering::Exclusive
+            || (self._numbering == Numbering::NoMixedIndex && !is!(arg => Some(Arg::Name(_))));
+        if counted {
+            if self._implicit.is_some_and(|i| i != arg.is_none()) {
+                return Err("mixed numbered and unnumbered directives".to_owned());
+            }
+            self._implicit = Some(arg.is_none());
+        }
+        let arg = arg.unwrap_or_else(|| {
+            self._next += 1;
+            Arg::Index(self._next - 1)
+        });
+        match self._list.iter_mut().find(|a| a.0 == arg) {
+            Some(a) if self._numbering == Numbering::Exclusive && a.1 != t =>
+                Err(format!("{} used as both ‘{}’ and ‘{}’", arg, a.1, t)),
+            Some(a) => {
+                if !a.1.split(',').any(|x| x == t) {
+                    let mut types: Vec<&str> = a.1.split(',').chain(Some(t)).collect();
+                    types.sort();
+                    a.1 = types.join(",");
+                }
+                Ok(())
+            }
+            None => {
+                self._list.push((arg, t.to_owned()));
+                Ok(())
+            }
+        }
+    }
+
+    fn finish(mut self) -> Vec<(Arg, String)> {
+        self._list.sort();
+        self._list
+    }
+}
+
+fn number(chars: &mut Peekable<Chars>) -> Option<usize> {
+    let mut n = None;
+    while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+        n = Some(n.unwrap_or(0) * 10 + d as usize);
+        chars.next();
+    }
+    n
+}
+
+fn identifier(chars: &mut Peekable<Chars>) -> String {
+    let mut id = String::new();
+    while let Some(&c) = chars.peek().filter(|c| c.is_alphanumeric() || **c == '_') {
+        id.push(c);
+        chars.next();
+    }
+    id
+}
+
+// Explicit argument number `n$`, if present.
+fn arg_number(chars: &mut Peekable<Chars>) -> Result<Option<Arg>, String> {
+    let mut ahead = chars.clone();
+    match number(&mut ahead) {
+        Some(0) if ahead.peek() == Some(&'$') => Err("argument number 0".to_owned()),
+        Some(n) if ahead.peek() == Some(&'$') => {
+            ahead.next();
+            *chars = ahead;
+            Ok(Some(Arg::Index(n)))
+        }
+        _ => Ok(None),
+    }
+}
+
+// Width or precision; returns whether it is `*`, and the `m$` number of its argument.
+fn star(chars: &mut Peekable<Chars>) -> Result<Option<Option<Arg>>, String> {
+    if chars.peek() != Some(&'*') {
+        number(chars);
+        return Ok(None);
+    }
+    chars.next();
+    arg_number(chars).map(Some)
+}
+
+fn skip_while<F: Fn(char) -> bool>(chars: &mut Peekable<Chars>, f: F) -> String {
+    let mut res = String::new();
+    while let Some(&c) = chars.peek().filter(|c| f(**c)) {
+        res.push(c);
+        chars.next();
+    }
+    res
+}
+
+/// Parse a C `printf` format string into the types of its arguments.
+///
+/// Arguments are numbered either implicitly, or all explicitly with `%n$`, including `*`
+/// widths and precisions. The types are the length modifier and conversion, with conversions
+/// taking the same argument type unified, e.g. `%i` is `d` and `%X` is `u`.
+pub fn parse_c(s: &str) -> Result<Vec<(Arg, String)>, String> {
+    let mut args = Args::new(Numbering::Exclusive);
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            continue;
+        }
+        if chars.peek() == Some(&'%') {
+            chars.next();
+            continue;
+        }
+        let explicit = arg_number(&mut chars)?;
+        skip_while(&mut chars, |c| "-+ #0'I".contains(c));
+        if let Some(w) = star(&mut chars)? {
+            args.add(w, "d")?;
+        }
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            if let Some(p) = star(&mut chars)? {
+                args.add(p, "d")?;
+            }
+        }
+        let mut length = skip_while(&mut chars, |c| "hlLqjzt".contains(c));
+        let conversion = match chars.next() {
+            Some('d') | Some('i') => "d",
+            Some('o') | Some('u') | Some('x') | Some('X') => "u",
+            Some('e') | Some('E') | Some('f') | Some('F') | Some('g') | Some('G') | Some('a')
+                | Some('A') => "f",
+            Some('c') => "c",
+            Some('s') => "s",
+            Some('p') => "p",
+            Some('n') => "n",
+            Some('C') => { length = "l".to_owned(); "c" }
+            Some('S') => { length = "l".to_owned(); "s" }
+            Some(c) => return Err(format!("invalid conversion ‘{}’", c)),
+            None => return Err("unterminated directive".to_owned()),
+        };
+        args.add(explicit, &(length + conversion))?;
+    }
+    Ok(args.finish())
+}
+
+/// Parse a Python `%` format string into the types of its arguments.
+///
+/// Arguments are either all named with `%(name)s`, or all positional. The types are unified
+/// like in C: all integer conversions are `d`, floating point `f` and `s`, `r` and `a` are `s`.
+pub fn parse_python(s: &str) -> Result<Vec<(Arg, String)>, String> {
+    let mut args = Args::new(Numbering::Exclusive);
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            continue;
+        }
+        let name = if chars.peek() == Some(&'(') {
+            chars.next();
+            let name = skip_while(&mut chars, |c| c != ')');
+            if chars.next().is_none() {
+                return Err("unterminated argument name".to_owned());
+            }
+            Some(Arg::Name(name))
+        } else {
+            None
+        };
+        skip_while(&mut chars, |c| "-+ #0".contains(c));
+        if star(&mut chars)?.is_some() {
+            args.add(None, "d")?;
+        }
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            if star(&mut chars)?.is_some() {
+                args.add(None, "d")?;
+            }
+        }
+        skip_while(&mut chars, |c| "hlL".contains(c));
+        let conversion = match chars.next() {
+            Some('%') if name.is_none() => continue,
+            Some('d') | Some('i') | Some('o') | Some('u') | Some('x') | Some('X') => "d",
+            Some('e') | Some('E') | Some('f') | Some('F') | Some('g') | Some('G') => "f",
+            Some('c') => "c",
+            Some('s') | Some('r') | Some('a') => "s",
+            Some(c) => return Err(format!("invalid conversion ‘{}’", c)),
+            None => return Err("unterminated directive".to_owned()),
+        };
+        args.add(name, conversion)?;
+    }
+    Ok(args.finish())
+}
+
+// Parse a brace format string, calling `field` with the content of each replacement field.
+fn parse_braces<F>(s: &str, mut field: F) -> Result<(), String>
+        where F: FnMut(&str) -> Result<(), String> {
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => { chars.next(); }
+            '}' if chars.peek() == Some(&'}') => { chars.next(); }
+            '}' => return Err("single ‘}’".to_owned()),
+            '{' => {
+                // Python allows nested fields in the format spec.
+                let mut content = String::new();
+                let mut depth = 0;
+                loop {
+                    match chars.next() {
+                        None => return Err("unterminated field".to_owned()),
+                        Some('}') if depth == 0 => break,
+                        Some(c) => {
+                            depth += (c == '{') as i32 - (c == '}') as i32;
+                            content.push(c);
+                        }
+                    }
+                }
+                field(&content)?;
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+// Argument of a brace field: number, name, or `None` for the next one.
+fn brace_arg(name: &str) -> Result<Option<Arg>, String> {
+    if name.is_empty() {
+        Ok(None)
+    } else if let Ok(n) = name.parse::<usize>() {
+        // Braces count from 0, but arguments are numbered from 1 like in C.
+        Ok(Some(Arg::Index(n + 1)))
+    } else if name.chars().all(|c| c.is_alphanumeric() || c == '_')
+            && !name.starts_with(|c: char| c.is_ascii_digit()) {
+        Ok(Some(Arg::Name(name.to_owned())))
+    } else {
+        Err(format!("invalid argument ‘{}’", name))
+    }
+}
+
+/// Parse a Python `str.format` string into the types of its arguments.
+///
+/// Fields `{}` are numbered automatically, which must not be mixed with explicit numbers like
+/// `{0}`, but names like `{name}` can be used with either. Attributes and indices of arguments
+/// are ignored. The type is the field without the argument, e.g. `{!r}` or `{:d}`; types of an
+/// argument used in several fields are joined with commas.
+pub fn parse_python_brace(s: &str) -> Result<Vec<(Arg, String)>, String> {
+    let mut args = Args::new(Numbering::NoMixedIndex);
+    parse_braces(s, |field| {
+        let (name, spec) = match field.find([':', '!']) {
+            Some(i) => field.split_at(i),
+            None => (field, ""),
+        };
+        let name = &name[..name.find(['.', '[']).unwrap_or(name.len())];
+        // Nested fields in the spec are arguments too.
+        let (outer, nested) = match spec.find('{') {
+            Some(i) => (&spec[..i], &spec[i..]),
+            None => (spec, ""),
+        };
+        parse_braces(nested, |f| args.add(brace_arg(f)?, "{}"))?;
+        let conversion: String = outer.chars().skip_while(|&c| c != '!').take(2).collect();
+        let ty = if nested.is_empty() {
+            outer.chars().last().filter(|c| "bcdeEfFgGnosxX%".contains(*c))
+        } else {
+            None
+        };
+        let ty = match ty {
+            Some(t) => format!("{{{}:{}}}", conversion, t),
+            None => format!("{{{}}}", conversion),
+        };
+        args.add(brace_arg(name)?, &ty)
+    })?;
+    Ok(args.finish())
+}
+
+// Width or precision in Rust format spec; named or numbered arguments end with `$`.
+fn rust_count(chars: &mut Peekable<Chars>, args: &mut Args) -> Result<(), String> {
+    let mut ahead = chars.clone();
+    let name = identifier(&mut ahead);
+    if !name.is_empty() && ahead.peek() == Some(&'$') {
+        ahead.next();
+        *chars = ahead;
+        args.add(brace_arg(&name)?, "usize")?;
+    } else {
+        number(chars);
+    }
+    Ok(())
+}
+
+/// Parse a Rust `format!` string into the types of its arguments.
+///
+/// Fields `{}` take the next argument and can be mixed with explicit `{0}` and `{name}`.
+/// Arguments for width and precision, like `{:1$}` or `{:.*}`, have type `usize`, the others
+/// the formatting trait written as field without the argument, e.g. `{}`, `{:?}` or `{:x}`.
+/// Types of an argument used in several fields are joined with commas.
+pub fn parse_rust(s: &str) -> Result<Vec<(Arg, String)>, String> {
+    let mut args = Args::new(Numbering::Free);
+    parse_braces(s, |field| {
+        let (name, spec) = match field.find(':') {
+            Some(i) => (&field[..i], &field[(i + 1)..]),
+            None => (field, ""),
+        };
+        let arg = brace_arg(name.trim())?;
+        let mut chars = spec.chars().peekable();
+        // fill and align
+        let mut ahead = chars.clone();
+        ahead.next();
+        if ahead.peek().is_some_and(|c| "<^>".contains(*c)) {
+            ahead.next();
+            chars = ahead;
+        } else if chars.peek().is_some_and(|c| "<^>".contains(*c)) {
+            chars.next();
+        }
+        skip_while(&mut chars, |c| "+-#".contains(c));
+        if chars.peek() == Some(&'0') {
+            let mut ahead = chars.clone();
+            ahead.next();
+            if ahead.peek() != Some(&'$') {
+                chars = ahead;
+            }
+        }
+        rust_count(&mut chars, &mut args)?;
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            if chars.peek() == Some(&'*') {
+                chars.next();
+                args.add(None, "usize")?;
+            } else {
+                rust_count(&mut chars, &mut args)?;
+            }
+        }
+        let ty: String = chars.collect();
+        match ty.as_str() {
+            "" | "?" | "x?" | "X?" | "o" | "x" | "X" | "p" | "b" | "e" | "E" => (),
+            _ => return Err(format!("invalid format trait ‘{}’", ty)),
+        }
+        let ty = if ty.is_empty() { "{}".to_owned() } else { format!("{{:{}}}", ty) };
+        args.add(arg, &ty)
+    })?;
+    Ok(args.finish())
+}
+
+/// Parse format string in given language.
+///
+/// The languages are named like in the gettext `format` flags: `c`, `python`, `python-brace`
+/// and `rust`. Returns `None` for other languages.
+pub fn parse(lang: &str, s: &str) -> Option<Result<Vec<(Arg, String)>, String>> {
+    match lang {
+        "c" => Some(parse_c(s)),
+        "python" => Some(parse_python(s)),
+        "python-brace" => Some(parse_python_brace(s)),
+        "rust" => Some(parse_rust(s)),
+        _ => None,
+    }
+}
+
+/// Compare arguments used by source and target format strings.
+///
+/// If `partial`, the target may omit arguments, as is allowed in plural variants used for a
+/// single number, e.g. “one file” for `%d files`.
+pub fn compare(source: &[(Arg, String)], target: &[(Arg, String)], partial: bool)
+        -> Vec<Mismatch> {
+    let mut res = Vec::new();
+    for (arg, t) in source {
+        match target.iter().find(|a| a.0 == *arg) {
+            Some(a) if a.1 != *t => res.push(Mismatch::Type(arg.clone(), t.clone(), a.1.clone())),
+            Some(_) => (),
+            None if partial => (),
+            None => res.push(Mismatch::Missing(arg.clone())),
+        }
+    }
+    for (arg, _) in target {
+        if !source.iter().any(|a| a.0 == *arg) {
+            res.push(Mismatch::Extra(arg.clone()));
+        }
+    }
+    res
+}
+
+/// Check format directives of a target against the source, in given language (see [`parse`]).
+///
+/// Languages that are not supported are not checked.
+///
+/// [`parse`]: fn.parse.html
+pub fn check(lang: &str, source: &str, target: &str, partial: bool) -> Vec<Mismatch> {
+    let source = match parse(lang, source) {
+        None => return Vec::new(),
+        Some(Ok(s)) => s,
+        Some(Err(why)) => return vec![Mismatch::InvalidSource(why)],
+    };
+    match parse(lang, target) {
+        Some(Ok(t)) => compare(&source, &t, partial),
+        Some(Err(why)) => vec![Mismatch::InvalidTarget(why)],
+        None => Vec::new(),
+    }
+}
+
+/// Check format directives of a unit, for each language it is marked as format string in.
+///
+/// Like in gettext, each variant of a plural target is checked against the plural form of the
+/// source. Variants of the `single` categories, which the plural rule uses for only one number
+/// (see [`PluralRule::single_categories`]), may omit arguments. Units with blank target are not
+/// checked.
+///
+/// Returns the language, variant for plural messages and the mismatch found.
+///
+/// [`PluralRule::single_categories`]: ../plural/struct.PluralRule.html#method.single_categories
+pub fn check_unit(unit: &Unit, single: &[Count]) -> Vec<(String, Option<Count>, Mismatch)> {
+    let mut res = Vec::new();
+    if unit.target().is_blank() {
+        return res;
+    }
+    let langs = unit.flags().iter().filter_map(|f| unpack!(*f => Flag::Format(ref l) => l));
+    for lang in langs {
+        for (count, source, target) in variants(unit) {
+            let partial = count.is_some_and(|c| single.contains(&c));
+            res.extend(check(lang, source, target, partial).into_iter()
+                .map(|m| (lang.clone(), count, m)));
+        }
+    }
+    res
+}
+
+// Pairs of source and target strings to compare. Plural target variants are all compared with
+// the plural source, like `msgid_plural` in gettext.
+pub(crate) fn variants(unit: &Unit) -> Vec<(Option<Count>, &str, &str)> {
+    match (unit.source(), unit.target()) {
+        (Message::Plural(source), Message::Plural(target)) => {
+            let s = source.get(&Count::Other).map_or("", String::as_str);
+            target.iter().map(|(&c, t)| (Some(c), s, t.as_str())).collect()
+        }
+        (source, Message::Singular(target)) => vec![(None, source.key(), target.as_str())],
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{Count, Flag, Message, UnitBuilder};
+    use super::{check, check_unit, parse, parse_c, Arg, Mismatch};
+
+    fn types(lang: &str, s: &str) -> Vec<String> {
+        parse(lang, s).unwrap().unwrap().into_iter().map(|a| a.1).collect()
+    }
+
+    fn args(lang: &str, s: &str) -> Vec<Arg> {
+        parse(lang, s).unwrap().unwrap().into_iter().map(|a| a.0).collect()
+    }
+
+    #[test]
+    fn parse_c_format() {
+        assert!(types("c", "100%% done").is_empty());
+        assert_eq!(vec!["d", "s"], types("c", "%i files in %s"));
+        assert_eq!(vec!["ld", "u", "f", "Lf"], types("c", "%-5ld %08X %.2f %Lg"));
+        assert_eq!(vec!["d", "d", "s"], types("c", "%*.*s"));
+        assert_eq!(vec!["s", "d"], types("c", "%2$d: %1$s"));
+        assert_eq!(vec!["d", "s"], types("c", "%2$*1$s"));
+        assert_eq!(vec!["d"], types("c", "%1$d %1$d"));
+        assert!(parse_c("%1$s %s").is_err());
+        assert!(parse_c("%1$s %1$d").is_err());
+        assert!(parse_c("%0$s").is_err());
+        assert!(parse_c("50%").is_err());
+        assert!(parse_c("%y").is_err());
+    }
+
+    #[test]
+    fn parse_python_format() {
+        assert_eq!(vec!["d", "s"], types("python", "%d%% of %r"));
+        assert_eq!(vec![Arg::Name("count".to_owned()), Arg::Name("name".to_owned())],
+                   args("python", "%(name)s has %(count)5.2f"));
+        assert!(parse("python", "%(name)s %s").unwrap().is_err());
+        assert!(parse("python", "%(name").unwrap().is_err());
+
+        assert_eq!(vec![Arg::Index(1), Arg::Index(2), Arg::Name("name".to_owned())],
+                   args("python-brace", "{} {name.title} {{literal}} {}"));
+        assert_eq!(vec!["{!r},{:d},{}", "{}"], types("python-brace", "{0!r} {1:>{0}} {0:d}"));
+        assert!(parse("python-brace", "{} {0}").unwrap().is_err());
+        assert!(parse("python-brace", "{").unwrap().is_err());
+        assert!(parse("python-brace", "}").unwrap().is_err());
+    }
+
+    #[test]
+    fn parse_rust_format() {
+        assert_eq!(vec![Arg::Index(1), Arg::Index(2), Arg::Name("name".to_owned())],
+                   args("rust", "{} {name} {{}} {0:?} {}"));
+        assert_eq!(vec!["{:?},{}", "{}", "{}"], types("rust", "{} {name} {0:?} {}"));
+        assert_eq!(vec!["usize", "{:x}", "usize", "{}", "usize"],
+                   types("rust", "{:>3$.*x} {name:-^width$}"));
+        assert!(parse("rust", "{:y}").unwrap().is_err());
+        assert!(parse("rust", "{").unwrap().is_err());
+        assert!(parse("lisp", "~a").is_none());
+    }
+
+    #[test]
+    fn check_strings() {
+        assert!(check("c", "%d of %s", "%2$s: %1$d", false).is_empty());
+        assert_eq!(vec![Mismatch::Missing(Arg::Index(2))], check("c", "%d of %s", "%d", false));
+        assert!(check("c", "%d files", "one file", true).is_empty());
+        assert_eq!(vec![Mismatch::Extra(Arg::Index(2))], check("c", "%s", "%s %s", true));
+        assert_eq!(vec![Mismatch::Type(Arg::Index(1), "d".to_owned(), "s".to_owned())],
+                   check("c", "%d", "%s", false));
+        match check("c", "%d", "%", false).as_slice() {
+            [Mismatch::InvalidTarget(_)] => (),
+            x => panic!("Unexpected {:?}", x),
+        }
+        assert!(check("python", "%(a)s and %(b)d", "%(b)d a %(a)s", false).is_empty());
+        assert_eq!(vec![Mismatch::Missing(Arg::Name("b".to_owned()))],
+                   check("python-brace", "{a} and {b}", "{a}", false));
+        assert!(check("rust", "{} of {}", "{1} z {0}", false).is_empty());
+        assert_eq!(vec![Mismatch::Type(Arg::Index(1), "{}".to_owned(), "{:?}".to_owned())],
+                   check("rust", "{}", "{:?}", false));
+        assert!(check("lisp", "~a", "", false).is_empty());
+    }
+
+    #[test]
+    fn check_plural_unit() {
+        let unit = UnitBuilder::new(Message::Plural(vec![
+                (Count::One, "{} file in {dir}".to_owned()),
+                (Count::Other, "{} files in {dir}".to_owned()),
+            ].into_iter().collect()))
+            .target(Message::Plural(vec![
+                (Count::One, "jeden soubor v {dir}".to_owned()),
+                (Count::Few, "{} soubory v {adresar}".to_owned()),
+                (Count::Other, "{:?} souborů".to_owned()),
+            ].into_iter().collect()))
+            .flag(Flag::Format("rust".to_owned()))
+            .flag(Flag::Format("c".to_owned()))
+            .build()
+            .unwrap();
+        let rust = |c, m| ("rust".to_owned(), Some(c), m);
+        assert_eq!(vec![
+            rust(Count::Few, Mismatch::Missing(Arg::Name("dir".to_owned()))),
+            rust(Count::Few, Mismatch::Extra(Arg::Name("adresar".to_owned()))),
+            rust(Count::Other, Mismatch::Type(Arg::Index(1), "{}".to_owned(), "{:?}".to_owned())),
+            rust(Count::Other, Mismatch::Missing(Arg::Name("dir".to_owned()))),
+        ], check_unit(&unit, &[Count::One]));
+    }
+
+    #[test]
+    fn check_plural_variants() {
+        let unit = |target: &[(Count, &str)]| UnitBuilder::new(Message::Plural(vec![
+                (Count::One, "One file".to_owned()),
+                (Count::Other, "%d files".to_owned()),
+            ].into_iter().collect()))
+            .target(Message::Plural(target.iter().map(|&(c, t)| (c, t.to_owned())).collect()))
+            .flag(Flag::Format("c".to_owned()))
+            .build()
+            .unwrap();
+        // Russian One is used for 1, 21, 31…, so it is compared with the plural source in full.
+        let ru = unit(&[(Count::One, "%d файл"), (Count::Few, "%d файла"),
+                        (Count::Other, "%d файлов")]);
+        assert!(check_unit(&ru, &[]).is_empty());
+        let ru = unit(&[(Count::One, "один файл"), (Count::Few, "файла"),
+                        (Count::Other, "%d файлов")]);
+        assert_eq!(vec![
+            ("c".to_owned(), Some(Count::One), Mismatch::Missing(Arg::Index(1))),
+            ("c".to_owned(), Some(Count::Few), Mismatch::Missing(Arg::Index(1))),
+        ], check_unit(&ru, &[]));
+        let cs = unit(&[(Count::One, "jeden soubor"), (Count::Few, "%d soubory"),
+                        (Count::Other, "%d souborů")]);
+        assert!(check_unit(&cs, &[Count::One]).is_empty());
+    }
+}
@@ -0,0 +1,560 @@
+//! Fuzzy matching of source strings
+//!
+//! Merging catalogues and looking up suggestions in translation memory need to find the sources
+//! most similar to a given string. The similarity is one minus the normalized edit distance with
+//! insertions and deletions, the same measure gettext uses for fuzzy matches in `msgmerge`.
+//!
+//! Computing it for all pairs of units would be too slow for large catalogues, so the strings are
+//! indexed by character trigrams. The trigrams shared with the searched string and the counts of
+//! characters give bounds on the similarity, so the exact value is only computed for promising
+//! candidates. Trigrams common to a large part of the strings are not looked up, but counted as
+//! shared with all of them. Searches only try a limited number of the most promising strings.
+
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use super::Unit;
+
+/// Default minimal similarity of matches. Same as in gettext.
+pub const DEFAULT_THRESHOLD: f64 = 0.6;
+
+/// Default number of candidates for which [`FuzzyIndex::best`] computes the similarity.
+///
+/// [`FuzzyIndex::best`]: struct.FuzzyIndex.html#method.best
+pub const DEFAULT_MAX_CANDIDATES: usize = 100;
+
+/// Similarity of two strings, from 0 to 1.
+///
+/// It is the proportion of characters in the longest common subsequence, like `fstrcmp` in
+/// gettext, so 1 means the strings are equal.
+pub fn similarity(a: &str, b: &str) -> f64 {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    Pattern::new(&a).similarity(&b)
+}
+
+// String prepared for computing the longest common subsequence with other strings by the
+// bit-parallel algorithm of Hyyrö, processing 64 characters of it at once.
+struct Pattern {
+    _len: usize,
+    _words: usize,
+    // Masks of positions of each character, `_words` per character, starting with empty masks
+    // for characters not in the pattern; ASCII characters are looked up directly, as they are
+    // the most common in sources.
+    _masks: Vec<u64>,
+    _ascii: [usize; 128],
+    _other: HashMap<char, usize>,
+}
+
+impl Pattern {
+    fn new(s: &[char]) -> Self {
+        let words = s.len().div_ceil(64);
+        let mut pattern = Pattern {
+            _len: s.len(),
+            _words: words,
+            _masks: vec![0; words],
+            _ascii: [0; 128],
+            _other: HashMap::new(),
+        };
+        for (i, &c) in s.iter().enumerate() {
+            let offset = match pattern.offset(c) {
+                0 => {
+                    let offset = pattern._masks.len();
+                    pattern._masks.resize(offset + words, 0);
+                    match pattern._ascii.get_mut(c as usize) {
+                        Some(a) => *a = offset,
+                        None => { pattern._other.insert(c, offset); }
+                    }
+                    offset
+                }
+                offset => offset,
+            };
+            pattern._masks[offset + i / 64] |= 1 << (i % 64);
+        }
+        pattern
+    }
+
+    fn offset(&self, c: char) -> usize {
+        match self._ascii.get(c as usize) {
+            Some(&offset) => offset,
+            None => self._other.get(&c).cloned().unwrap_or(0),
+        }
+    }
+
+    // Length of the longest common subsequence with `other`.
+    fn lcs(&self, other: &[char]) -> usize {
+        // Zero bits of `v` mark positions of the pattern in the common subsequence so far.
+        if self._words == 1 {
+            let mut v = !0u64;
+            for &c in other {
+                let u = v & self._masks[self.offset(c)];
+                v = v.wrapping_add(u) | (v & !u);
+            }
+            return v.count_zeros() as usize;
+        }
+        let mut v = vec![!0u64; self._words];
+        for &c in other {
+            let offset = self.offset(c);
+            let mask = &self._masks[offset..offset + self._words];
+            let mut carry = 0;
+            for (v, &m) in v.iter_mut().zip(mask) {
+                let u = *v & m;
+                let (sum, c1) = v.overflowing_add(u);
+                let (sum, c2) = sum.overflowing_add(carry);
+                carry = (c1 || c2) as u64;
+                *v = sum | (*v & !u);
+            }
+        }
+        v.iter().map(|v| v.count_zeros() as usize).sum()
+    }
+
+    fn similarity(&self, other: &[char]) -> f64 {
+        if self._len == 0 && other.is_empty() {
+            return 1.0;
+        }
+        2.0 * self.lcs(other) as f64 / (self._len + other.len()) as f64
+    }
+}
+
+type Trigram = [char; 3];
+
+// Counts of characters by their code modulo 32, for strings short enough for the counts to fit.
+// The common counts bound the longest common subsequence and are much faster to compute.
+type Histogram = Option<[u8; 32]>;
+
+fn histogram(s: &[char]) -> Histogram {
+    if s.len() > u8::MAX as usize {
+        return None;
+    }
+    let mut res = [0u8; 32];
+    for &c in s {
+        res[c as usize % 32] += 1;
+    }
+    Some(res)
+}
+
+// Upper bound of similarity of strings of given lengths with given histograms.
+fn histogram_bound(a: &Histogram, b: &Histogram, len: usize, other: usize) -> f64 {
+    match (a, b) {
+        (Some(a), Some(b)) if len + other > 0 => {
+            let common: usize = a.iter().zip(b).map(|(&x, &y)| x.min(y) as usize).sum();
+            2.0 * common as f64 / (len + other) as f64
+        }
+        _ => 1.0,
+    }
+}
+
+// Trigrams in more than this fraction of the strings, and in more than `STOP_MIN` strings, are
+// not looked up; instead every string is assumed to share them, which keeps the bounds valid.
+const STOP_FRACTION: usize = 16;
+const STOP_MIN: usize = 256;
+
+// Trigrams of the string padded with two NULs at each end, with their counts.
+fn trigrams(s: &[char]) -> HashMap<Trigram, u32> {
+    let mut padded = vec!['\0', '\0'];
+    padded.extend_from_slice(s);
+    padded.extend_from_slice(&['\0', '\0']);
+    let mut res = HashMap::new();
+    for w in padded.windows(3) {
+        *res.entry([w[0], w[1], w[2]]).or_insert(0) += 1;
+    }
+    res
+}
+
+/// Index of strings for fuzzy search.
+///
+/// Strings are identified by the order in which they were added, starting from 0. Search returns
+/// strings with similarity (see [`similarity`]) at least the threshold, best first.
+///
+/// Searches reuse a buffer inside the index, so it can't be shared between threads.
+///
+/// ```
+/// use translate_storage::fuzzy::FuzzyIndex;
+///
+/// let mut index = FuzzyIndex::default();
+/// index.add("Cannot open file %s");
+/// index.add("Save as…");
+/// let found = index.search("Cannot open the file %s");
+/// assert_eq!(1, found.len());
+/// assert_eq!(0, found[0].0);
+/// ```
+///
+/// [`similarity`]: fn.similarity.html
+#[derive(Clone,Debug)]
+pub struct FuzzyIndex {
+    _threshold: f64,
+    _max_candidates: usize,
+    _strings: Vec<Vec<char>>,
+    _histograms: Vec<Histogram>,
+    _postings: HashMap<Trigram, Vec<(u32, u32)>>,
+    _lengths: BTreeMap<usize, Vec<u32>>,
+    _scratch: RefCell<Scratch>,
+}
+
+// Counts of trigrams shared with the query, kept between searches so that only the strings
+// that share some are touched.
+#[derive(Clone,Debug,Default)]
+struct Scratch {
+    _shared: Vec<u32>,
+    _touched: Vec<u32>,
+}
+
+impl Default for FuzzyIndex {
+    fn default() -> Self {
+        FuzzyIndex::new(DEFAULT_THRESHOLD)
+    }
+}
+
+impl FuzzyIndex {
+    /// Create empty index with given threshold.
+    pub fn new(threshold: f64) -> Self {
+        FuzzyIndex {
+            _threshold: threshold,
+            _max_candidates: DEFAULT_MAX_CANDIDATES,
+            _strings: Vec::new(),
+            _histograms: Vec::new(),
+            _postings: HashMap::new(),
+            _lengths: BTreeMap::new(),
+            _scratch: RefCell::default(),
+        }
+    }
+
+    /// Create index of sources of units, identified by their position in the iterator.
+    pub fn from_units<'a, I: IntoIterator<Item = &'a Unit>>(units: I, threshold: f64) -> Self {
+        let mut index = FuzzyIndex::new(threshold);
+        for unit in units {
+            index.add(unit.source().key());
+        }
+        index
+    }
+
+    pub fn threshold(&self) -> f64 { self._threshold }
+
+    pub fn set_threshold(&mut self, threshold: f64) { self._threshold = threshold; }
+
+    pub fn max_candidates(&self) -> usize { self._max_candidates }
+
+    pub fn set_max_candidates(&mut self, max: usize) { self._max_candidates = max; }
+
+    /// Number of indexed strings.
+    pub fn len(&self) -> usize { self._strings.len() }
+
+    pub fn is_empty(&self) -> bool { self._strings.is_empty() }
+
+    /// Add a string and return its identifier.
+    pub fn add(&mut self, text: &str) -> usize {
+        let id = self._strings.len();
+        let chars: Vec<char> = text.chars().collect();
+        for (t, n) in trigrams(&chars) {
+            self._postings.entry(t).or_default().push((id as u32, n));
+        }
+        self._lengths.entry(chars.len()).or_default().push(id as u32);
+        self._scratch.get_mut()._shared.push(0);
+        self._histograms.push(histogram(&chars));
+        self._strings.push(chars);
+        id
+    }
+
+    /// Get an indexed string.
+    pub fn get(&self, id: usize) -> Option<String> {
+        self._strings.get(id).map(|s| s.iter().collect())
+    }
+
+    /// Find strings similar to `text`.
+    ///
+    /// Returns identifiers with the similarity, ordered from the most similar; strings with the
+    /// same similarity are ordered by identifier.
+    ///
+    /// Like `best`, this is a heuristic: the similarity is only computed for the `max_candidates`
+    /// most promising strings, so when more strings are similar, some are missing from the result.
+    /// With `max_candidates` at least the number of indexed strings, all matches are returned.
+    pub fn search(&self, text: &str) -> Vec<(usize, f64)> {
+        let query: Vec<char> = text.chars().collect();
+        let len = query.len();
+        let t = self._threshold.max(0.0);
+        let pattern = Pattern::new(&query);
+        let histogram = histogram(&query);
+        let mut res = Vec::new();
+        for (Reverse(bound), _, id, n) in self.ranked_candidates(&query, t) {
+            if f64::from_bits(bound) < t {
+                break;
+            }
+            let other = &self._strings[id];
+            let chars = histogram_bound(&histogram, &self._histograms[id], len, other.len());
+            if n < trigram_bound(len, other.len(), t) || chars < t {
+                continue;
+            }
+            let score = pattern.similarity(other);
+            if score >= t {
+                res.push((id, score));
+            }
+        }
+        res.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then(a.0.cmp(&b.0)));
+        res
+    }
+
+    /// Find the string most similar to `text`, if any is above the threshold.
+    ///
+    /// Of strings with the same similarity, the one with the lowest identifier is returned.
+    ///
+    /// This is faster than `search`, because candidates that cannot beat the best match found so
+    /// far are skipped. Like the fuzzy search in gettext, it is a heuristic: when no match is good
+    /// enough to rule out the rest, it gives up after computing similarity of `max_candidates`
+    /// strings, so it may miss a match that shares few trigrams with `text`. Strings sharing only
+    /// the most common trigrams are not tried at all, unless there are not enough others.
+    pub fn best(&self, text: &str) -> Option<(usize, f64)> {
+        let query: Vec<char> = text.chars().collect();
+        let len = query.len();
+        let pattern = Pattern::new(&query);
+        let histogram = histogram(&query);
+        let mut best: Option<(usize, f64)> = None;
+        let mut best_score = self._threshold.max(0.0);
+        for (Reverse(bound), _, id, n) in self.ranked_candidates(&query, best_score) {
+            if f64::from_bits(bound) < best_score {
+                break;
+            }
+            let other = &self._strings[id];
+            let chars = histogram_bound(&histogram, &self._histograms[id], len, other.len());
+            if n < trigram_bound(len, other.len(), best_score) || chars < best_score {
+                continue;
+            }
+            let score = pattern.similarity(other);
+            if score > best_score || (score == best_score && best.is_none_or(|b| id < b.0)) {
+                best = Some((id, score));
+                best_score = score;
+            }
+        }
+        best
+    }
+
+    // The `max_candidates` most promising strings for similarity at least `t`, from the highest
+    // bound of their similarity and, as the bound is often 1 for short strings, the highest
+    // proportion of shared trigrams, with the number of trigrams they may share with the query.
+    // Strings sharing only the most common trigrams are only included if there are not enough
+    // others.
+    fn ranked_candidates(&self, query: &[char], t: f64)
+            -> Vec<(Reverse<u64>, Reverse<u64>, usize, i64)> {
+        let len = query.len();
+        let mut candidates = self.candidates(query, t, true);
+        if candidates.len() < self._max_candidates {
+            candidates = self.candidates(query, t, false);
+        }
+        // Non-negative floats compare the same as their bits.
+        let mut queue: Vec<(Reverse<u64>, Reverse<u64>, usize, i64)> = candidates.into_iter()
+            .map(|(id, n)| {
+                let other = self._strings[id].len();
+                let bound = similarity_bound(len, other, n);
+                let shared = 2.0 * n.max(0) as f64 / (len + other + 4) as f64;
+                (Reverse(bound.to_bits()), Reverse(shared.to_bits()), id, n)
+            })
+            .collect();
+        if queue.len() > self._max_candidates {
+            queue.select_nth_unstable(self._max_candidates);
+            queue.truncate(self._max_candidates);
+        }
+        queue.sort_unstable();
+        queue
+    }
+
+    // Strings that may have similarity at least `t` with the query, with the number of trigrams
+    // they may share with it. With `shared_only`, only strings sharing some trigram that is not
+    // too common are returned.
+    fn candidates(&self, query: &[char], t: f64, shared_only: bool) -> Vec<(usize, i64)> {
+        let len = query.len();
+        // Similarity is at most 2·min/(len + other length), which limits the other length.
+        let (min_len, max_len) = if t > 0.0 {
+            ((len as f64 * t / (2.0 - t) - 1e-9).ceil() as usize,
+             (len as f64 * (2.0 - t) / t + 1e-9).floor() as usize)
+        } else {
+            (0, usize::MAX)
+        };
+
+        let mut scratch = self._scratch.borrow_mut();
+        let Scratch { _shared: ref mut shared, _touched: ref mut touched } = *scratch;
+        let stop = (self._strings.len() / STOP_FRACTION).max(STOP_MIN);
+        let mut common = 0;
+        for (g, n) in trigrams(query) {
+            match self._postings.get(&g) {
+                Some(postings) if postings.len() > stop => common += n as i64,
+                Some(postings) => for &(id, m) in postings {
+                    if shared[id as usize] == 0 {
+                        touched.push(id);
+                    }
+                    shared[id as usize] += n.min(m);
+                },
+                None => (),
+            }
+        }
+
+        let mut res = Vec::new();
+        if shared_only {
+            let longest = self._lengths.keys().next_back().map_or(0, |&l| l.min(max_len));
+            let bounds: Vec<i64> = (0..=longest)
+                .map(|other| if other < min_len { i64::MAX } else { trigram_bound(len, other, t) })
+                .collect();
+            for &id in touched.iter() {
+                let n = shared[id as usize] as i64 + common;
+                if bounds.get(self._strings[id as usize].len()).is_some_and(|&b| n >= b) {
+                    res.push((id as usize, n));
+                }
+            }
+        } else {
+            for (&other, ids) in self._lengths.range(min_len..) {
+                if other > max_len {
+                    break;
+                }
+                let bound = trigram_bound(len, other, t);
+                res.extend(ids.iter()
+                    .map(|&id| (id as usize, shared[id as usize] as i64 + common))
+                    .filter(|&(_, n)| n >= bound));
+            }
+        }
+        for &id in touched.iter() {
+            shared[id as usize] = 0;
+        }
+        touched.clear();
+        res
+    }
+}
+
+// Upper bound of similarity of strings of given lengths sharing `n` trigrams. Inverts
+// `trigram_bound`: each common character beyond the bound may add at most 5 shared trigrams.
+fn similarity_bound(len: usize, other: usize, n: i64) -> f64 {
+    if len + other == 0 {
+        return 1.0;
+    }
+    let common = ((n + 2 * (len + other) as i64 - 2) / 5).max(0).min(len.min(other) as i64);
+    2.0 * common as f64 / (len + other) as f64
+}
+
+// Minimal number of trigrams (of the padded strings) shared by strings of given lengths with
+// similarity at least `t`. Deleting a character breaks at most 3 trigrams and inserting one at
+// most 2, and the similarity limits the number of deleted and inserted characters.
+fn trigram_bound(len: usize, other: usize, t: f64) -> i64 {
+    let common = ((len + other) as f64 * t / 2.0 - 1e-9).ceil().max(0.0) as i64;
+    let (len, other) = (len as i64, other as i64);
+    len + 2 - 3 * (len - common).max(0) - 2 * (other - common).max(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{similarity, FuzzyIndex, Pattern};
+
+    // Longest common subsequence by the textbook dynamic programming.
+    fn naive_lcs(a: &[char], b: &[char]) -> usize {
+        let mut row = vec![0usize; b.len() + 1];
+        for &ca in a {
+            let mut diag = 0;
+            for (j, &cb) in b.iter().enumerate() {
+                let up = row[j + 1];
+                row[j + 1] = if ca == cb { diag + 1 } else { up.max(row[j]) };
+                diag = up;
+            }
+        }
+        row[b.len()]
+    }
+
+    #[test]
+    fn similar() {
+        assert_eq!(1.0, similarity("", ""));
+        assert_eq!(1.0, similarity("abc", "abc"));
+        assert_eq!(0.0, similarity("abc", "xyz"));
+        assert_eq!(0.8, similarity("abcd", "abxcde"));
+    }
+
+    #[test]
+    fn long_lcs() {
+        let texts = ["", "a", "abracadabra",
+                     &"The quick brown fox jumps over the lazy dog. ".repeat(4),
+                     &"The quick brown cat jumped over a lazy dog! ".repeat(5),
+                     &"ab".repeat(100), &"ba".repeat(70)];
+        for a in &texts {
+            let a: Vec<char> = a.chars().collect();
+            let pattern = Pattern::new(&a);
+            for b in &texts {
+                let b: Vec<char> = b.chars().collect();
+                assert_eq!(naive_lcs(&a, &b), pattern.lcs(&b));
+            }
+        }
+    }
+
+    #[test]
+    fn search() {
+        let words = ["Open", "Open…", "Open file", "Cannot open file %s", "Cannot open the file %s",
+                     "Close", "Quit", "%d file", "%d files", "Save the file as %s", "x"];
+        let mut index = FuzzyIndex::default();
+        for w in &words {
+            index.add(w);
+        }
+        assert_eq!(words.len(), index.len());
+        assert_eq!(Some("Open…".to_owned()), index.get(1));
+        // Compare with checking all strings.
+        for threshold in &[0.0, 0.3, 0.6, 0.9, 1.0] {
+            index.set_threshold(*threshold);
+            for q in words.iter().chain(["", "Op", "open files", "Cannot save file %s"].iter()) {
+                let mut all: Vec<(usize, f64)> = words.iter()
+                    .map(|w| similarity(q, w))
+                    .enumerate()
+                    .filter(|&(_, s)| s >= *threshold)
+                    .collect();
+                all.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then(a.0.cmp(&b.0)));
+                assert_eq!(all, index.search(q), "searching {:?} at {}", q, threshold);
+                assert_eq!(all.first().cloned(), index.best(q), "best {:?} at {}", q, threshold);
+            }
+        }
+        index.set_threshold(0.6);
+        assert_eq!(Some(4), index.best("Cannot open the file %s").map(|m| m.0));
+        assert_eq!(vec![8, 7], index.search("%d files").iter().map(|m| m.0).collect::<Vec<_>>());
+        assert_eq!(None, index.best("Something else entirely"));
+    }
+
+    // Strings of random words, either of random letters or from a small vocabulary, which makes
+    // the texts repetitive like in real catalogues.
+    fn sample_texts(n: usize, repetitive: bool, seed: u64) -> Vec<String> {
+        let vocabulary = ["the", "file", "open", "cannot", "save", "to", "of", "%s", "%d", "a",
+                          "error", "is", "not", "and", "in", "directory", "name", "for", "user"];
+        let mut state = seed;
+        let mut next = move |n: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % n as u64) as usize
+        };
+        (0..n).map(|_| {
+            let words: Vec<String> = (0..2 + next(10)).map(|_| if repetitive {
+                vocabulary[next(vocabulary.len())].to_owned()
+            } else {
+                (0..1 + next(8)).map(|_| (b'a' + next(26) as u8) as char).collect()
+            }).collect();
+            words.join(" ")
+        }).collect()
+    }
+
+    #[test]
+    fn search_with_common_trigrams() {
+        let texts = sample_texts(1000, true, 3);
+        let mut index = FuzzyIndex::default();
+        for text in &texts {
+            index.add(text);
+        }
+        for threshold in &[0.6, 0.9] {
+            index.set_threshold(*threshold);
+            for q in &sample_texts(10, true, 4) {
+                let mut all: Vec<(usize, f64)> = texts.iter()
+                    .map(|w| similarity(q, w))
+                    .enumerate()
+                    .filter(|&(_, s)| s >= *threshold)
+                    .collect();
+                all.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then(a.0.cmp(&b.0)));
+                let found = index.search(q);
+                assert!(found.len() <= index.max_candidates());
+                assert!(found.iter().all(|m| all.contains(m)), "searching {:?} at {}", q, threshold);
+                assert_eq!(found.first().cloned(), index.best(q), "best {:?} at {}", q, threshold);
+
+                index.set_max_candidates(texts.len());
+                assert_eq!(all, index.search(q), "searching {:?} at {}", q, threshold);
+                index.set_max_candidates(super::DEFAULT_MAX_CANDIDATES);
+            }
+        }
+    }
+}
@@ -21,10 +21,17 @@
 #[macro_use]
 extern crate lazy_static;
 
+extern crate chrono;
+
+extern crate encoding_rs;
+
 extern crate locale_config;
 
 extern crate regex;
 
+extern crate xml;
+
+use chrono::{DateTime,FixedOffset};
 use std::collections::BTreeMap;
 use locale_config::LanguageRange;
 
@@ -60,18 +67,31 @@ macro_rules! is {
 }
 
 
+pub mod build;
+pub mod catalogue;
+pub mod check;
+pub mod format;
+pub mod fuzzy;
+pub mod locale;
+pub mod merge;
+pub mod plural;
+pub mod mo;
 pub mod po;
+pub mod stats;
+pub mod translator;
+pub mod xliff;
 
 /// Plural variants
 ///
 /// Which variants are used depends on the language. In English it is easy: 1 is One and everything
 /// else is Other. But other languages may have more cases, with Arabic having all six.
 // TODO: When Count is in locale, use that version
-#[derive(Copy,Clone,Debug,Eq,PartialEq,Ord,PartialOrd,Hash)]
+#[derive(Copy,Clone,Debug,Default,Eq,PartialEq,Ord,PartialOrd,Hash)]
 pub enum Count {
     /// Zero has a separate variant in some langauges.
     Zero,
     /// One. In some languages also includes zero.
+    #[default]
     One,
     /// Special case for two.
     Two,
@@ -83,16 +103,13 @@ pub enum Count {
     Other,
 }
 
-impl Default for Count {
-    fn default() -> Count { Count::One }
-}
-
 /// String wrapper possibly with plural variants.
 ///
 /// This is used for source and target strings in translation Unit.
-#[derive(Clone,Debug,Eq,PartialEq,Ord,PartialOrd,Hash)]
+#[derive(Clone,Debug,Default,Eq,PartialEq,Ord,PartialOrd,Hash)]
 pub enum Message {
     /// Unset message, used for untranslated entries.
+    #[default]
     Empty,
     /// Message independent of any count.
     Singular(String),
@@ -102,44 +119,72 @@ pub enum Message {
 
 impl Message {
     pub fn is_empty(&self) -> bool {
-        match self {
-            &Message::Empty => true,
-            _ => false,
-        }
+        is!(*self => Message::Empty)
     }
 
     pub fn is_singular(&self) -> bool {
-        match self {
-            &Message::Singular(_) => true,
-            _ => false,
-        }
+        is!(*self => Message::Singular(_))
     }
 
     pub fn is_plural(&self) -> bool {
-        match self {
-            &Message::Plural(_) => true,
-            _ => false,
-        }
+        is!(*self => Message::Plural(_))
     }
 
     pub fn is_blank(&self) -> bool {
-        match self {
-            &Message::Empty => true,
-            &Message::Singular(ref s) => s == "",
-            &Message::Plural(ref m) => m.values().all(|s| s == ""),
+        match *self {
+            Message::Empty => true,
+            Message::Singular(ref s) => s.is_empty(),
+            Message::Plural(ref m) => m.values().all(String::is_empty),
         }
     }
 
     pub fn singular(&self) -> Option<&str> {
-        match self {
-            &Message::Singular(ref s) => Some(s.as_ref()),
-            _ => None,
+        unpack!(*self => Message::Singular(ref s) => s.as_ref())
+    }
+
+    /// Get the string identifying the message, like `msgid` in gettext.
+    ///
+    /// It is the singular string, or the One variant of plural message (the first variant if
+    /// there is no One).
+    pub fn key(&self) -> &str {
+        match *self {
+            Message::Empty => "",
+            Message::Singular(ref s) => s,
+            Message::Plural(ref map) =>
+                map.get(&Count::One).or_else(|| map.values().next()).map_or("", String::as_str),
         }
     }
+
+    // Check the invariants of the variants.
+    fn check(&self) -> Result<(), Error> {
+        match *self {
+            Message::Plural(ref map) if !map.contains_key(&Count::Other) =>
+                Err(Error::Invalid("plural message without Other variant")),
+            _ => Ok(()),
+        }
+    }
+
+    // Check that the target is singular for singular source and plural for plural one. Either
+    // can be empty.
+    fn check_target(&self, target: &Message) -> Result<(), Error> {
+        if self.is_empty() || target.is_empty() || self.is_plural() == target.is_plural() {
+            Ok(())
+        } else {
+            Err(Error::Invalid("source and target are not both singular or both plural"))
+        }
+    }
+}
+
+impl<'a> From<&'a str> for Message {
+    fn from(s: &'a str) -> Message {
+        Message::Singular(s.to_owned())
+    }
 }
 
-impl Default for Message {
-    fn default() -> Message { Message::Empty }
+impl From<String> for Message {
+    fn from(s: String) -> Message {
+        Message::Singular(s)
+    }
 }
 
 /// Note (comment) origins.
@@ -153,6 +198,69 @@ pub enum Origin {
     Tag(String),
 }
 
+/// Flags of a unit.
+///
+/// These are the hints for tools processing the translation, like the `#,` comments in PO. The
+/// `fuzzy` flag is not among them, as it is represented by the unit `State`.
+#[derive(Clone,Debug,Eq,PartialEq,Hash)]
+pub enum Flag {
+    /// The message is a format string in given language, e.g. `c` for `c-format`.
+    Format(String),
+    /// The message is not a format string in given language even if it looks like one, e.g. `c`
+    /// for `no-c-format`. Unlike the lack of `Format`, this is not overridden by tools guessing
+    /// the format.
+    NoFormat(String),
+    /// Range of numbers the plural message is used with, inclusive, like `range: 1..5`.
+    Range(u64, u64),
+    /// The message should not be wrapped.
+    NoWrap,
+    /// The message should be wrapped, which is the default.
+    Wrap,
+    /// Other flag, kept verbatim.
+    Other(String),
+}
+
+impl Flag {
+    /// Parse a flag in the PO syntax.
+    pub fn parse(flag: &str) -> Flag {
+        let flag = flag.trim();
+        let range = |r: &str| {
+            let mut bounds = r.trim().splitn(2, "..").map(|b| b.trim().parse::<u64>().ok());
+            match (bounds.next()?, bounds.next()?) {
+                (Some(min), Some(max)) if min <= max => Some(Flag::Range(min, max)),
+                _ => None,
+            }
+        };
+        if flag == "wrap" {
+            Flag::Wrap
+        } else if flag == "no-wrap" {
+            Flag::NoWrap
+        } else if let Some(f) = flag.strip_prefix("range:").and_then(range) {
+            f
+        } else if let Some(lang) = flag.strip_prefix("no-").and_then(|f| f.strip_suffix("-format")) {
+            Flag::NoFormat(lang.to_owned())
+        } else if let Some(lang) = flag.strip_suffix("-format") {
+            Flag::Format(lang.to_owned())
+        } else {
+            Flag::Other(flag.to_owned())
+        }
+    }
+}
+
+impl std::fmt::Display for Flag {
+    /// Format the flag in the PO syntax.
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Flag::Format(ref lang) => write!(f, "{}-format", lang),
+            Flag::NoFormat(ref lang) => write!(f, "no-{}-format", lang),
+            Flag::Range(min, max) => write!(f, "range: {}..{}", min, max),
+            Flag::NoWrap => write!(f, "no-wrap"),
+            Flag::Wrap => write!(f, "wrap"),
+            Flag::Other(ref flag) => write!(f, "{}", flag),
+        }
+    }
+}
+
 /// Translation state.
 ///
 /// Indicates whether the translation is considered usable.
@@ -161,9 +269,10 @@ pub enum Origin {
 ///
 /// - Rejected, Unreviewed, NeedsReview (from TT), possibly more (note: obsolete is a separate
 ///   flag)
-#[derive(Copy,Clone,PartialEq,Eq,PartialOrd,Ord,Debug,Hash)]
+#[derive(Copy,Clone,PartialEq,Eq,PartialOrd,Ord,Debug,Default,Hash)]
 pub enum State {
     /// The unit is not translated.
+    #[default]
     Empty,
     /// The unit is a suggestion that might be embarrassingly wrong, possibly automatic. It needs
     /// checking by human translator before it can be used. (Used for `#,fuzzy` entries in `.po`.)
@@ -172,10 +281,6 @@ pub enum State {
     Final,
 }
 
-impl Default for State {
-    fn default() -> State { State::Empty }
-}
-
 /// Elementary unit of translation.
 ///
 /// A translation unit contains:
@@ -189,7 +294,9 @@ impl Default for State {
 ///  - Notes, from developer or translator.
 ///  - References back into the source where the unit is used.
 ///  - Previous source and context if the target is automatic suggestion from fuzzy matching.
+///  - Flags, like whether the message is a format string.
 ///  - Obsolete flag, indicating the unit is not currently in use.
+///  - Line in the file the unit was read from, for reporting problems.
 #[derive(Clone,Debug,Default)]
 pub struct Unit {
     _context: Option<String>,
@@ -199,8 +306,10 @@ pub struct Unit {
     _prev_source: Message,
     _notes: Vec<(Origin, String)>,
     _locations: Vec<String>,
+    _flags: Vec<Flag>,
     _state: State,
     _obsolete: bool,
+    _line: usize,
 }
 
 impl Unit {
@@ -218,24 +327,266 @@ impl Unit {
     pub fn notes(&self) -> &Vec<(Origin, String)> { &self._notes }
     /// Get locations.
     pub fn locations(&self) -> &Vec<String> { &self._locations }
+    /// Get the flags.
+    pub fn flags(&self) -> &Vec<Flag> { &self._flags }
+    /// Get the language the message is format string in, according to the `Format` flag.
+    pub fn format(&self) -> Option<&str> {
+        self._flags.iter().filter_map(|f| unpack!(*f => Flag::Format(ref lang) => lang.as_str())).next()
+    }
     /// Get the state.
     pub fn state(&self) -> State { self._state }
     /// Returns whether the unit should be used in application.
     pub fn is_translated(&self) -> bool { self._state == State::Final }
     /// Returns whether the unit is obsolete.
     pub fn is_obsolete(&self) -> bool { self._obsolete }
+    /// Get the line the unit starts at in the file it was read from, or 0 if not known.
+    ///
+    /// For PO it is the line of `msgid`, like gettext tools report.
+    pub fn line(&self) -> usize { self._line }
+
+    /// Set the context string.
+    pub fn set_context(&mut self, context: Option<String>) { self._context = context; }
+
+    /// Set the source string. It can't be empty and if plural, it must have the Other variant.
+    /// Unless the target is empty, it must be plural exactly when the target is.
+    pub fn set_source(&mut self, source: Message) -> Result<(), Error> {
+        if source.is_empty() {
+            return Err(Error::Invalid("empty source"));
+        }
+        source.check()?;
+        source.check_target(&self._target)?;
+        self._source = source;
+        Ok(())
+    }
+
+    /// Set the target string. If plural, it must have the Other variant, and unless empty, it
+    /// must be plural exactly when the source is.
+    ///
+    /// The state is not changed; set it as appropriate.
+    pub fn set_target(&mut self, target: Message) -> Result<(), Error> {
+        target.check()?;
+        self._source.check_target(&target)?;
+        self._target = target;
+        Ok(())
+    }
+
+    /// Set the previous context.
+    pub fn set_prev_context(&mut self, context: Option<String>) { self._prev_context = context; }
+
+    /// Set the previous source. If plural, it must have the Other variant.
+    pub fn set_prev_source(&mut self, source: Message) -> Result<(), Error> {
+        source.check()?;
+        self._prev_source = source;
+        Ok(())
+    }
+
+    /// Set the notes/comments.
+    pub fn set_notes(&mut self, notes: Vec<(Origin, String)>) { self._notes = notes; }
+
+    /// Add a note/comment.
+    pub fn add_note(&mut self, origin: Origin, note: &str) { self._notes.push((origin, note.to_owned())); }
+
+    /// Set locations.
+    pub fn set_locations(&mut self, locations: Vec<String>) { self._locations = locations; }
+
+    /// Add a location.
+    pub fn add_location(&mut self, location: &str) { self._locations.push(location.to_owned()); }
+
+    /// Set the flags.
+    pub fn set_flags(&mut self, flags: Vec<Flag>) { self._flags = flags; }
+
+    /// Add a flag, unless already present.
+    pub fn add_flag(&mut self, flag: Flag) {
+        if !self._flags.contains(&flag) {
+            self._flags.push(flag);
+        }
+    }
+
+    /// Set the state.
+    pub fn set_state(&mut self, state: State) { self._state = state; }
+
+    /// Set whether the unit is obsolete.
+    pub fn set_obsolete(&mut self, obsolete: bool) { self._obsolete = obsolete; }
+
+    /// Set the line the unit starts at, 0 if not known.
+    pub fn set_line(&mut self, line: usize) { self._line = line; }
+}
+
+/// Builder for units.
+///
+/// Sets the unit fields one by one and checks the invariants when the unit is built. Unless set
+/// explicitly, the state is `Final` if the target is not blank and `Empty` otherwise.
+///
+/// ```
+/// use translate_storage::{Origin, UnitBuilder};
+///
+/// let unit = UnitBuilder::new("Open")
+///     .context("menu")
+///     .target("Otevřít")
+///     .note(Origin::Developer, "File menu item")
+///     .location("src/menu.rs:42")
+///     .build()
+///     .unwrap();
+/// assert!(unit.is_translated());
+/// ```
+#[derive(Clone,Debug)]
+pub struct UnitBuilder {
+    _unit: Unit,
+    _state: Option<State>,
+}
+
+impl UnitBuilder {
+    /// Start building unit with given source.
+    pub fn new<M: Into<Message>>(source: M) -> Self {
+        UnitBuilder {
+            _unit: Unit { _source: source.into(), ..Unit::default() },
+            _state: None,
+        }
+    }
+
+    /// Set the context string.
+    pub fn context(mut self, context: &str) -> Self {
+        self._unit._context = Some(context.to_owned());
+        self
+    }
+
+    /// Set the target string.
+    pub fn target<M: Into<Message>>(mut self, target: M) -> Self {
+        self._unit._target = target.into();
+        self
+    }
+
+    /// Set the previous context.
+    pub fn prev_context(mut self, context: &str) -> Self {
+        self._unit._prev_context = Some(context.to_owned());
+        self
+    }
+
+    /// Set the previous source.
+    pub fn prev_source<M: Into<Message>>(mut self, source: M) -> Self {
+        self._unit._prev_source = source.into();
+        self
+    }
+
+    /// Add a note/comment.
+    pub fn note(mut self, origin: Origin, note: &str) -> Self {
+        self._unit.add_note(origin, note);
+        self
+    }
+
+    /// Add a location.
+    pub fn location(mut self, location: &str) -> Self {
+        self._unit.add_location(location);
+        self
+    }
+
+    /// Add a flag, unless already present.
+    pub fn flag(mut self, flag: Flag) -> Self {
+        self._unit.add_flag(flag);
+        self
+    }
+
+    /// Set the state explicitly.
+    pub fn state(mut self, state: State) -> Self {
+        self._state = Some(state);
+        self
+    }
+
+    /// Set whether the unit is obsolete.
+    pub fn obsolete(mut self, obsolete: bool) -> Self {
+        self._unit._obsolete = obsolete;
+        self
+    }
+
+    /// Build the unit, checking that the source is not empty, plural messages have the Other
+    /// variant and the target, if any, is plural exactly when the source is.
+    pub fn build(self) -> Result<Unit, Error> {
+        let mut unit = self._unit;
+        if unit._source.is_empty() {
+            return Err(Error::Invalid("empty source"));
+        }
+        unit._source.check()?;
+        unit._target.check()?;
+        unit._source.check_target(&unit._target)?;
+        unit._prev_source.check()?;
+        unit._state = self._state.unwrap_or(
+            if unit._target.is_blank() { State::Empty } else { State::Final });
+        Ok(unit)
+    }
 }
 
 /// Catalogue reader.
 ///
 /// Defines common interface of catalogue readers. Read the units by simply iterating over the
 /// reader. The other methods are for the important metadata.
+///
+/// Metadata not covered by specific methods are available as properties. All the formats can
+/// carry the gettext header fields, so those are used as the properties, and the default
+/// implementations of the specific methods look up the corresponding gettext fields.
 pub trait CatalogueReader : Iterator<Item = Result<Unit, Error>> {
     fn target_language(&self) -> &LanguageRange<'static>;
-    // TODO: More attributes, possibly a generic API
+
+    /// Get the language of the source strings, if the format records it.
+    fn source_language(&self) -> Option<&LanguageRange<'static>> {
+        None
+    }
+
+    /// Get the header entry, if the catalogue has one.
+    ///
+    /// It is the gettext header entry, with empty source, the fields in the target and the
+    /// comments in notes. It is not returned by the iterator, but passing it to
+    /// `CatalogueWriter::write_header` keeps the comments and state of the header.
+    fn header_unit(&self) -> Option<&Unit> {
+        None
+    }
+
+    /// Get name and version of the project the catalogue belongs to, like the
+    /// `Project-Id-Version` field in PO.
+    fn project(&self) -> Option<&str> {
+        self.property("Project-Id-Version")
+    }
+
+    /// Get the date of the last change to the translation.
+    fn revision_date(&self) -> Option<DateTime<FixedOffset>> {
+        self.property("PO-Revision-Date").and_then(po::parse_date)
+    }
+
+    /// Get the name of the tool that last wrote the catalogue.
+    fn generator(&self) -> Option<&str> {
+        self.property("X-Generator")
+    }
+
+    /// Get all the properties, in their original order.
+    fn properties(&self) -> Vec<(&str, &str)> {
+        Vec::new()
+    }
+
+    /// Get a property by key.
+    fn property(&self, key: &str) -> Option<&str> {
+        self.properties().into_iter().find(|p| p.0 == key).map(|p| p.1)
+    }
+}
+
+/// Catalogue writer.
+///
+/// Defines common interface of catalogue writers, so conversion can be generic over the output
+/// format. Write the header first, then the units, and finally call `finish` to complete the
+/// catalogue.
+pub trait CatalogueWriter {
+    /// Write the catalogue header.
+    ///
+    /// The `header` is the header entry of a PO catalogue, if there is one (see
+    /// `CatalogueReader::header_unit`). Formats that store the metadata differently take from it
+    /// what they can.
+    fn write_header(&mut self, target_language: &LanguageRange, header: Option<&Unit>)
+        -> Result<(), Error>;
+    /// Write a unit.
+    fn write_unit(&mut self, unit: &Unit) -> Result<(), Error>;
+    /// Complete the catalogue and flush the output.
+    fn finish(&mut self) -> Result<(), Error>;
 }
 
-/// Error in reading (and, in future, writing) a catalogue.
+/// Error in reading or writing a catalogue.
 #[derive(Debug)]
 pub enum Error {
     /// An I/O error from file operation.
@@ -247,16 +598,45 @@ pub enum Error {
     /// Parameters are line number, optional unexpected token and an array of expected tokens.
     /// Unset unexpected token means the parser is not smart enough to remember what it stopped on.
     /// Empty array of expected items means the parser is not smart enough to remember what it
-    /// could have accepted instead.
+    /// could have accepted instead. Where the line is long, like for invalid escape sequences,
+    /// the unexpected token includes the column.
     Parse(usize, Option<String>, Vec<&'static str>),
+    /// Number of plural forms of a message does not match the catalogue plural rule.
+    ///
+    /// Parameters are line number, number of forms given by the rule and number of forms found.
+    PluralForms(usize, usize, usize),
+    /// The catalogue cannot be decoded to text.
+    ///
+    /// Parameters are line number and name of the encoding. The encoding may also be one not
+    /// supported at all.
+    Decode(usize, String),
+    /// The catalogue is not well-formed XML.
+    ///
+    /// Parameters are line number and the message from the XML parser.
+    Xml(usize, String),
+    /// A unit cannot be represented in the output format.
+    ///
+    /// Parameter describes what could not be written, e.g. a plural variant not used by the
+    /// catalogue plural rule.
+    Unsupported(String),
+    /// Writer methods called out of order.
+    ///
+    /// Parameter describes the offending call, e.g. writing the header after units or writing
+    /// anything after the catalogue was finished.
+    Sequence(&'static str),
+    /// A unit or catalogue would be constructed in invalid state.
+    ///
+    /// Parameter describes the violated invariant, e.g. plural message without Other variant or
+    /// duplicate unit in catalogue.
+    Invalid(&'static str),
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
-            &Error::Io(0, ref err) => err.fmt(f),
-            &Error::Io(line, ref err) => write!(f, "{} at line {}", err, line),
-            &Error::Parse(line, ref got, ref exp) => {
+        match *self {
+            Error::Io(0, ref err) => err.fmt(f),
+            Error::Io(line, ref err) => write!(f, "{} at line {}", err, line),
+            Error::Parse(line, ref got, ref exp) => {
                 write!(f, "Parse error at line {}", line)?;
                 if !exp.is_empty() {
                     let mut prefix = ", expected";
@@ -265,26 +645,98 @@ impl std::fmt::Display for Error {
                         prefix = " or";
                     }
                 }
-                if got.is_some() {
-                    write!(f, ", got ‘{}’", got.as_ref().unwrap())?;
+                if let Some(ref got) = *got {
+                    write!(f, ", got ‘{}’", got)?;
                 }
                 Ok(())
             }
+            Error::PluralForms(line, exp, got) =>
+                write!(f, "Wrong number of plural forms at line {}, expected {}, got {}", line, exp, got),
+            Error::Decode(line, ref enc) => write!(f, "Can't decode line {} as ‘{}’", line, enc),
+            Error::Xml(line, ref msg) => write!(f, "Malformed XML at line {}: {}", line, msg),
+            Error::Unsupported(ref what) => write!(f, "Can't write {}", what),
+            Error::Sequence(what) => write!(f, "Writer used out of order: {}", what),
+            Error::Invalid(what) => write!(f, "Invalid unit: {}", what),
         }
     }
 }
 
 impl std::error::Error for Error {
+    #[allow(deprecated)]
     fn description(&self) -> &str {
-        self.cause().map(std::error::Error::description).unwrap_or("parse error")
+        match *self {
+            Error::Io(_, ref err) => err.description(),
+            Error::Parse(..) => "parse error",
+            Error::PluralForms(..) => "wrong number of plural forms",
+            Error::Decode(..) => "decoding error",
+            Error::Xml(..) => "malformed XML",
+            Error::Unsupported(..) => "unsupported in output format",
+            Error::Sequence(..) => "writer used out of order",
+            Error::Invalid(..) => "invalid unit",
+        }
     }
 
-    fn cause(&self) -> Option<&std::error::Error> {
-        match self {
-            &Error::Io(_, ref err) => Some(err),
-            &Error::Parse(..) => None,
+    #[allow(deprecated)]
+    fn cause(&self) -> Option<&dyn std::error::Error> {
+        self.source()
+    }
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            Error::Io(_, ref err) => Some(err),
+            Error::Parse(..) | Error::PluralForms(..) | Error::Decode(..) | Error::Xml(..)
+                | Error::Unsupported(..) | Error::Sequence(..) | Error::Invalid(..) => None,
         }
     }
 }
 
 // Note: tests in each submodule
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use super::{Error, Flag, State, UnitBuilder};
+    use super::Count::*;
+    use super::Message::*;
+    use super::Origin::*;
+
+    #[test]
+    fn build_units() {
+        let mut plural = BTreeMap::new();
+        plural.insert(One, "%d file".to_owned());
+        let invalid = Plural(plural.clone());
+        assert!(matches!(UnitBuilder::new(Empty).build(), Err(Error::Invalid(_))));
+        assert!(matches!(UnitBuilder::new(invalid.clone()).build(), Err(Error::Invalid(_))));
+        assert!(matches!(UnitBuilder::new("x").target(invalid.clone()).build(), Err(Error::Invalid(_))));
+        plural.insert(Other, "%d files".to_owned());
+        assert!(matches!(UnitBuilder::new("x").target(Plural(plural.clone())).build(),
+                         Err(Error::Invalid(_))));
+        assert!(matches!(UnitBuilder::new(Plural(plural.clone())).target("y").build(),
+                         Err(Error::Invalid(_))));
+
+        let mut u1 = UnitBuilder::new(Plural(plural))
+            .context("dialog")
+            .note(Translator, "Check the plural")
+            .location("src/main.c:10")
+            .flag(Flag::Format("c".to_owned()))
+            .flag(Flag::Format("c".to_owned()))
+            .build().unwrap();
+        assert_eq!(State::Empty, u1.state());
+        assert_eq!(1, u1.flags().len());
+        assert!(u1.set_target(invalid).is_err());
+        assert!(u1.set_target(Singular("soubor".to_owned())).is_err());
+        assert!(u1.set_source(Empty).is_err());
+        let mut target = BTreeMap::new();
+        target.insert(One, "%d soubor".to_owned());
+        target.insert(Other, "%d souborů".to_owned());
+        u1.set_target(Plural(target)).unwrap();
+        assert!(u1.set_source(Singular("file".to_owned())).is_err());
+        u1.set_state(State::NeedsWork);
+        assert_eq!(State::NeedsWork, u1.state());
+
+        let u2 = UnitBuilder::new("Old").target("Starý").prev_source("Older").obsolete(true)
+            .build().unwrap();
+        assert_eq!(State::Final, u2.state());
+        assert!(u2.is_obsolete());
+    }
+}
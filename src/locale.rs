@@ -0,0 +1,261 @@
+//! Discovery of installed catalogues and language negotiation
+//!
+//! [`Locales`] scans a directory for catalogues of one domain, in either of the layouts gettext
+//! projects use, and selects catalogues for the user's [`Locale`] preferences. The selected
+//! catalogues are loaded into a translator [`Chain`], which falls back from the most specific
+//! language to the more generic ones and finally to the source strings, e.g. `cs-CZ` → `cs` →
+//! source.
+//!
+//! [`Locales`]: struct.Locales.html
+//! [`Locale`]: ../../locale_config/struct.Locale.html
+//! [`Chain`]: ../translator/struct.Chain.html
+
+use locale_config::{LanguageRange, Locale};
+use mo::MoReader;
+use po::{self, PoReader};
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use translator::{Chain, Translator};
+use super::Error;
+
+/// Layout of the catalogue directory.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub enum Layout {
+    /// Installed catalogues, `<lang>/LC_MESSAGES/<domain>.mo` (or `.po`) in the `locale`
+    /// directory. The parameter is the domain.
+    ///
+    /// The language directories use the Unix locale names, like `cs_CZ` or `sr@latin`. If both
+    /// MO and PO catalogue exist, the MO one is used.
+    Gettext(String),
+    /// Source catalogues, `<lang>.po` in the `po` directory.
+    Po,
+}
+
+/// Catalogues available in a directory.
+#[derive(Clone,Debug)]
+pub struct Locales {
+    _layout: Layout,
+    _catalogues: Vec<(LanguageRange<'static>, PathBuf)>,
+}
+
+impl Locales {
+    /// Find catalogues in directory `dir` laid out according to `layout`.
+    ///
+    /// Entries that don't fit the layout, or whose name is not a language tag, are ignored. Fails
+    /// only if the directory cannot be read.
+    pub fn scan<P: AsRef<Path>>(dir: P, layout: Layout) -> Result<Self, Error> {
+        Self::scan_files(dir.as_ref(), layout, &["mo", "po"])
+    }
+
+    // Scan using the first of `exts` that exists in the gettext layout. The other layout only has
+    // PO files.
+    pub(crate) fn scan_files(dir: &Path, layout: Layout, exts: &[&str]) -> Result<Self, Error> {
+        let mut catalogues = Vec::new();
+        for entry in fs::read_dir(dir).map_err(|e| Error::Io(0, e))? {
+            let path = entry.map_err(|e| Error::Io(0, e))?.path();
+            let (name, file) = match layout {
+                Layout::Gettext(ref domain) => {
+                    let base = path.join("LC_MESSAGES");
+                    let file = exts.iter()
+                        .map(|ext| base.join(format!("{}.{}", domain, ext)))
+                        .find(|f| f.is_file());
+                    (path.file_name().and_then(|n| n.to_str()).map(str::to_owned), file)
+                }
+                Layout::Po => {
+                    let is_po = path.extension().is_some_and(|e| e == "po") && path.is_file();
+                    (path.file_stem().and_then(|n| n.to_str()).map(str::to_owned),
+                     Some(path).filter(|_| is_po))
+                }
+            };
+            if let (Some(name), Some(file)) = (name, file) {
+                let lang = po::parse_language(&name);
+                if lang != LanguageRange::invariant() {
+                    catalogues.push((lang, file));
+                }
+            }
+        }
+        catalogues.sort_by(|a, b| a.0.as_ref().cmp(b.0.as_ref()).then_with(|| a.1.cmp(&b.1)));
+        catalogues.dedup_by(|a, b| a.0 == b.0);
+        Ok(Locales { _layout: layout, _catalogues: catalogues })
+    }
+
+    pub fn layout(&self) -> &Layout { &self._layout }
+
+    /// Available catalogues with their languages, sorted by language.
+    pub fn catalogues(&self) -> &[(LanguageRange<'static>, PathBuf)] { &self._catalogues }
+
+    /// Get the catalogue for exactly this language.
+    pub fn get(&self, lang: &LanguageRange) -> Option<&Path> {
+        self._catalogues.iter().find(|c| c.0.as_ref() == lang.as_ref()).map(|c| c.1.as_path())
+    }
+
+    /// Select catalogues for the `messages` category of `locale`, in order of preference.
+    ///
+    /// Each language in the preference list is looked up together with its [`fallbacks`], skipping
+    /// catalogues already selected. The invariant language stands for the source strings, so the
+    /// languages after it are not used.
+    ///
+    /// [`fallbacks`]: fn.fallbacks.html
+    pub fn negotiate(&self, locale: &Locale) -> Vec<(&LanguageRange<'static>, &Path)> {
+        let mut res: Vec<(&LanguageRange<'static>, &Path)> = Vec::new();
+        for tag in locale.tags_for("messages") {
+            if tag == LanguageRange::invariant() {
+                break;
+            }
+            for lang in fallbacks(&tag) {
+                let found = self._catalogues.iter().find(|c| c.0 == lang);
+                if let Some((l, p)) = found {
+                    if !res.iter().any(|r| r.0 == l) {
+                        res.push((l, p.as_path()));
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    /// Load the catalogues selected by [`negotiate`] into a translator chain.
+    ///
+    /// [`negotiate`]: #method.negotiate
+    pub fn load(&self, locale: &Locale) -> Result<Chain, Error> {
+        let mut chain = Chain::default();
+        for (_, path) in self.negotiate(locale) {
+            chain.push(load_file(path)?);
+        }
+        Ok(chain)
+    }
+
+    /// Load the catalogues for the current locale of the thread.
+    pub fn load_current(&self) -> Result<Chain, Error> {
+        self.load(&Locale::current())
+    }
+}
+
+/// Language ranges to try for `lang`, from the most specific.
+///
+/// Like the lookup in [RFC 4647], subtags are removed from the end, together with a single
+/// character subtag that would be left last, so `sr-Latn-RS` gives `sr-Latn-RS`, `sr-Latn` and
+/// `sr`. Invariant language has no fallbacks.
+///
+/// [RFC 4647]: https://www.rfc-editor.org/rfc/rfc4647.txt
+pub fn fallbacks(lang: &LanguageRange) -> Vec<LanguageRange<'static>> {
+    let mut res = Vec::new();
+    let mut tag = lang.as_ref();
+    while !tag.is_empty() {
+        res.push(LanguageRange::new(tag).map_or_else(|_| LanguageRange::invariant(),
+                                                     LanguageRange::into_static));
+        tag = tag.rfind('-').map_or("", |i| &tag[..i]);
+        while tag.len() > 1 && tag.as_bytes()[tag.len() - 2] == b'-' {
+            tag = &tag[..(tag.len() - 2)];
+        }
+    }
+    res
+}
+
+/// Load translator from a PO or MO file, according to its extension.
+pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Translator, Error> {
+    let path = path.as_ref();
+    let file = BufReader::new(File::open(path).map_err(|e| Error::Io(0, e))?);
+    if path.extension().is_some_and(|e| e == "mo") {
+        Translator::from_reader(MoReader::new(file))
+    } else {
+        Translator::from_reader(PoReader::new(file))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::locale_config::{LanguageRange, Locale};
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use super::{fallbacks, Layout, Locales};
+
+    fn po(lang: &str, open: &str) -> String {
+        format!("msgid \"\"\nmsgstr \"\"\n\"Language: {}\\n\"\n\nmsgid \"Open\"\nmsgstr \"{}\"\n",
+                lang, open)
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("translate-storage-{}-{}", name, ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn fallback_tags() {
+        let tags = |s| fallbacks(&LanguageRange::new(s).unwrap()).iter()
+            .map(|l| l.as_ref().to_owned()).collect::<Vec<_>>();
+        assert_eq!(vec!["cs-CZ", "cs"], tags("cs-CZ"));
+        assert_eq!(vec!["sr-Latn-RS", "sr-Latn", "sr"], tags("sr-Latn-RS"));
+        assert_eq!(vec!["de-DE-u-va-posix", "de-DE-u-va", "de-DE", "de"], tags("de-DE-u-va-posix"));
+        assert!(tags("").is_empty());
+    }
+
+    #[test]
+    fn scan_gettext() {
+        let dir = temp_dir("gettext");
+        for &(lang, ext, open) in &[("cs", "po", "Otevřít"), ("cs_CZ", "po", "Otevřít!"),
+                                     ("de", "po", "Öffnen"), ("sk", "po", "Otvoriť")] {
+            let path = dir.join(lang).join("LC_MESSAGES");
+            fs::create_dir_all(&path).unwrap();
+            fs::write(path.join("app").with_extension(ext), po(lang, open)).unwrap();
+        }
+        fs::create_dir_all(dir.join("fr").join("LC_MESSAGES")).unwrap();
+        fs::write(dir.join("README"), "not a catalogue").unwrap();
+
+        let locales = Locales::scan(&dir, Layout::Gettext("app".to_owned())).unwrap();
+        let langs: Vec<_> = locales.catalogues().iter().map(|c| c.0.as_ref()).collect();
+        assert_eq!(vec!["cs", "cs-CZ", "de", "sk"], langs);
+        assert_eq!(Some(dir.join("de/LC_MESSAGES/app.po").as_path()),
+                   locales.get(&LanguageRange::new("de").unwrap()));
+
+        let mut locale = Locale::new("cs-CZ,fr,sk").unwrap();
+        locale.add(&LanguageRange::invariant());
+        locale.add(&LanguageRange::new("de").unwrap());
+        let selected: Vec<_> = locales.negotiate(&locale).iter().map(|c| c.0.as_ref()).collect();
+        assert_eq!(vec!["cs-CZ", "cs", "sk"], selected);
+        let chain = locales.load(&locale).unwrap();
+        assert_eq!(3, chain.translators().len());
+        assert_eq!("Otevřít!", chain.gettext("Open"));
+        assert_eq!("Close", chain.gettext("Close"));
+
+        let chain = locales.load(&Locale::new("cs,messages=de").unwrap()).unwrap();
+        assert_eq!("Öffnen", chain.gettext("Open"));
+        assert!(locales.load(&Locale::new("en-US").unwrap()).unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn scan_gettext_dotted_domain() {
+        let dir = temp_dir("gettext-dotted");
+        for &(lang, file) in &[("pl", "org.example.app.po"), ("it", "org.example.po")] {
+            let path = dir.join(lang).join("LC_MESSAGES");
+            fs::create_dir_all(&path).unwrap();
+            fs::write(path.join(file), po(lang, "Open")).unwrap();
+        }
+        let locales = Locales::scan(&dir, Layout::Gettext("org.example.app".to_owned())).unwrap();
+        assert_eq!(vec![(LanguageRange::new("pl").unwrap(),
+                         dir.join("pl/LC_MESSAGES/org.example.app.po"))],
+                   locales.catalogues());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn scan_po() {
+        let dir = temp_dir("po");
+        fs::write(dir.join("pt_BR.po"), po("pt_BR", "Abrir")).unwrap();
+        fs::write(dir.join("app.pot"), po("", "")).unwrap();
+        fs::write(dir.join("LINGUAS"), "pt_BR\n").unwrap();
+
+        let locales = Locales::scan(&dir, Layout::Po).unwrap();
+        let langs: Vec<_> = locales.catalogues().iter().map(|c| c.0.as_ref()).collect();
+        assert_eq!(vec!["pt-BR"], langs);
+        assert!(locales.negotiate(&Locale::new("pt").unwrap()).is_empty());
+        let chain = locales.load(&Locale::new("pt-BR-x-test").unwrap()).unwrap();
+        assert_eq!("Abrir", chain.gettext("Open"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,244 @@
+//! Updating translations from a new template
+//!
+//! When the sources change, the template (`.pot`) is regenerated and the translated catalogues
+//! have to be updated to it, like [`msgmerge`][msgmerge] does. The result has the units of the
+//! template, in its order, with translations taken from the translated catalogue:
+//!
+//!  - Units with the same context and source keep their translation and state.
+//!  - Units with no exact match get translation of the most similar source (see the [`fuzzy`]
+//!    module), marked as needing work and with the original context and source recorded as
+//!    previous ones, so the translator can see what changed.
+//!  - Translated units that were not used become obsolete.
+//!
+//! Comments of developers, locations and flags come from the template, comments of translators
+//! from the translated catalogue.
+//!
+//! [`fuzzy`]: ../fuzzy/index.html
+//! [msgmerge]: https://www.gnu.org/software/gettext/manual/html_node/msgmerge-Invocation.html
+
+use catalogue::Catalogue;
+use fuzzy::{FuzzyIndex, DEFAULT_THRESHOLD};
+use super::{Count, Error, Message, Origin, State, Unit};
+
+/// Update translated catalogue to a new template.
+///
+/// Metadata and the rest of the header entry, like its state, are taken from the translated
+/// catalogue, except `POT-Creation-Date`, which comes from the template. The plural rule of the
+/// translated catalogue is needed when a message changed between singular and plural, and an
+/// error is returned if it is invalid.
+pub fn merge(translated: &Catalogue, template: &Catalogue) -> Result<Catalogue, Error> {
+    let plurals = translated.header().plural_forms()?.unwrap_or_default()
+        .categories(translated.target_language());
+
+    let mut res = translated.without_units();
+    if let Some(date) = template.header().pot_creation_date() {
+        res.header_mut().set_pot_creation_date(&date);
+    }
+
+    let old: Vec<&Unit> = translated.iter().collect();
+    let mut used = vec![false; old.len()];
+    let candidates: Vec<usize> = (0..old.len()).filter(|&i| !old[i].target().is_blank()).collect();
+    let index = FuzzyIndex::from_units(candidates.iter().map(|&i| old[i]), DEFAULT_THRESHOLD);
+    for unit in template.active() {
+        let context = unit.context().as_ref().map(String::as_str);
+        let found = translated.position(context, unit.source().key())
+            .map(|i| (i, true))
+            .or_else(|| index.best(unit.source().key()).map(|m| (candidates[m.0], false)));
+        let merged = match found {
+            Some((i, exact)) => {
+                used[i] = true;
+                merge_unit(unit, old[i], exact, &plurals)
+            }
+            None => Unit {
+                _target: Message::Empty,
+                _prev_context: None,
+                _prev_source: Message::Empty,
+                _notes: developer_notes(unit).collect(),
+                _state: State::Empty,
+                ..unit.clone()
+            },
+        };
+        res.insert(merged);
+    }
+
+    for (i, unit) in old.iter().enumerate() {
+        if !used[i] && !unit.target().is_blank() && !res.contains(
+                unit.context().as_ref().map(String::as_str), unit.source().key()) {
+            res.insert(Unit { _obsolete: true, ..(*unit).clone() });
+        }
+    }
+    Ok(res)
+}
+
+fn developer_notes(unit: &Unit) -> impl Iterator<Item = (Origin, String)> + '_ {
+    unit.notes().iter().filter(|n| n.0 == Origin::Developer).cloned()
+}
+
+fn merge_unit(new: &Unit, old: &Unit, exact: bool, plurals: &[Count]) -> Unit {
+    let mut unit = Unit {
+        _target: old.target().clone(),
+        _prev_context: None,
+        _prev_source: Message::Empty,
+        _notes: old.notes().iter()
+            .filter(|n| n.0 != Origin::Developer)
+            .cloned()
+            .chain(developer_notes(new))
+            .collect(),
+        _state: old.state(),
+        _obsolete: false,
+        ..new.clone()
+    };
+    if exact && old.state() == State::NeedsWork {
+        // Keep what the translator has not reviewed yet.
+        unit._prev_context = old.prev_context().clone();
+        unit._prev_source = old.prev_source().clone();
+    }
+    if !exact || new.source() != old.source() {
+        unit._prev_context = old.context().clone();
+        unit._prev_source = old.source().clone();
+        unit._state = State::NeedsWork;
+    }
+    match (new.source().is_plural(), unit._target.clone()) {
+        (true, Message::Singular(s)) => {
+            // Like msgmerge, use the singular translation for all the variants.
+            unit._target = Message::Plural(plurals.iter().map(|&c| (c, s.clone())).collect());
+            unit._state = State::NeedsWork;
+        }
+        (false, Message::Plural(map)) => {
+            let s = map.get(&plurals[0]).or_else(|| map.values().next()).cloned().unwrap_or_default();
+            unit._target = Message::Singular(s);
+            unit._state = State::NeedsWork;
+        }
+        _ => (),
+    }
+    if unit._target.is_blank() {
+        unit._state = State::Empty;
+    }
+    unit
+}
+
+#[cfg(test)]
+mod tests {
+    use ::catalogue::Catalogue;
+    use ::po::{PoReader, PoWriter};
+    use super::merge;
+
+    static TRANSLATED_PO: &str = r###"msgid ""
+msgstr ""
+"Project-Id-Version: translate-storage test\n"
+"POT-Creation-Date: 2017-04-20 10:00+0200\n"
+"Language: cs\n"
+"MIME-Version: 1.0\n"
+"Content-Type: text/plain; charset=UTF-8\n"
+"Content-Transfer-Encoding: 8bit\n"
+"Plural-Forms: nplurals=3; plural=(n==1) ? 0 : (n>=2 && n<=4) ? 1 : 2;\n"
+
+# Keep it short
+#. Menu item
+#: src/menu.c:10
+msgid "Open"
+msgstr "Otevřít"
+
+#: src/main.c:20
+#, c-format
+msgid "Cannot open file %s"
+msgstr "Nelze otevřít soubor %s"
+
+msgid "%d file"
+msgstr "%d soubor"
+
+msgid "Removed"
+msgstr "Odstraněno"
+
+msgid "Untranslated"
+msgstr ""
+"###;
+
+    static TEMPLATE_POT: &str = r###"msgid ""
+msgstr ""
+"Project-Id-Version: PACKAGE VERSION\n"
+"POT-Creation-Date: 2017-05-01 12:00+0200\n"
+"MIME-Version: 1.0\n"
+"Content-Type: text/plain; charset=CHARSET\n"
+"Content-Transfer-Encoding: 8bit\n"
+
+#. Open menu item
+#: src/menu.c:12
+msgid "Open"
+msgstr ""
+
+#: src/main.c:25
+#, c-format
+msgid "Cannot open the file %s"
+msgstr ""
+
+#: src/list.c:5
+msgid "%d file"
+msgid_plural "%d files"
+msgstr[0] ""
+msgstr[1] ""
+
+msgid "Brand new"
+msgstr ""
+"###;
+
+    static MERGED_PO: &str = r###"msgid ""
+msgstr ""
+"Project-Id-Version: translate-storage test\n"
+"POT-Creation-Date: 2017-05-01 12:00+0200\n"
+"Language: cs\n"
+"MIME-Version: 1.0\n"
+"Content-Type: text/plain; charset=UTF-8\n"
+"Content-Transfer-Encoding: 8bit\n"
+"Plural-Forms: nplurals=3; plural=(n==1) ? 0 : (n>=2 && n<=4) ? 1 : 2;\n"
+
+# Keep it short
+#. Open menu item
+#: src/menu.c:12
+msgid "Open"
+msgstr "Otevřít"
+
+#: src/main.c:25
+#, fuzzy, c-format
+#| msgid "Cannot open file %s"
+msgid "Cannot open the file %s"
+msgstr "Nelze otevřít soubor %s"
+
+#: src/list.c:5
+#, fuzzy
+#| msgid "%d file"
+msgid "%d file"
+msgid_plural "%d files"
+msgstr[0] "%d soubor"
+msgstr[1] "%d soubor"
+msgstr[2] "%d soubor"
+
+msgid "Brand new"
+msgstr ""
+
+#~ msgid "Removed"
+#~ msgstr "Odstraněno"
+"###;
+
+    #[test]
+    fn merge_template() {
+        let translated = Catalogue::from_reader(PoReader::new(TRANSLATED_PO.as_bytes())).unwrap();
+        let template = Catalogue::from_reader(PoReader::new(TEMPLATE_POT.as_bytes())).unwrap();
+        let merged = merge(&translated, &template).unwrap();
+        let mut writer = PoWriter::new(Vec::new());
+        merged.write_to(&mut writer).unwrap();
+        assert_eq!(MERGED_PO, String::from_utf8(writer.into_inner()).unwrap());
+    }
+
+    #[test]
+    fn merge_fuzzy_header() {
+        let po = format!("# Translator notice\n#, fuzzy\n{}", TRANSLATED_PO);
+        let translated = Catalogue::from_reader(PoReader::new(po.as_bytes())).unwrap();
+        let template = Catalogue::from_reader(PoReader::new(TEMPLATE_POT.as_bytes())).unwrap();
+        let merged = merge(&translated, &template).unwrap();
+        let mut writer = PoWriter::new(Vec::new());
+        merged.write_to(&mut writer).unwrap();
+        let out = String::from_utf8(writer.into_inner()).unwrap();
+        assert!(out.starts_with("# Translator notice\n#, fuzzy\nmsgid \"\"\n"), "{}", out);
+    }
+}
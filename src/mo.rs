@@ -0,0 +1,711 @@
+//! Handling of [GNU Machine Objects][MO]
+//!
+//! This is the binary format [gettext] compiles catalogues to for use at runtime. It only
+//! contains the context, source and target of translated units, so notes, locations and other
+//! metadata are lost when compiling.
+//!
+//! [MO]: https://www.gnu.org/software/gettext/manual/html_node/MO-Files.html
+//! [gettext]: https://www.gnu.org/software/gettext/
+
+use encoding_rs::{Encoding,UTF_8};
+use locale_config::LanguageRange;
+use regex::bytes::Regex as BytesRegex;
+use std::collections::BTreeMap;
+use std::io::{Read,Write};
+use super::{CatalogueReader,CatalogueWriter,Count,Error,Message,State,Unit};
+use super::plural::PluralRule;
+use super::po::{header_fields,header_text,parse_language,PoHeader};
+
+lazy_static!{
+    static ref CHARSET_RE: BytesRegex = BytesRegex::new(
+        r#"(?m)^Content-Type:[^\n]*charset=([-_.:A-Za-z0-9]+)"#).unwrap();
+}
+
+const MAGIC: u32 = 0x950412de;
+
+// Layout of the file header, as offsets of the fields.
+const REVISION: usize = 4;
+const COUNT: usize = 8;
+const ORIG_TABLE: usize = 12;
+const TRANS_TABLE: usize = 16;
+const HEADER_SIZE: usize = 28;
+
+/// Reader for MO files.
+///
+/// Both little- and big-endian files are read. The strings are decoded according to the charset
+/// given in the header entry, whose `Language` and `Plural-Forms` fields also determine the
+/// target language and plural variants.
+///
+/// As there are no lines, errors report the number of the message (counting from 1) instead.
+pub struct MoReader {
+    _data: Vec<u8>,
+    _big_endian: bool,
+    _count: usize,
+    _next: usize,
+    _header_index: Option<usize>,
+    _failed: Option<Error>,
+    _encoding: &'static Encoding,
+    _header_unit: Option<Unit>,
+    _header: PoHeader,
+    _target_language: LanguageRange<'static>,
+    _plural_rule: PluralRule,
+    _plurals: Vec<Count>,
+}
+
+impl MoReader {
+    pub fn new<R: Read>(mut reader: R) -> Self {
+        let mut res = MoReader {
+            _data: Vec::new(),
+            _big_endian: false,
+            _count: 0,
+            _next: 0,
+            _header_index: None,
+            _failed: None,
+            _encoding: UTF_8,
+            _header_unit: None,
+            _header: PoHeader::default(),
+            _target_language: LanguageRange::invariant(),
+            _plural_rule: PluralRule::default(),
+            _plurals: PluralRule::default().categories(&LanguageRange::invariant()),
+        };
+        if let Err(e) = reader.read_to_end(&mut res._data).map_err(|e| Error::Io(0, e))
+                .and_then(|_| res.parse_header()) {
+            res._failed = Some(e);
+            res._count = 0;
+        }
+        res
+    }
+
+    /// Get the plural rule from the `Plural-Forms` field of the header entry.
+    pub fn plural_rule(&self) -> &PluralRule {
+        &self._plural_rule
+    }
+
+    fn u32_at(&self, pos: usize) -> Option<u32> {
+        let b = self._data.get(pos..(pos + 4))?;
+        let b = [b[0], b[1], b[2], b[3]];
+        Some(if self._big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) })
+    }
+
+    // The `i`-th string from the table at given offset of the header.
+    fn string(&self, table: usize, i: usize) -> Result<&[u8], Error> {
+        let entry = self.u32_at(table).map(|t| t as usize + 8 * i);
+        entry.and_then(|e| Some((self.u32_at(e)? as usize, self.u32_at(e + 4)? as usize)))
+            .and_then(|(len, off)| self._data.get(off..off.checked_add(len)?))
+            .ok_or_else(|| Error::Parse(i + 1, None, vec![]))
+    }
+
+    fn decode(&self, i: usize, bytes: &[u8]) -> Result<String, Error> {
+        self._encoding.decode_without_bom_handling_and_without_replacement(bytes)
+            .map(|s| s.into_owned())
+            .ok_or_else(|| Error::Decode(i + 1, self._encoding.name().to_owned()))
+    }
+
+    fn parse_header(&mut self) -> Result<(), Error> {
+        self._big_endian = match self._data.get(0..4) {
+            Some(m) if *m == MAGIC.to_le_bytes() => false,
+            Some(m) if *m == MAGIC.to_be_bytes() => true,
+            _ => return Err(Error::Parse(0, None, vec!["MO magic number"])),
+        };
+        match self.u32_at(REVISION) {
+            Some(r) if r >> 16 <= 1 => (),
+            r => {
+                let got = r.map(|r| format!("revision {}.{}", r >> 16, r & 0xFFFF));
+                return Err(Error::Parse(0, got, vec!["revision 0 or 1"]));
+            }
+        }
+        self._count = self.u32_at(COUNT)
+            .ok_or_else(|| Error::Parse(0, None, vec!["message count"]))? as usize;
+
+        self._header_index = (0..self._count)
+            .find(|&i| self.string(ORIG_TABLE, i).ok() == Some(&b""[..]));
+        let i = match self._header_index {
+            Some(i) => i,
+            None => return Ok(()),
+        };
+        let raw = self.string(TRANS_TABLE, i)?.to_vec();
+        if let Some(c) = CHARSET_RE.captures(&raw) {
+            if &c[1] != b"CHARSET" {
+                let name = || String::from_utf8_lossy(&c[1]).into_owned();
+                self._encoding = Encoding::for_label(&c[1]).ok_or_else(|| Error::Decode(i + 1, name()))?;
+            }
+        }
+        let text = self.decode(i, &raw)?;
+        for (key, val) in header_fields(&text) {
+            match key {
+                "Language" => self._target_language = parse_language(val),
+                "Plural-Forms" => self._plural_rule = PluralRule::parse(val).map_err(|e| match e {
+                    Error::Parse(_, got, exp) => Error::Parse(i + 1, got, exp),
+                    e => e,
+                })?,
+                _ => (),
+            }
+        }
+        self._plurals = self._plural_rule.categories(&self._target_language);
+        let unit = Unit {
+            _source: Message::Singular(String::new()),
+            _target: Message::Singular(text),
+            _state: State::Final,
+            ..Unit::default()
+        };
+        self._header = PoHeader::from_unit(&unit);
+        self._header_unit = Some(unit);
+        Ok(())
+    }
+
+    fn read_unit(&self, i: usize) -> Result<Unit, Error> {
+        let orig = self.string(ORIG_TABLE, i)?;
+        let trans = self.string(TRANS_TABLE, i)?;
+        let mut unit = Unit::default();
+
+        let source = match orig.iter().position(|&b| b == 4) {
+            Some(n) => {
+                unit._context = Some(self.decode(i, &orig[..n])?);
+                &orig[(n + 1)..]
+            }
+            None => orig,
+        };
+        let mut source = source.split(|&b| b == 0);
+        let msgid = self.decode(i, source.next().unwrap())?;
+        unit._source = match source.next() {
+            Some(plural) => {
+                let mut map = BTreeMap::new();
+                map.insert(Count::One, msgid);
+                map.insert(Count::Other, self.decode(i, plural)?);
+                let forms = trans.split(|&b| b == 0)
+                    .map(|f| self.decode(i, f))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if forms.len() != self._plurals.len() {
+                    return Err(Error::PluralForms(i + 1, self._plurals.len(), forms.len()));
+                }
+                unit._target = Message::Plural(self._plurals.iter().cloned().zip(forms).collect());
+                Message::Plural(map)
+            }
+            None => {
+                unit._target = Message::Singular(self.decode(i, trans)?);
+                Message::Singular(msgid)
+            }
+        };
+        unit._state = if unit._target.is_blank() { State::Empty } else { State::Final };
+        Ok(unit)
+    }
+}
+
+impl Iterator for MoReader {
+    type Item = Result<Unit, Error>;
+    fn next(&mut self) -> Option<Result<Unit, Error>> {
+        if let Some(e) = self._failed.take() {
+            return Some(Err(e));
+        }
+        if Some(self._next) == self._header_index {
+            self._next += 1;
+        }
+        if self._next >= self._count {
+            return None;
+        }
+        self._next += 1;
+        Some(self.read_unit(self._next - 1))
+    }
+}
+
+impl CatalogueReader for MoReader {
+    fn target_language(&self) -> &LanguageRange<'static> {
+        &self._target_language
+    }
+
+    /// Get the header entry, if the catalogue has one.
+    ///
+    /// The header is the entry with empty source. It is not returned by the iterator.
+    fn header_unit(&self) -> Option<&Unit> {
+        self._header_unit.as_ref()
+    }
+
+    fn properties(&self) -> Vec<(&str, &str)> {
+        self._header.fields().collect()
+    }
+}
+
+/// Writer for MO files.
+///
+/// Produces the same output as `msgfmt`: little-endian file with the messages sorted and a hash
+/// table for lookup by the gettext runtime. As the messages must be sorted, nothing is written
+/// until `finish` is called.
+///
+/// Only final units are written by default, like `msgfmt` does; units that need work and
+/// untranslated units can be included with `set_use_fuzzy` and `set_use_untranslated`. Obsolete
+/// units are always skipped. The plural variants are ordered according to the header entry,
+/// which should therefore be written first.
+pub struct MoWriter<W: Write> {
+    _writer: W,
+    // Keyed by context and singular source, which is what the strings are sorted and hashed by.
+    _messages: BTreeMap<Vec<u8>, (Vec<u8>, Vec<u8>)>,
+    _plurals: Vec<Count>,
+    _use_fuzzy: bool,
+    _use_untranslated: bool,
+    _finished: bool,
+}
+
+impl<W: Write> MoWriter<W> {
+    pub fn new(writer: W) -> Self {
+        MoWriter {
+            _writer: writer,
+            _messages: BTreeMap::new(),
+            _plurals: PluralRule::default().categories(&LanguageRange::invariant()),
+            _use_fuzzy: false,
+            _use_untranslated: false,
+            _finished: false,
+        }
+    }
+
+    /// Include units that need work (fuzzy). Like `msgfmt --use-fuzzy`.
+    pub fn set_use_fuzzy(&mut self, use_fuzzy: bool) {
+        self._use_fuzzy = use_fuzzy;
+    }
+
+    /// Include units without translation. Their target is written as empty string(s).
+    pub fn set_use_untranslated(&mut self, use_untranslated: bool) {
+        self._use_untranslated = use_untranslated;
+    }
+
+    /// Get the wrapped writer back.
+    pub fn into_inner(self) -> W {
+        self._writer
+    }
+
+    fn add(&mut self, unit: &Unit) -> Result<(), Error> {
+        fn bytes(s: &str) -> Result<&[u8], Error> {
+            if s.contains('\0') {
+                return Err(Error::Unsupported(format!("NUL character in message {:?}", s)));
+            }
+            Ok(s.as_bytes())
+        }
+
+        let mut key = Vec::new();
+        if let Some(ref ctx) = *unit.context() {
+            key.extend_from_slice(bytes(ctx)?);
+            key.push(4);
+        }
+        let mut orig = Vec::new();
+        let plural = match *unit.source() {
+            Message::Plural(ref map) => {
+                let one = map.get(&Count::One).or_else(|| map.values().next());
+                let other = map.get(&Count::Other).or_else(|| map.values().last());
+                key.extend_from_slice(bytes(one.map_or("", String::as_str))?);
+                orig.push(0);
+                orig.extend_from_slice(bytes(other.map_or("", String::as_str))?);
+                true
+            }
+            ref source => {
+                key.extend_from_slice(bytes(source.singular().unwrap_or(""))?);
+                false
+            }
+        };
+
+        let mut target = Vec::new();
+        match *unit.target() {
+            Message::Plural(ref map) => {
+                if let Some(c) = map.keys().find(|c| !self._plurals.contains(c)) {
+                    return Err(Error::Unsupported(
+                            format!("plural form {:?} not used by the catalogue plural rule", c)));
+                }
+                for (i, c) in self._plurals.iter().enumerate() {
+                    if i > 0 {
+                        target.push(0);
+                    }
+                    target.extend_from_slice(bytes(map.get(c).map_or("", String::as_str))?);
+                }
+            }
+            Message::Singular(ref s) => target.extend_from_slice(bytes(s)?),
+            Message::Empty if plural => target.resize(self._plurals.len() - 1, 0),
+            Message::Empty => (),
+        }
+
+        if self._messages.contains_key(&key) {
+            return Err(Error::Unsupported(
+                    format!("duplicate message {:?}", String::from_utf8_lossy(&key))));
+        }
+        orig.splice(0..0, key.iter().cloned());
+        self._messages.insert(key, (orig, target));
+        Ok(())
+    }
+
+    fn write_mo(&mut self) -> Result<(), Error> {
+        let count = self._messages.len();
+        let hash_size = if count == 0 { 0 } else { next_prime(count * 4 / 3) };
+        let mut hash_table = vec![0u32; hash_size];
+        for (n, key) in self._messages.keys().enumerate() {
+            let hash = hashpjw(key) as usize;
+            let mut idx = hash % hash_size;
+            if hash_table[idx] != 0 {
+                let incr = 1 + hash % (hash_size - 2);
+                while hash_table[idx] != 0 {
+                    idx = if idx >= hash_size - incr { idx - (hash_size - incr) } else { idx + incr };
+                }
+            }
+            hash_table[idx] = n as u32 + 1;
+        }
+
+        let hash_offset = HEADER_SIZE + 16 * count;
+        let mut offset = hash_offset + 4 * hash_size;
+        let mut res = Vec::new();
+        let trans_table = HEADER_SIZE + 8 * count;
+        for &n in &[MAGIC as usize, 0, count, HEADER_SIZE, trans_table, hash_size, hash_offset] {
+            res.extend_from_slice(&(n as u32).to_le_bytes());
+        }
+        let strings = || {
+            self._messages.values().map(|m| &m.0).chain(self._messages.values().map(|m| &m.1))
+        };
+        for s in strings() {
+            res.extend_from_slice(&(s.len() as u32).to_le_bytes());
+            res.extend_from_slice(&(offset as u32).to_le_bytes());
+            offset += s.len() + 1;
+        }
+        for h in hash_table {
+            res.extend_from_slice(&h.to_le_bytes());
+        }
+        for s in strings() {
+            res.extend_from_slice(s);
+            res.push(0);
+        }
+        self._writer.write_all(&res).map_err(|e| Error::Io(0, e))
+    }
+}
+
+impl<W: Write> CatalogueWriter for MoWriter<W> {
+    fn write_header(&mut self, target_language: &LanguageRange, header: Option<&Unit>)
+            -> Result<(), Error> {
+        if !self._messages.is_empty() {
+            return Err(Error::Sequence("header written after units"));
+        }
+        let text = header.and_then(|h| h.target().singular()).unwrap_or("");
+        let unit = Unit {
+            _source: Message::Singular(String::new()),
+            _target: Message::Singular(header_text(text, target_language)),
+            _state: State::Final,
+            ..Unit::default()
+        };
+        self.write_unit(&unit)
+    }
+
+    /// Add a unit to the catalogue.
+    ///
+    /// If the unit is the header entry, plural rule for the following units is taken from it. The
+    /// header entry is written even if it is fuzzy, like `msgfmt` does, and with the charset
+    /// changed to UTF-8 like by `write_header`.
+    fn write_unit(&mut self, unit: &Unit) -> Result<(), Error> {
+        if self._finished {
+            return Err(Error::Sequence("unit written after finish"));
+        }
+        let header = unit.source().is_singular() && unit.source().is_blank() && unit.context().is_none();
+        let wanted = match unit.state() {
+            _ if header => true,
+            State::Final => true,
+            State::NeedsWork => self._use_fuzzy,
+            State::Empty => self._use_untranslated,
+        };
+        if unit.is_obsolete() || !wanted {
+            return Ok(());
+        }
+        if header {
+            let mut lang = LanguageRange::invariant();
+            let mut rule = PluralRule::default();
+            for (key, val) in header_fields(unit.target().singular().unwrap_or("")) {
+                match key {
+                    "Language" => lang = parse_language(val),
+                    "Plural-Forms" => rule = PluralRule::parse(val)?,
+                    _ => (),
+                }
+            }
+            self._plurals = rule.categories(&lang);
+            let text = header_text(unit.target().singular().unwrap_or(""), &lang);
+            return self.add(&Unit { _target: Message::Singular(text), ..unit.clone() });
+        }
+        self.add(unit)
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        if !self._finished {
+            self._finished = true;
+            self.write_mo()?;
+        }
+        self._writer.flush().map_err(|e| Error::Io(0, e))
+    }
+}
+
+/// Convert MO catalogue back to editable units, like `msgunfmt`.
+///
+/// The header entry is passed to `write_header`, or a default header is generated for the target
+/// language if the catalogue lacks one, followed by all the units in the order they appear in the
+/// MO file. Any `CatalogueWriter` can be used, but `PoWriter` gives the same output as
+/// `msgunfmt`. The writer is not finished, so more units can be added.
+pub fn decompile<R: Read, W: CatalogueWriter>(mo: R, writer: &mut W) -> Result<(), Error> {
+    let mut reader = MoReader::new(mo);
+    if let Some(e) = reader._failed.take() {
+        return Err(e);
+    }
+    writer.write_header(reader.target_language(), reader.header_unit())?;
+    for unit in reader {
+        writer.write_unit(&unit?)?;
+    }
+    Ok(())
+}
+
+// The hash function used by gettext for the lookup table.
+fn hashpjw(s: &[u8]) -> u32 {
+    let mut hash: u32 = 0;
+    for &b in s {
+        hash = (hash << 4).wrapping_add(b as u32);
+        let g = hash & 0xF000_0000;
+        if g != 0 {
+            hash ^= g >> 24;
+            hash ^= g;
+        }
+    }
+    hash
+}
+
+// Like in gettext, small primes are skipped, so the table is never shorter than 11 entries.
+fn next_prime(n: usize) -> usize {
+    let is_prime = |n: usize| (3..).step_by(2).take_while(|d| d * d <= n).all(|d| !n.is_multiple_of(d));
+    (n.max(10) | 1..).step_by(2).find(|&n| is_prime(n)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{CatalogueReader,CatalogueWriter,State,Unit};
+    use ::Count::*;
+    use ::locale_config::LanguageRange;
+    use ::Message::*;
+    use ::po::PoWriter;
+    use std::str;
+    use super::{decompile,MoReader,MoWriter};
+
+    // Build MO file with given strings, without hash table.
+    fn mo(entries: &[(&[u8], &[u8])], big_endian: bool) -> Vec<u8> {
+        let word = |n: usize| {
+            if big_endian { (n as u32).to_be_bytes() } else { (n as u32).to_le_bytes() }
+        };
+        let mut tables = Vec::new();
+        let mut strings = Vec::new();
+        let strings_start = 28 + 16 * entries.len();
+        for column in 0..2 {
+            for e in entries {
+                let s = if column == 0 { e.0 } else { e.1 };
+                tables.extend_from_slice(&word(s.len()));
+                tables.extend_from_slice(&word(strings_start + strings.len()));
+                strings.extend_from_slice(s);
+                strings.push(0);
+            }
+        }
+        let mut res = Vec::new();
+        for &n in &[0x950412de, 0, entries.len(), 28, 28 + 8 * entries.len(), 0, strings_start] {
+            res.extend_from_slice(&word(n));
+        }
+        res.extend(tables);
+        res.extend(strings);
+        res
+    }
+
+    #[test]
+    fn read_mo() {
+        let entries: &[(&[u8], &[u8])] = &[
+            (b"", b"Language: cs\nContent-Type: text/plain; charset=ISO-8859-2\n\
+                    Plural-Forms: nplurals=3; plural=(n==1) ? 0 : (n>=2 && n<=4) ? 1 : 2;\n"),
+            (b"%d file\0%d files", b"%d soubor\0%d soubory\0%d soubor\xF9"),
+            (b"Simple message", b"Jednoduch\xE1 zpr\xE1va"),
+            (b"menu\x04Open", b"Otev\xF8\xEDt"),
+        ];
+        for &big_endian in &[false, true] {
+            let mut reader = MoReader::new(&mo(entries, big_endian)[..]);
+            assert_eq!(LanguageRange::new("cs").unwrap(), *reader.target_language());
+            let header = reader.header_unit().unwrap().target().clone();
+            assert!(header.singular().unwrap().starts_with("Language: cs\n"));
+
+            let u1 = reader.next().unwrap().unwrap();
+            let plural = |s: &[(::Count, &str)]| {
+                Plural(s.iter().map(|&(c, s)| (c, s.to_owned())).collect())
+            };
+            assert_eq!(plural(&[(One, "%d file"), (Other, "%d files")]), *u1.source());
+            assert_eq!(plural(&[(One, "%d soubor"), (Few, "%d soubory"), (Other, "%d souborů")]),
+                       *u1.target());
+            assert_eq!(::State::Final, u1.state());
+
+            let u2 = reader.next().unwrap().unwrap();
+            assert_eq!(Singular("Jednoduchá zpráva".to_owned()), *u2.target());
+
+            let u3 = reader.next().unwrap().unwrap();
+            assert_eq!(Some("menu".to_owned()), *u3.context());
+            assert_eq!(Singular("Open".to_owned()), *u3.source());
+            assert_eq!(Singular("Otevřít".to_owned()), *u3.target());
+            assert!(reader.next().is_none());
+        }
+    }
+
+    #[test]
+    fn errors() {
+        match MoReader::new(&b"msgid \"\"\n"[..]).next() {
+            Some(Err(::Error::Parse(0, None, _))) => (),
+            x => panic!("Unexpected {:?}", x),
+        }
+
+        let mut truncated = mo(&[(b"", b"Language: de\n"), (b"One", b"Eins")], false);
+        truncated.truncate(truncated.len() - 3);
+        let mut reader = MoReader::new(&truncated[..]);
+        match reader.next() {
+            Some(Err(::Error::Parse(2, None, _))) => (),
+            x => panic!("Unexpected {:?}", x),
+        }
+        assert!(reader.next().is_none());
+
+        let mut reader = MoReader::new(&mo(&[(b"%d file\0%d files", b"%d Datei")], false)[..]);
+        match reader.next() {
+            Some(Err(::Error::PluralForms(1, 2, 1))) => (),
+            x => panic!("Unexpected {:?}", x),
+        }
+    }
+
+    #[test]
+    fn write_mo() {
+        let unit = |ctx: Option<&str>, source: &str, target: &str, state| Unit {
+            _context: ctx.map(str::to_owned),
+            _source: Singular(source.to_owned()),
+            _target: if target.is_empty() { Empty } else { Singular(target.to_owned()) },
+            _state: state,
+            ..Unit::default()
+        };
+        let header = unit(None, "", "Plural-Forms: nplurals=2; plural=(n != 1);\n", State::Final);
+        let units = vec![
+            unit(None, "Open", "Öffnen", State::Final),
+            unit(Some("menu"), "Open", "Öffnen…", State::Final),
+            unit(None, "Fuzzy", "Unscharf", State::NeedsWork),
+            unit(None, "Untranslated", "", State::Empty),
+            Unit {
+                _source: Plural(vec![(One, "%d dog".to_owned()), (Other, "%d dogs".to_owned())]
+                                .into_iter().collect()),
+                _target: Plural(vec![(One, "%d Hund".to_owned()), (Other, "%d Hunde".to_owned())]
+                                .into_iter().collect()),
+                _state: State::Final,
+                ..Unit::default()
+            },
+        ];
+
+        let write = |fuzzy, untranslated| {
+            let mut writer = MoWriter::new(Vec::new());
+            writer.set_use_fuzzy(fuzzy);
+            writer.set_use_untranslated(untranslated);
+            writer.write_header(&LanguageRange::new("de").unwrap(), Some(&header)).unwrap();
+            for u in &units {
+                writer.write_unit(u).unwrap();
+            }
+            writer.finish().unwrap();
+            writer.into_inner()
+        };
+
+        let mo = write(false, false);
+        // The hash table follows the two string tables, with the size of the next prime.
+        assert_eq!(&[11, 0, 0, 0, 28 + 16 * 4, 0, 0, 0], &mo[20..28]);
+        let mut reader = MoReader::new(&mo[..]);
+        assert_eq!(LanguageRange::new("de").unwrap(), *reader.target_language());
+        let read = (&mut reader).map(Result::unwrap).collect::<Vec<_>>();
+        let sources = read.iter().map(|u| u.source().singular().unwrap_or("%d dog"));
+        assert_eq!(vec!["%d dog", "Open", "Open"], sources.collect::<Vec<_>>());
+        assert_eq!(units[4].target(), read[0].target());
+        assert_eq!(Some("menu".to_owned()), *read[2].context());
+
+        let read = MoReader::new(&write(true, true)[..]).map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(5, read.len());
+        assert_eq!(State::Empty, read.iter().find(|u| u.source() == units[3].source()).unwrap().state());
+
+        let mut writer = MoWriter::new(Vec::new());
+        writer.write_unit(&units[0]).unwrap();
+        assert!(writer.write_unit(&units[0]).is_err());
+        assert!(writer.write_header(&LanguageRange::invariant(), None).is_err());
+    }
+
+    #[test]
+    fn write_fuzzy_header_and_odd_plurals() {
+        let header = Unit {
+            _source: Singular(String::new()),
+            _target: Singular("Language: ja\nPlural-Forms: nplurals=1; plural=0;\n".to_owned()),
+            _state: State::NeedsWork,
+            ..Unit::default()
+        };
+        // plural source without Other, as the XLIFF readers used to produce
+        let plural = Unit {
+            _source: Plural(vec![(One, "%d file".to_owned())].into_iter().collect()),
+            _target: Plural(vec![(Other, "%d ファイル".to_owned())].into_iter().collect()),
+            _state: State::Final,
+            ..Unit::default()
+        };
+        let mut writer = MoWriter::new(Vec::new());
+        writer.write_unit(&header).unwrap();
+        writer.write_unit(&plural).unwrap();
+        writer.finish().unwrap();
+        let mo = writer.into_inner();
+        let mut reader = MoReader::new(&mo[..]);
+        assert_eq!(1, reader.plural_rule().nplurals());
+        let unit = reader.next().unwrap().unwrap();
+        assert_eq!(plural.target(), unit.target());
+        assert_eq!("%d file", unit.source().key());
+    }
+
+    #[test]
+    fn write_header_unit_charset() {
+        let header = Unit {
+            _source: Singular(String::new()),
+            _target: Singular("Language: cs\nContent-Type: text/plain; charset=ISO-8859-2\n".to_owned()),
+            _state: State::Final,
+            ..Unit::default()
+        };
+        let unit = Unit {
+            _source: Singular("Open".to_owned()),
+            _target: Singular("Otevřít".to_owned()),
+            _state: State::Final,
+            ..Unit::default()
+        };
+        let mut writer = MoWriter::new(Vec::new());
+        writer.write_unit(&header).unwrap();
+        writer.write_unit(&unit).unwrap();
+        writer.finish().unwrap();
+        let mo = writer.into_inner();
+        let mut reader = MoReader::new(&mo[..]);
+        assert_eq!(Some("Language: cs\nContent-Type: text/plain; charset=UTF-8\n"),
+                   reader.header_unit().unwrap().target().singular());
+        assert_eq!(unit.target(), reader.next().unwrap().unwrap().target());
+    }
+
+    #[test]
+    fn decompile_mo() {
+        let entries: &[(&[u8], &[u8])] = &[
+            (b"", b"Content-Type: text/plain; charset=ISO-8859-2\n\
+                    Plural-Forms: nplurals=3; plural=(n==1) ? 0 : (n>=2 && n<=4) ? 1 : 2;\n"),
+            (b"%d file\0%d files", b"%d soubor\0%d soubory\0%d soubor\xF9"),
+            (b"menu\x04Open", b"Otev\xF8\xEDt"),
+        ];
+        let mut writer = PoWriter::new(Vec::new());
+        decompile(&mo(entries, true)[..], &mut writer).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(r#"msgid ""
+msgstr ""
+"Content-Type: text/plain; charset=UTF-8\n"
+"Plural-Forms: nplurals=3; plural=(n==1) ? 0 : (n>=2 && n<=4) ? 1 : 2;\n"
+
+msgid "%d file"
+msgid_plural "%d files"
+msgstr[0] "%d soubor"
+msgstr[1] "%d soubory"
+msgstr[2] "%d souborů"
+
+msgctxt "menu"
+msgid "Open"
+msgstr "Otevřít"
+"#, str::from_utf8(&writer.into_inner()).unwrap());
+
+        let mut writer = PoWriter::new(Vec::new());
+        decompile(&mo(&[(b"Open", b"Offen")], false)[..], &mut writer).unwrap();
+        let po = writer.into_inner();
+        assert!(str::from_utf8(&po).unwrap().starts_with("msgid \"\"\nmsgstr \"\"\n\"MIME-Version"));
+
+        assert!(decompile(&b""[..], &mut PoWriter::new(Vec::new())).is_err());
+    }
+}
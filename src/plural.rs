@@ -0,0 +1,544 @@
+//! Handling of [gettext plural forms][PF]
+//!
+//! Gettext selects plural variants by evaluating a C-like expression from the `Plural-Forms`
+//! header, like `nplurals=3; plural=(n==1) ? 0 : (n>=2 && n<=4) ? 1 : 2;`, which yields index of
+//! the `msgstr[N]` to use. This library, however, describes plural variants with the [`Count`]
+//! categories defined by [CLDR][CLDR], so the indices have to be mapped to them. That is done by
+//! sampling the expression over a range of numbers and comparing the results with CLDR rules for
+//! the catalogue language.
+//!
+//! [PF]: https://www.gnu.org/software/gettext/manual/html_node/Plural-forms.html
+//! [CLDR]: http://cldr.unicode.org/index/cldr-spec/plural-rules
+//! [`Count`]: ../enum.Count.html
+
+use locale_config::LanguageRange;
+use std::collections::BTreeMap;
+use std::fmt;
+use super::{Count,Error};
+
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
+enum BinOp {
+    Mul, Div, Rem,
+    Add, Sub,
+    Lt, Le, Gt, Ge,
+    Eq, Ne,
+    And,
+    Or,
+}
+
+impl BinOp {
+    // Binding power; higher binds tighter. The conditional operator is below all of these.
+    fn precedence(self) -> u8 {
+        match self {
+            BinOp::Or => 1,
+            BinOp::And => 2,
+            BinOp::Eq | BinOp::Ne => 3,
+            BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => 4,
+            BinOp::Add | BinOp::Sub => 5,
+            BinOp::Mul | BinOp::Div | BinOp::Rem => 6,
+        }
+    }
+
+    fn from_token(tok: &str) -> Option<BinOp> {
+        Some(match tok {
+            "*" => BinOp::Mul, "/" => BinOp::Div, "%" => BinOp::Rem,
+            "+" => BinOp::Add, "-" => BinOp::Sub,
+            "<" => BinOp::Lt, "<=" => BinOp::Le, ">" => BinOp::Gt, ">=" => BinOp::Ge,
+            "==" => BinOp::Eq, "!=" => BinOp::Ne,
+            "&&" => BinOp::And,
+            "||" => BinOp::Or,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone,Debug,Eq,PartialEq)]
+enum Expr {
+    N,
+    Const(u64),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Cond(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    // Evaluates like gettext does, in unsigned long arithmetic. Division by zero, which would
+    // crash a C program, gives zero.
+    fn eval(&self, n: u64) -> u64 {
+        match *self {
+            Expr::N => n,
+            Expr::Const(c) => c,
+            Expr::Not(ref e) => (e.eval(n) == 0) as u64,
+            Expr::Binary(BinOp::And, ref l, ref r) => (l.eval(n) != 0 && r.eval(n) != 0) as u64,
+            Expr::Binary(BinOp::Or, ref l, ref r) => (l.eval(n) != 0 || r.eval(n) != 0) as u64,
+            Expr::Binary(op, ref l, ref r) => {
+                let (a, b) = (l.eval(n), r.eval(n));
+                match op {
+                    BinOp::Mul => a.wrapping_mul(b),
+                    BinOp::Div => a.checked_div(b).unwrap_or(0),
+                    BinOp::Rem => a.checked_rem(b).unwrap_or(0),
+                    BinOp::Add => a.wrapping_add(b),
+                    BinOp::Sub => a.wrapping_sub(b),
+                    BinOp::Lt => (a < b) as u64,
+                    BinOp::Le => (a <= b) as u64,
+                    BinOp::Gt => (a > b) as u64,
+                    BinOp::Ge => (a >= b) as u64,
+                    BinOp::Eq => (a == b) as u64,
+                    BinOp::Ne => (a != b) as u64,
+                    BinOp::And | BinOp::Or => unreachable!(),
+                }
+            }
+            Expr::Cond(ref c, ref t, ref f) => if c.eval(n) != 0 { t.eval(n) } else { f.eval(n) },
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<&str>, Error> {
+    let mut res = Vec::new();
+    let mut rest = s.trim_start();
+    while !rest.is_empty() {
+        let len = if rest.starts_with(|c: char| c.is_ascii_digit()) {
+            rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len())
+        } else if ["<=", ">=", "==", "!=", "&&", "||"].iter().any(|t| rest.starts_with(t)) {
+            2
+        } else if rest.starts_with(|c| "n*/%+-<>!?:()".contains(c)) {
+            1
+        } else {
+            let bad = rest.chars().next().unwrap();
+            return Err(Error::Parse(0, Some(bad.to_string()), Vec::new()));
+        };
+        res.push(&rest[..len]);
+        rest = rest[len..].trim_start();
+    }
+    Ok(res)
+}
+
+struct ExprParser<'a> {
+    _tokens: Vec<&'a str>,
+    _pos: usize,
+}
+
+impl<'a> ExprParser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self._tokens.get(self._pos).cloned()
+    }
+
+    fn expect(&mut self, tok: &'static str) -> Result<(), Error> {
+        if self.peek() == Some(tok) {
+            self._pos += 1;
+            Ok(())
+        } else {
+            Err(self.unexpected(vec![tok]))
+        }
+    }
+
+    fn unexpected(&self, exp: Vec<&'static str>) -> Error {
+        Error::Parse(0, Some(self.peek().unwrap_or("end of expression").to_owned()), exp)
+    }
+
+    // cond := binary [ '?' cond ':' cond ]
+    fn parse_cond(&mut self) -> Result<Expr, Error> {
+        let c = self.parse_binary(1)?;
+        if self.peek() != Some("?") {
+            return Ok(c);
+        }
+        self._pos += 1;
+        let t = self.parse_cond()?;
+        self.expect(":")?;
+        let f = self.parse_cond()?;
+        Ok(Expr::Cond(Box::new(c), Box::new(t), Box::new(f)))
+    }
+
+    // Precedence climbing over the left-associative binary operators.
+    fn parse_binary(&mut self, min_prec: u8) -> Result<Expr, Error> {
+        let mut lhs = self.parse_unary()?;
+        while let Some(op) = self.peek().and_then(BinOp::from_token) {
+            if op.precedence() < min_prec {
+                break;
+            }
+            self._pos += 1;
+            let rhs = self.parse_binary(op.precedence() + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    // unary := '!' unary | '(' cond ')' | 'n' | number
+    fn parse_unary(&mut self) -> Result<Expr, Error> {
+        match self.peek() {
+            Some("!") => {
+                self._pos += 1;
+                Ok(Expr::Not(Box::new(self.parse_unary()?)))
+            }
+            Some("(") => {
+                self._pos += 1;
+                let e = self.parse_cond()?;
+                self.expect(")")?;
+                Ok(e)
+            }
+            Some("n") => {
+                self._pos += 1;
+                Ok(Expr::N)
+            }
+            Some(t) if t.starts_with(|c: char| c.is_ascii_digit()) => {
+                self._pos += 1;
+                t.parse().map(Expr::Const).map_err(|_| Error::Parse(0, Some(t.to_owned()), Vec::new()))
+            }
+            _ => Err(self.unexpected(vec!["n", "number", "(", "!"])),
+        }
+    }
+}
+
+fn parse_expr(s: &str) -> Result<Expr, Error> {
+    let mut parser = ExprParser { _tokens: tokenize(s)?, _pos: 0 };
+    let e = parser.parse_cond()?;
+    if parser.peek().is_some() {
+        return Err(parser.unexpected(Vec::new()));
+    }
+    Ok(e)
+}
+
+/// Plural rule from the `Plural-Forms` header.
+///
+/// Holds the number of forms and the expression selecting among them.
+#[derive(Clone,Debug)]
+pub struct PluralRule {
+    _nplurals: usize,
+    _expr: Expr,
+    _text: String,
+}
+
+impl PluralRule {
+    /// Parse the value of `Plural-Forms` header, `nplurals=N; plural=EXPR;`.
+    ///
+    /// Errors are reported as `Error::Parse` with line number 0, as the rule itself does not know
+    /// where it came from.
+    pub fn parse(s: &str) -> Result<PluralRule, Error> {
+        let mut nplurals = None;
+        let mut expr = None;
+        for part in s.split(';').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, val) = match part.find('=') {
+                Some(i) => (part[..i].trim(), part[(i+1)..].trim()),
+                None => return Err(Error::Parse(0, Some(part.to_owned()), vec!["nplurals=", "plural="])),
+            };
+            match key {
+                "nplurals" => nplurals = Some(val.parse::<usize>()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| Error::Parse(0, Some(val.to_owned()), vec!["number"]))?),
+                "plural" => expr = Some(parse_expr(val)?),
+                _ => return Err(Error::Parse(0, Some(key.to_owned()), vec!["nplurals", "plural"])),
+            }
+        }
+        match (nplurals, expr) {
+            (Some(n), Some(e)) => Ok(PluralRule { _nplurals: n, _expr: e, _text: s.trim().to_owned() }),
+            (None, _) => Err(Error::Parse(0, None, vec!["nplurals="])),
+            (_, None) => Err(Error::Parse(0, None, vec!["plural="])),
+        }
+    }
+
+    /// Number of plural forms.
+    pub fn nplurals(&self) -> usize { self._nplurals }
+
+    /// Index of plural form to use for number `n`.
+    ///
+    /// Like in gettext, index out of range is replaced with 0.
+    pub fn index(&self, n: u64) -> usize {
+        let i = self._expr.eval(n);
+        if i < self._nplurals as u64 { i as usize } else { 0 }
+    }
+
+    /// Map plural form indices to plural categories.
+    ///
+    /// Returns a vector of `nplurals()` distinct categories, one for each index, always including
+    /// `Count::Other`. Categories are determined by comparing the rule with CLDR rules for the
+    /// language, or, if the language is not known or its rules do not fit, by heuristics based on
+    /// which numbers select each index.
+    pub fn categories(&self, lang: &LanguageRange) -> Vec<Count> {
+        let samples = self.samples();
+        let mut res = cldr_categories(lang, &samples).unwrap_or_else(|| guess_categories(&samples));
+        if !res.contains(&Count::Other) {
+            // Gettext can't express fractions, so the CLDR Other category often has no integer in
+            // it (e.g. Polish or Russian). The index covering most numbers takes its place.
+            let catch_all = (0..samples.len()).max_by_key(|&i| samples[i].len()).unwrap();
+            res[catch_all] = Count::Other;
+        }
+        res
+    }
+
+    /// Get the categories the rule uses for only one number, like One in English, but not in
+    /// Russian, where it is also used for 21, 31 and so on. Their translations may leave out the
+    /// number, e.g. “one file” for `%d files`.
+    pub fn single_categories(&self, lang: &LanguageRange) -> Vec<Count> {
+        self.samples().iter().zip(self.categories(lang))
+            .filter(|&(s, _)| s.len() == 1)
+            .map(|(_, c)| c)
+            .collect()
+    }
+
+    // Numbers selecting each index, from a range that covers all the rules in practical use.
+    fn samples(&self) -> Vec<Vec<u64>> {
+        let mut res = vec![Vec::new(); self._nplurals];
+        for n in (0..200).chain([1000, 10000, 100000, 1000000].iter().cloned()) {
+            res[self.index(n)].push(n);
+        }
+        res
+    }
+}
+
+/// The default rule, used when a catalogue does not specify any, is the Germanic one,
+/// `nplurals=2; plural=(n != 1);`.
+impl Default for PluralRule {
+    fn default() -> PluralRule {
+        PluralRule::parse("nplurals=2; plural=(n != 1);").unwrap()
+    }
+}
+
+impl PartialEq for PluralRule {
+    fn eq(&self, other: &PluralRule) -> bool {
+        self._nplurals == other._nplurals && self._expr == other._expr
+    }
+}
+
+impl Eq for PluralRule {}
+
+impl fmt::Display for PluralRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self._text)
+    }
+}
+
+/// Map plural forms to categories for formats that list the forms without a plural rule.
+///
+/// If the language is known and uses `nplurals` categories for integers, those are returned in
+/// the order gettext uses for the language, with the most common one replaced by `Count::Other`
+/// like in `PluralRule::categories`. That is the order of the smallest positive number in each
+/// category, except a category of zero alone comes first. Otherwise the usual categories for the
+/// number of forms are assumed. At most six forms can be mapped.
+pub fn language_categories(lang: &LanguageRange, nplurals: usize) -> Vec<Count> {
+    use Count::*;
+    let code = lang.as_ref().split(['-', '_']).next().unwrap_or("").to_ascii_lowercase();
+    // category -> (smallest positive sample, number of samples)
+    let mut tally: BTreeMap<Count, (Option<u64>, usize)> = BTreeMap::new();
+    for n in (0..200).chain([1000, 10000, 100000, 1000000].iter().cloned()) {
+        if let Some(c) = cldr_category(&code, n) {
+            let entry = tally.entry(c).or_insert((None, 0));
+            entry.0 = entry.0.or(Some(n).filter(|&n| n > 0));
+            entry.1 += 1;
+        }
+    }
+    if tally.len() == nplurals {
+        let mut res = tally.keys().cloned().collect::<Vec<_>>();
+        res.sort_by_key(|c| tally[c].0.unwrap_or(0));
+        if !res.contains(&Other) {
+            let most = tally.iter().max_by_key(|&(_, &(_, t))| t).map(|(&c, _)| c).unwrap();
+            res.iter_mut().filter(|c| **c == most).for_each(|c| *c = Other);
+        }
+        return res;
+    }
+    match nplurals {
+        0 | 1 => vec![Other],
+        2 => vec![One, Other],
+        3 => vec![One, Few, Other],
+        4 => vec![One, Two, Few, Other],
+        5 => vec![One, Two, Few, Many, Other],
+        _ => vec![Zero, One, Two, Few, Many, Other],
+    }
+}
+
+// Assign to each index the CLDR category most of its samples fall into. Returns None if the
+// language is unknown or the rule does not correspond to CLDR.
+fn cldr_categories(lang: &LanguageRange, samples: &[Vec<u64>]) -> Option<Vec<Count>> {
+    let code = lang.as_ref().split(['-', '_']).next().unwrap_or("").to_ascii_lowercase();
+    cldr_category(&code, 1)?;
+    let mut res: Vec<Count> = Vec::new();
+    for s in samples {
+        let mut tally = BTreeMap::new();
+        for &n in s {
+            *tally.entry(cldr_category(&code, n).unwrap()).or_insert(0) += 1;
+        }
+        let c = tally.iter().max_by_key(|&(_, &t)| t).map(|(&c, _)| c)?;
+        if res.contains(&c) {
+            return None;
+        }
+        res.push(c);
+    }
+    Some(res)
+}
+
+fn guess_categories(samples: &[Vec<u64>]) -> Vec<Count> {
+    let mut res: Vec<Option<Count>> = samples.iter().map(|s| match *s.as_slice() {
+        [0] => Some(Count::Zero),
+        [1] | [0, 1] => Some(Count::One),
+        [2] => Some(Count::Two),
+        _ => None,
+    }).collect();
+    if let Some(i) = (0..samples.len()).filter(|&i| res[i].is_none()).max_by_key(|&i| samples[i].len()) {
+        res[i] = Some(Count::Other);
+    }
+    let mut spare = [Count::Few, Count::Many, Count::Zero, Count::One, Count::Two, Count::Other]
+        .iter()
+        .cloned()
+        .filter(|c| !res.contains(&Some(*c)))
+        .collect::<Vec<_>>()
+        .into_iter();
+    res.into_iter().map(|c| c.or_else(|| spare.next()).unwrap_or(Count::Other)).collect()
+}
+
+// CLDR cardinal plural rules, restricted to non-negative integers, since that's all gettext
+// handles.
+fn cldr_category(lang: &str, n: u64) -> Option<Count> {
+    use Count::*;
+    let (n10, n100) = (n % 10, n % 100);
+    Some(match lang {
+        "bo" | "dz" | "id" | "ig" | "ii" | "ja" | "jv" | "km" | "ko" | "lo" | "ms" | "my" | "sah"
+            | "th" | "to" | "vi" | "wo" | "yo" | "yue" | "zh" => Other,
+        "af" | "az" | "bg" | "ca" | "da" | "de" | "el" | "en" | "eo" | "es" | "et" | "eu" | "fi"
+            | "fo" | "fy" | "gl" | "hu" | "it" | "ka" | "kk" | "ky" | "lb" | "ml" | "mn" | "nb"
+            | "ne" | "nl" | "nn" | "no" | "sq" | "sv" | "sw" | "ta" | "te" | "tr" | "ur" | "uz" =>
+            if n == 1 { One } else { Other },
+        "am" | "bn" | "fa" | "ff" | "fr" | "gu" | "hi" | "hy" | "kab" | "kn" | "mr" | "pt" | "zu" =>
+            if n <= 1 { One } else { Other },
+        "cs" | "sk" =>
+            match n { 1 => One, 2..=4 => Few, _ => Other },
+        "pl" =>
+            if n == 1 { One }
+            else if (2..=4).contains(&n10) && !(12..=14).contains(&n100) { Few }
+            else { Many },
+        "be" | "ru" | "uk" =>
+            if n10 == 1 && n100 != 11 { One }
+            else if (2..=4).contains(&n10) && !(12..=14).contains(&n100) { Few }
+            else { Many },
+        "bs" | "hr" | "sh" | "sr" =>
+            if n10 == 1 && n100 != 11 { One }
+            else if (2..=4).contains(&n10) && !(12..=14).contains(&n100) { Few }
+            else { Other },
+        "is" | "mk" =>
+            if n10 == 1 && n100 != 11 { One } else { Other },
+        "sl" =>
+            match n100 { 1 => One, 2 => Two, 3 | 4 => Few, _ => Other },
+        "lt" =>
+            if (11..=19).contains(&n100) { Other }
+            else if n10 == 1 { One }
+            else if n10 >= 2 { Few }
+            else { Other },
+        "lv" =>
+            if n10 == 0 || (11..=19).contains(&n100) { Zero }
+            else if n10 == 1 { One }
+            else { Other },
+        "mo" | "ro" =>
+            if n == 1 { One }
+            else if n == 0 || (1..=19).contains(&n100) { Few }
+            else { Other },
+        "ar" =>
+            match (n, n100) { (0, _) => Zero, (1, _) => One, (2, _) => Two, (_, 3..=10) => Few,
+                              (_, 11..=99) => Many, _ => Other },
+        "he" | "iw" =>
+            match n { 1 => One, 2 => Two, _ => Other },
+        "ga" =>
+            match n { 1 => One, 2 => Two, 3..=6 => Few, 7..=10 => Many, _ => Other },
+        "gd" =>
+            match n { 1 | 11 => One, 2 | 12 => Two, 3..=10 | 13..=19 => Few, _ => Other },
+        "cy" =>
+            match n { 0 => Zero, 1 => One, 2 => Two, 3 => Few, 6 => Many, _ => Other },
+        "mt" =>
+            match (n, n100) { (1, _) => One, (2, _) => Two, (0, _) | (_, 3..=10) => Few,
+                              (_, 11..=19) => Many, _ => Other },
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ::Count::*;
+    use ::locale_config::LanguageRange;
+    use super::PluralRule;
+
+    #[test]
+    fn evaluate() {
+        let r = PluralRule::parse("nplurals=3; plural=(n==1) ? 0 : (n>=2 && n<=4) ? 1 : 2;").unwrap();
+        assert_eq!(3, r.nplurals());
+        assert_eq!(vec![2, 0, 1, 1, 1, 2, 2], (0..7).map(|n| r.index(n)).collect::<Vec<_>>());
+
+        let r = PluralRule::parse(
+            "nplurals=3; plural=(n%10==1 && n%100!=11 ? 0 : \
+             n%10>=2 && n%10<=4 && (n%100<10 || n%100>=20) ? 1 : 2);").unwrap();
+        assert_eq!(0, r.index(21));
+        assert_eq!(2, r.index(11));
+        assert_eq!(1, r.index(23));
+        assert_eq!(2, r.index(13));
+
+        let r = PluralRule::parse("nplurals=2; plural=!(n == 1 || 7 - 2 * 3 == n);").unwrap();
+        assert_eq!(vec![1, 0, 1], (0..3).map(|n| r.index(n)).collect::<Vec<_>>());
+
+        // out of range index falls back to 0, like in gettext
+        let r = PluralRule::parse("nplurals=2; plural=n;").unwrap();
+        assert_eq!(0, r.index(5));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(PluralRule::parse("nplurals=2;").is_err());
+        assert!(PluralRule::parse("plural=n != 1;").is_err());
+        assert!(PluralRule::parse("nplurals=2; plural=(n != 1;").is_err());
+        assert!(PluralRule::parse("nplurals=2; plural=n ? 1;").is_err());
+        assert!(PluralRule::parse("nplurals=2; plural=n $ 1;").is_err());
+        assert!(PluralRule::parse("nplurals=x; plural=n != 1;").is_err());
+    }
+
+    #[test]
+    fn categories() {
+        let lang = |l| LanguageRange::new(l).unwrap();
+        let cs = PluralRule::parse("nplurals=3; plural=(n==1) ? 0 : (n>=2 && n<=4) ? 1 : 2;").unwrap();
+        assert_eq!(vec![One, Few, Other], cs.categories(&lang("cs")));
+        assert_eq!(vec![One, Few, Other], cs.categories(&LanguageRange::invariant()));
+
+        let pl = PluralRule::parse(
+            "nplurals=3; plural=(n==1 ? 0 : n%10>=2 && n%10<=4 && (n%100<10 || n%100>=20) ? 1 : 2);")
+            .unwrap();
+        assert_eq!(vec![One, Few, Other], pl.categories(&lang("pl-PL")));
+
+        let fr = PluralRule::parse("nplurals=2; plural=(n > 1);").unwrap();
+        assert_eq!(vec![One, Other], fr.categories(&lang("fr")));
+
+        let ar = PluralRule::parse(
+            "nplurals=6; plural=n==0 ? 0 : n==1 ? 1 : n==2 ? 2 : n%100>=3 && n%100<=10 ? 3 : \
+             n%100>=11 ? 4 : 5;").unwrap();
+        assert_eq!(vec![Zero, One, Two, Few, Many, Other], ar.categories(&lang("ar")));
+
+        let ja = PluralRule::parse("nplurals=1; plural=0;").unwrap();
+        assert_eq!(vec![Other], ja.categories(&lang("ja")));
+
+        assert_eq!(vec![One, Other], PluralRule::default().categories(&lang("en")));
+    }
+
+    #[test]
+    fn single_categories() {
+        let lang = |l| LanguageRange::new(l).unwrap();
+        let cs = PluralRule::parse("nplurals=3; plural=(n==1) ? 0 : (n>=2 && n<=4) ? 1 : 2;").unwrap();
+        assert_eq!(vec![One], cs.single_categories(&lang("cs")));
+        let ru = PluralRule::parse(
+            "nplurals=3; plural=(n%10==1 && n%100!=11 ? 0 : n%10>=2 && n%10<=4 && \
+             (n%100<10 || n%100>=20) ? 1 : 2);").unwrap();
+        assert!(ru.single_categories(&lang("ru")).is_empty());
+        let fr = PluralRule::parse("nplurals=2; plural=(n > 1);").unwrap();
+        assert!(fr.single_categories(&lang("fr")).is_empty());
+        let ar = PluralRule::parse(
+            "nplurals=6; plural=n==0 ? 0 : n==1 ? 1 : n==2 ? 2 : n%100>=3 && n%100<=10 ? 3 : \
+             n%100>=11 ? 4 : 5;").unwrap();
+        assert_eq!(vec![Zero, One, Two], ar.single_categories(&lang("ar")));
+    }
+
+    #[test]
+    fn language_categories() {
+        let lang = |l| LanguageRange::new(l).unwrap();
+        assert_eq!(vec![One, Few, Other], super::language_categories(&lang("cs"), 3));
+        assert_eq!(vec![One, Few, Other], super::language_categories(&lang("ru-RU"), 3));
+        assert_eq!(vec![Zero, One, Two, Few, Many, Other], super::language_categories(&lang("ar"), 6));
+        assert_eq!(vec![One, Other], super::language_categories(&lang("fr"), 2));
+        assert_eq!(vec![One, Other, Zero], super::language_categories(&lang("lv"), 3));
+        assert_eq!(vec![One, Few, Other], super::language_categories(&lang("pl"), 3));
+        assert_eq!(vec![Other], super::language_categories(&lang("ja"), 1));
+        assert_eq!(vec![One, Two, Few, Other], super::language_categories(&LanguageRange::invariant(), 4));
+    }
+}
@@ -10,12 +10,17 @@
 //! [gettext]: https://www.gnu.org/software/gettext/
 //! [tt]: http://toolkit.translatehouse.org/
 
+use chrono::{DateTime,FixedOffset};
+use encoding_rs::{Encoding,UTF_8};
 use locale_config::LanguageRange;
-use regex::{Regex,Captures};
-use std::collections::{BTreeMap,HashMap};
-use std::io::{BufRead,Lines};
+use regex::Regex;
+use regex::bytes::Regex as BytesRegex;
+use std::collections::{BTreeMap,VecDeque};
+use std::fmt;
+use std::io::{self,BufRead,Write};
 use std::iter::Peekable;
-use super::{CatalogueReader,Count,Error,Message,Origin,State,Unit};
+use super::{CatalogueReader,CatalogueWriter,Count,Error,Flag,Message,Origin,State,Unit};
+use super::plural::PluralRule;
 
 #[derive(Clone,Debug)]
 enum PoLine {
@@ -31,54 +36,43 @@ enum PoLine {
 
 struct LineIter<R: BufRead> {
     _n: usize,
-    _inner: Lines<R>,
+    _inner: R,
+    // Raw lines read ahead to find the charset in the header entry.
+    _pending: VecDeque<io::Result<Vec<u8>>>,
+    _encoding: &'static Encoding,
+    _failed: bool,
 }
 
 lazy_static!{
     static ref MESSAGE_RE: Regex = Regex::new(
-        r#"^\s*(#~?\|?)?\s*(msgctxt|msgid|msgif_plural|msgstr(?:\[[012345]\])?)?\s*"(.*)"\s*$"#)
+        r#"^\s*(#~\|?|#\|)?\s*(msgctxt|msgid|msgid_plural|msgstr(?:\[[0-9]+\])?)?\s*"(.*)"\s*$"#)
         .unwrap();
     static ref COMMENT_RE: Regex = Regex::new(
         r#"^\s*#([:.,]?)\s*(.*)"#).unwrap();
-
-    static ref UNESCAPE_RE: Regex = Regex::new(r#"\\[rtn"\\]"#).unwrap();
-    static ref UNESCAPE_MAP: HashMap<&'static str, &'static str> = [
-        (r"\r", "\r"),
-        (r"\t", "\t"),
-        (r"\n", "\n"),
-        ("\\\"", "\""),
-        (r"\\", r"\"),
-    ].iter().cloned().collect();
+    static ref CHARSET_RE: BytesRegex = BytesRegex::new(
+        r#"Content-Type:[^\\]*charset=([-_.:A-Za-z0-9]+)"#).unwrap();
 }
 
-fn parse_po_line(line: &str, n: usize) -> Result<PoLine, ()> {
+fn parse_po_line(line: &str, n: usize, encoding: &'static Encoding) -> Result<PoLine, Error> {
     if !line.contains(|c: char| !c.is_whitespace()) {
         return Ok(PoLine::Blank);
     }
     if let Some(c) = MESSAGE_RE.captures(line) {
-        if c.get(2).is_some() {
-            return Ok(PoLine::Message(
-                    n,
-                    c.get(1).map(|x| x.as_str()).unwrap_or("").to_owned(),
-                    if c.get(1).map(|x| x.as_str()).unwrap_or("").ends_with('|') {
-                        String::from("|") + c.get(2).unwrap().as_str()
-                    } else {
-                        c.get(2).unwrap().as_str().to_owned()
-                    },
-                    UNESCAPE_RE.replace(
-                        c.get(3).unwrap().as_str(),
-                        |d: &Captures| -> String {
-                            UNESCAPE_MAP.get(d.get(0).unwrap().as_str()).unwrap().to_string()
-                        }).into_owned()));
+        let string = c.get(3).unwrap();
+        let string = unescape(string.as_str(), encoding).map_err(|(i, seq)| {
+            let column = line[..string.start()].chars().count() + i + 1;
+            Error::Parse(n, Some(format!("{} at column {}", seq, column)), vec!["escape sequence"])
+        })?;
+        let prefix = c.get(1).map(|x| x.as_str()).unwrap_or("").to_owned();
+        if let Some(tag) = c.get(2) {
+            let tag = if prefix.ends_with('|') {
+                String::from("|") + tag.as_str()
+            } else {
+                tag.as_str().to_owned()
+            };
+            return Ok(PoLine::Message(n, prefix, tag, string));
         } else {
-            return Ok(PoLine::Continuation(
-                    n,
-                    c.get(1).map(|x| x.as_str()).unwrap_or("").to_owned(),
-                    UNESCAPE_RE.replace(
-                        c.get(3).unwrap().as_str(),
-                        |d: &Captures| -> String {
-                            UNESCAPE_MAP.get(d.get(0).unwrap().as_str()).unwrap().to_string()
-                        }).into_owned()));
+            return Ok(PoLine::Continuation(n, prefix, string));
         }
     }
     if let Some(c) = COMMENT_RE.captures(line) {
@@ -87,34 +81,205 @@ fn parse_po_line(line: &str, n: usize) -> Result<PoLine, ()> {
                 c.get(1).unwrap().as_str().chars().next().unwrap_or(' '),
                 c.get(2).unwrap().as_str().to_owned()));
     }
-    return Err(());
+    Err(Error::Parse(n, Some(line.to_owned()), Vec::new()))
+}
+
+// Resolve the C escape sequences gettext accepts. Octal and hexadecimal escapes give bytes in the
+// file's encoding, so each run of escapes is decoded with it. On error, returns the position of
+// the offending sequence, in characters, and the sequence.
+fn unescape(s: &str, encoding: &'static Encoding) -> Result<String, (usize, String)> {
+    let mut res = String::with_capacity(s.len());
+    // bytes of the current run of escapes and the position where it started
+    let mut bytes = Vec::new();
+    let mut run_start = 0;
+    let flush = |res: &mut String, bytes: &mut Vec<u8>, run_start| {
+        if !bytes.is_empty() {
+            match encoding.decode_without_bom_handling_and_without_replacement(bytes) {
+                Some(decoded) => res.push_str(&decoded),
+                None => return Err((run_start,
+                                    format!("escaped bytes that are not {}", encoding.name()))),
+            }
+            bytes.clear();
+        }
+        Ok(())
+    };
+    let mut chars = s.chars().enumerate().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c != '\\' {
+            flush(&mut res, &mut bytes, run_start)?;
+            res.push(c);
+            continue;
+        }
+        if bytes.is_empty() {
+            run_start = i;
+        }
+        let e = match chars.next() {
+            Some((_, e)) => e,
+            None => return Err((i, String::from("\\"))),
+        };
+        match e {
+            'n' => bytes.push(b'\n'),
+            't' => bytes.push(b'\t'),
+            'r' => bytes.push(b'\r'),
+            'a' => bytes.push(0x07),
+            'b' => bytes.push(0x08),
+            'f' => bytes.push(0x0C),
+            'v' => bytes.push(0x0B),
+            '\\' | '"' => bytes.push(e as u8),
+            d if d.is_digit(8) => {
+                let mut val = d.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match chars.peek().and_then(|&(_, d)| d.to_digit(8)) {
+                        Some(v) => val = val * 8 + v,
+                        None => break,
+                    }
+                    chars.next();
+                }
+                bytes.push(val as u8);
+            }
+            'x' => {
+                let mut val = None;
+                while let Some(v) = chars.peek().and_then(|&(_, d)| d.to_digit(16)) {
+                    val = Some(val.unwrap_or(0u32).wrapping_mul(16).wrapping_add(v));
+                    chars.next();
+                }
+                match val {
+                    Some(v) => bytes.push(v as u8),
+                    None => return Err((i, String::from("\\x"))),
+                }
+            }
+            e => return Err((i, format!("\\{}", e))),
+        }
+    }
+    flush(&mut res, &mut bytes, run_start)?;
+    Ok(res)
 }
 
 impl<R: BufRead> Iterator for LineIter<R> {
     type Item = Result<PoLine, Error>;
     fn next(&mut self) -> Option<Result<PoLine, Error>> {
         loop {
-            let line = match self._inner.next() {
-                Some(Ok(s)) => s,
-                Some(Err(e)) => return Some(Err(Error::Io(self._n + 1, e))),
-                None => return None,
+            let raw = match self._pending.pop_front().or_else(|| self.read_raw())? {
+                Ok(raw) => raw,
+                Err(e) => return Some(Err(Error::Io(self._n + 1, e))),
             };
             self._n += 1;
-            match parse_po_line(&line, self._n) {
+            let raw = if self._n == 1 && self._encoding == UTF_8 && raw.starts_with(b"\xEF\xBB\xBF") {
+                &raw[3..]
+            } else {
+                &raw[..]
+            };
+            let line = match self._encoding.decode_without_bom_handling_and_without_replacement(raw) {
+                Some(line) => line,
+                None => return Some(Err(Error::Decode(self._n, self._encoding.name().to_owned()))),
+            };
+            match parse_po_line(&line, self._n, self._encoding) {
                 Ok(PoLine::Blank) => (),
-                Ok(p) => return Some(Ok(p)),
-                Err(_) => return Some(Err(Error::Parse(self._n, Some(line), Vec::new()))),
+                res => return Some(res),
             }
         }
     }
 }
 
+// Part of the header entry being read ahead.
+#[derive(Clone,Copy,PartialEq)]
+enum HeaderPart { Comments, Msgid, Msgstr }
+
 impl<R: BufRead> LineIter<R> {
-    fn new(r: R) -> LineIter<R> {
-        LineIter {
+    // Lines are decoded separately, which works because newline is never part of a multi-byte
+    // sequence in any of the encodings gettext supports. Files without the declaration, and
+    // templates that still have the `CHARSET` placeholder, are taken to be UTF-8. If the charset
+    // is not known, the error is returned with iterator that yields nothing.
+    fn new(r: R) -> (LineIter<R>, Option<Error>) {
+        let mut res = LineIter {
             _n: 0,
-            _inner: r.lines(),
+            _inner: r,
+            _pending: VecDeque::new(),
+            _encoding: UTF_8,
+            _failed: false,
+        };
+        let (line, charset) = match res.read_header_charset() {
+            Some(c) => c,
+            None => return (res, None),
+        };
+        match Encoding::for_label(&charset) {
+            Some(encoding) => {
+                res._encoding = encoding;
+                (res, None)
+            }
+            None => {
+                res._pending.clear();
+                res._failed = true;
+                (res, Some(Error::Decode(line, String::from_utf8_lossy(&charset).into_owned())))
+            }
+        }
+    }
+
+    fn read_raw(&mut self) -> Option<io::Result<Vec<u8>>> {
+        if self._failed {
+            return None;
+        }
+        let mut line = Vec::new();
+        match self._inner.read_until(b'\n', &mut line) {
+            Ok(0) => None,
+            Ok(_) => {
+                if line.ends_with(b"\n") {
+                    line.pop();
+                    if line.ends_with(b"\r") {
+                        line.pop();
+                    }
+                }
+                Some(Ok(line))
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    // Read ahead the header entry and find the charset in its `msgstr`. Returns the number of the
+    // line with the declaration and the charset. The lines are only looked at as ASCII, which all
+    // the encodings gettext supports are compatible with.
+    fn read_header_charset(&mut self) -> Option<(usize, Vec<u8>)> {
+        let mut part = HeaderPart::Comments;
+        // msgstr contents, and the line number and offset in it where each line starts
+        let mut msgstr = Vec::new();
+        let mut starts = Vec::new();
+        while let Some(raw) = self.read_raw() {
+            let line = match raw {
+                Ok(ref l) if self._pending.is_empty() =>
+                    l.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(l).trim_ascii_start().to_vec(),
+                Ok(ref l) => l.trim_ascii_start().to_vec(),
+                Err(_) => {
+                    self._pending.push_back(raw);
+                    break;
+                }
+            };
+            self._pending.push_back(raw);
+            let string = line.iter().position(|&b| b == b'"')
+                .and_then(|s| line.iter().rposition(|&b| b == b'"').filter(|&e| e > s).map(|e| (s, e)))
+                .map(|(s, e)| &line[(s + 1)..e]);
+            part = match part {
+                HeaderPart::Comments if line.is_empty() || line.starts_with(b"#") =>
+                    HeaderPart::Comments,
+                HeaderPart::Comments if line.starts_with(b"msgid ") && string == Some(b"") =>
+                    HeaderPart::Msgid,
+                HeaderPart::Msgid if line.starts_with(b"\"") && string == Some(b"") =>
+                    HeaderPart::Msgid,
+                HeaderPart::Msgid | HeaderPart::Msgstr
+                        if line.starts_with(b"msgstr ")
+                            || (part == HeaderPart::Msgstr && line.starts_with(b"\"")) => {
+                    starts.push((self._pending.len(), msgstr.len()));
+                    msgstr.extend_from_slice(string.unwrap_or(b""));
+                    HeaderPart::Msgstr
+                }
+                _ => break,
+            };
+        }
+        let m = CHARSET_RE.captures(&msgstr)?.get(1)?;
+        if m.as_bytes() == b"CHARSET" {
+            return None;
         }
+        let line = starts.iter().rev().find(|l| l.1 <= m.start()).map_or(0, |l| l.0);
+        Some((line, m.as_bytes().to_vec()))
     }
 }
 
@@ -129,10 +294,11 @@ impl<R: BufRead> MsgParser for Peekable<LineIter<R>> {
         while let Some(&Ok(PoLine::Comment(..))) = self.peek() {
             match self.next() {
                 Some(Ok(PoLine::Comment(_, ',', s))) => {
-                    for flag in s.split(',').map(str::trim) {
-                        match flag {
-                            "fuzzy" => unit._state = State::NeedsWork,
-                            _ => (), // TODO: Implement other flags (do we need any?)
+                    for flag in s.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+                        if flag == "fuzzy" {
+                            unit._state = State::NeedsWork;
+                        } else {
+                            unit._flags.push(Flag::parse(flag));
                         }
                     }
                 }
@@ -215,43 +381,223 @@ fn is_header(oru: &Option<Result<Unit, Error>>) -> bool {
     }
 }
 
+pub(crate) fn header_fields(text: &str) -> impl Iterator<Item = (&str, &str)> {
+    text.split('\n').filter_map(|line| line.find(':').map(|n| (line[..n].trim(), line[(n+1)..].trim())))
+}
+
+pub(crate) fn parse_language(lang: &str) -> LanguageRange<'static> {
+    LanguageRange::new(lang)
+        .map(LanguageRange::into_static)
+        .or_else(|_| LanguageRange::from_unix(lang))
+        .unwrap_or_else(|_| LanguageRange::invariant())
+}
+
+/// Header entry of a PO file.
+///
+/// The header is the entry with empty `msgid`, whose translation is a list of `Key: value`
+/// fields. The fields are kept in their original order, including custom `X-` fields, together
+/// with the translator comments preceding the entry, which usually hold the title and copyright
+/// notice of the catalogue.
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct PoHeader {
+    _fields: Vec<(String, String)>,
+    _comments: Vec<String>,
+}
+
+static DATE_FORMAT: &str = "%Y-%m-%d %H:%M%z";
+
+pub(crate) fn parse_date(date: &str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_str(date, DATE_FORMAT).ok()
+}
+
+impl PoHeader {
+    /// Parse the header from the header unit.
+    pub fn from_unit(unit: &Unit) -> Self {
+        PoHeader {
+            _fields: header_fields(unit.target().singular().unwrap_or(""))
+                .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                .collect(),
+            _comments: unit.notes().iter()
+                .filter(|n| n.0 == Origin::Translator)
+                .map(|n| n.1.clone())
+                .collect(),
+        }
+    }
+
+    /// Create the header unit, as `PoWriter::write_header` expects it.
+    pub fn to_unit(&self) -> Unit {
+        Unit {
+            _source: Message::Singular(String::new()),
+            _target: Message::Singular(self.to_string()),
+            _notes: self._comments.iter().map(|c| (Origin::Translator, c.clone())).collect(),
+            _state: State::Final,
+            ..Unit::default()
+        }
+    }
+
+    /// Get value of a field. If the field is repeated, the first value is returned.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self._fields.iter().find(|f| f.0 == key).map(|f| f.1.as_str())
+    }
+
+    /// Set value of a field. Existing field is replaced in place, new one is appended.
+    pub fn set(&mut self, key: &str, value: &str) {
+        match self._fields.iter_mut().find(|f| f.0 == key) {
+            Some(f) => f.1 = value.to_owned(),
+            None => self._fields.push((key.to_owned(), value.to_owned())),
+        }
+    }
+
+    /// Remove a field, returning its value.
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let n = self._fields.iter().position(|f| f.0 == key)?;
+        Some(self._fields.remove(n).1)
+    }
+
+    /// Iterate over all fields in order.
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &str)> {
+        self._fields.iter().map(|f| (f.0.as_str(), f.1.as_str()))
+    }
+
+    /// Iterate over the custom fields, those with `X-` prefix, in order.
+    pub fn custom_fields(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields().filter(|f| f.0.starts_with("X-"))
+    }
+
+    /// Get the translator comments preceding the header, one line per item.
+    pub fn comments(&self) -> &[String] {
+        &self._comments
+    }
+
+    pub fn set_comments(&mut self, comments: Vec<String>) {
+        self._comments = comments;
+    }
+
+    pub fn project_id_version(&self) -> Option<&str> {
+        self.get("Project-Id-Version")
+    }
+
+    pub fn report_msgid_bugs_to(&self) -> Option<&str> {
+        self.get("Report-Msgid-Bugs-To")
+    }
+
+    /// Get the creation date of the template. `None` if missing or still the template placeholder.
+    pub fn pot_creation_date(&self) -> Option<DateTime<FixedOffset>> {
+        self.date("POT-Creation-Date")
+    }
+
+    pub fn set_pot_creation_date(&mut self, date: &DateTime<FixedOffset>) {
+        self.set("POT-Creation-Date", &date.format(DATE_FORMAT).to_string());
+    }
+
+    /// Get the date of last translation update. `None` if missing or still the template
+    /// placeholder.
+    pub fn po_revision_date(&self) -> Option<DateTime<FixedOffset>> {
+        self.date("PO-Revision-Date")
+    }
+
+    pub fn set_po_revision_date(&mut self, date: &DateTime<FixedOffset>) {
+        self.set("PO-Revision-Date", &date.format(DATE_FORMAT).to_string());
+    }
+
+    pub fn last_translator(&self) -> Option<&str> {
+        self.get("Last-Translator")
+    }
+
+    pub fn language_team(&self) -> Option<&str> {
+        self.get("Language-Team")
+    }
+
+    pub fn language(&self) -> Option<LanguageRange<'static>> {
+        self.get("Language").map(parse_language)
+    }
+
+    /// Get the charset from the `Content-Type` field. `None` if missing or still the template
+    /// placeholder.
+    pub fn charset(&self) -> Option<&str> {
+        let ct = self.get("Content-Type")?;
+        let n = ct.find("charset=")?;
+        Some(ct[(n + 8)..].split(|c: char| c == ';' || c.is_whitespace()).next().unwrap())
+            .filter(|c| !c.is_empty() && *c != "CHARSET")
+    }
+
+    /// Get the plural rule from the `Plural-Forms` field, if present.
+    pub fn plural_forms(&self) -> Result<Option<PluralRule>, Error> {
+        self.get("Plural-Forms").map(PluralRule::parse).transpose()
+    }
+
+    fn date(&self, key: &str) -> Option<DateTime<FixedOffset>> {
+        parse_date(self.get(key)?)
+    }
+}
+
+impl fmt::Display for PoHeader {
+    /// Format the fields as the text of the header entry.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (key, value) in self.fields() {
+            writeln!(f, "{}: {}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reader for PO files.
+///
+/// The file is decoded according to the charset given in the `Content-Type` field of the header
+/// entry, so any encoding gettext supports can be read. The units can then be read by iterating
+/// over the reader.
 pub struct PoReader<R: BufRead> {
     _lines: Peekable<LineIter<R>>,
     _next_unit: Option<Result<Unit, Error>>,
     _failed: Option<Error>,
-    _header: HashMap<String, String>,
+    _header: PoHeader,
+    _header_unit: Option<Unit>,
     _target_language: LanguageRange<'static>,
+    _plural_rule: PluralRule,
     _plurals: Vec<Count>,
 }
 
 impl<R: BufRead> PoReader<R> {
     pub fn new(reader: R) -> Self {
+        let (lines, failed) = LineIter::new(reader);
         let mut res = PoReader {
-            _lines: LineIter::new(reader).peekable(),
+            _lines: lines.peekable(),
             _next_unit: None,
-            _failed: None,
-            _header: HashMap::new(),
+            _failed: failed,
+            _header: PoHeader::default(),
+            _header_unit: None,
             _target_language: LanguageRange::invariant(),
-            _plurals: Vec::new(),
+            _plural_rule: PluralRule::default(),
+            _plurals: PluralRule::default().categories(&LanguageRange::invariant()),
         };
+        let header_line = res.peek_line();
         res._next_unit = res.next_unit();
         if is_header(&res._next_unit) {
-            res.parse_po_header();
+            res.parse_po_header(header_line);
             res._next_unit = res.next_unit();
         }
-        return res;
+        res
     }
 
     fn make_source(msgid: Option<String>, msgid_plural: Option<String>) -> Message {
-        if msgid.is_none() {
-            Message::Empty
-        } else if msgid_plural.is_none() {
-            Message::Singular(msgid.unwrap())
-        } else {
-            let mut map = BTreeMap::new();
-            map.insert(Count::One, msgid.unwrap());
-            map.insert(Count::Other, msgid_plural.unwrap());
-            Message::Plural(map)
+        match (msgid, msgid_plural) {
+            (None, _) => Message::Empty,
+            (Some(id), None) => Message::Singular(id),
+            (Some(id), Some(id_pl)) => {
+                let mut map = BTreeMap::new();
+                map.insert(Count::One, id);
+                map.insert(Count::Other, id_pl);
+                Message::Plural(map)
+            }
+        }
+    }
+
+    fn peek_line(&mut self) -> usize {
+        match self._lines.peek() {
+            Some(&Ok(PoLine::Comment(n, ..))) |
+            Some(&Ok(PoLine::Message(n, ..))) |
+            Some(&Ok(PoLine::Continuation(n, ..))) => n,
+            _ => 0,
         }
     }
 
@@ -276,6 +622,7 @@ impl<R: BufRead> PoReader<R> {
 
         unit._context = self._lines.parse_msg("msgctxt", &mut unit)?;
 
+        unit._line = self.peek_line();
         let msgid = self._lines.parse_msg("msgid", &mut unit)?;
         if msgid.is_none() {
             return self._lines.expected(vec!["msgid"]);
@@ -291,16 +638,18 @@ impl<R: BufRead> PoReader<R> {
             }
         } else {
             assert!(unit._source.is_plural());
-            const TAGS: &'static [&'static str] =
-                &["msgstr[0]", "msgstr[1]", "msgstr[2]", "msgstr[3]", "msgstr[4]", "msgstr[5]", "msgstr[6]"];
-            let mut map = BTreeMap::new();
-            for (c, t) in self._plurals.iter().zip(TAGS) {
-                match self._lines.parse_msg(t, &mut unit)? {
-                    None => return self._lines.expected(vec![t]),
-                    Some(s) => { map.insert(*c, s); }
-                }
+            let line = self.peek_line();
+            let mut forms = Vec::new();
+            while let Some(s) = self._lines.parse_msg(&format!("msgstr[{}]", forms.len()), &mut unit)? {
+                forms.push(s);
+            }
+            if forms.is_empty() {
+                return self._lines.expected(vec!["msgstr[0]"]);
             }
-            unit._target = Message::Plural(map);
+            if forms.len() != self._plurals.len() {
+                return Err(Error::PluralForms(line, self._plurals.len(), forms.len()));
+            }
+            unit._target = Message::Plural(self._plurals.iter().cloned().zip(forms).collect());
         }
 
         if unit._state == State::Empty && !unit._target.is_blank() {
@@ -309,7 +658,7 @@ impl<R: BufRead> PoReader<R> {
         }
 
         assert!(!unit._source.is_empty());
-        return Ok(Some(unit));
+        Ok(Some(unit))
     }
 
     fn next_unit(&mut self) -> Option<Result<Unit, Error>> {
@@ -320,37 +669,41 @@ impl<R: BufRead> PoReader<R> {
         }
     }
 
-    fn parse_po_header(&mut self) {
-        if let Some(Ok(ref u)) = self._next_unit {
-            for line in u._target.singular().unwrap_or("").split('\n') {
-                if let Some(n) = line.find(':') {
-                    let key = line[..n].trim();
-                    let val = line[(n+1)..].trim();
-                    self._header.insert(key.to_owned(), val.to_owned());
-                }
+    fn parse_po_header(&mut self, line: usize) {
+        if let Some(Ok(u)) = self._next_unit.take() {
+            self._header = PoHeader::from_unit(&u);
+            if let Some(lang) = self._header.language() {
+                self._target_language = lang;
             }
-            if let Some(lang) = self._header.get("Language") {
-                self._target_language
-                    = LanguageRange::new(lang)
-                    .map(LanguageRange::into_static)
-                    .or_else(|_| LanguageRange::from_unix(lang))
-                    .unwrap_or_else(|_| LanguageRange::invariant());
+            match self._header.plural_forms() {
+                Ok(Some(rule)) => self._plural_rule = rule,
+                Ok(None) => (),
+                Err(Error::Parse(_, got, exp)) => self._failed = Some(Error::Parse(line, got, exp)),
+                Err(e) => self._failed = Some(e),
             }
-            // FIXME FIXME: Extract plurals
+            self._header_unit = Some(u);
         }
+        self._plurals = self._plural_rule.categories(&self._target_language);
+    }
+
+    /// Get the parsed header. It is empty if the catalogue has no header entry.
+    pub fn header(&self) -> &PoHeader {
+        &self._header
     }
 }
 
 impl<R: BufRead> Iterator for PoReader<R> {
     type Item = Result<Unit, Error>;
     fn next(&mut self) -> Option<Result<Unit, Error>> {
-        if self._next_unit.is_none() {
-            return None;
+        if let Some(e) = self._failed.take() {
+            return Some(Err(e));
         }
 
+        self._next_unit.as_ref()?;
+
         let mut res = self.next_unit();
         ::std::mem::swap(&mut res, &mut self._next_unit);
-        return res;
+        res
     }
 }
 
@@ -358,17 +711,445 @@ impl<R: BufRead> CatalogueReader for PoReader<R> {
     fn target_language(&self) -> &LanguageRange<'static> {
         &self._target_language
     }
+
+    /// Get the header entry, if the catalogue has one.
+    ///
+    /// The header is the entry with empty `msgid` at the start of the catalogue. It is not
+    /// returned by the iterator, but is needed to write the catalogue back.
+    fn header_unit(&self) -> Option<&Unit> {
+        self._header_unit.as_ref()
+    }
+
+    fn properties(&self) -> Vec<(&str, &str)> {
+        self._header.fields().collect()
+    }
+}
+
+// Display width of a character; East Asian wide characters take two columns.
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x00..=0x1F | 0x7F..=0x9F | 0x0300..=0x036F | 0x200B..=0x200F => 0,
+        0x1100..=0x115F | 0x2E80..=0x303E | 0x3041..=0x33FF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF
+            | 0xA000..=0xA4CF | 0xAC00..=0xD7A3 | 0xF900..=0xFAFF | 0xFE30..=0xFE4F
+            | 0xFF00..=0xFF60 | 0xFFE0..=0xFFE6 | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+// Line breaking classes from Unicode Standard Annex #14, as far as they matter for texts found in
+// catalogues and grouped the way gettext treats them.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+enum BreakClass {
+    Alphabetic, Numeric, Ideographic, Space, Newline, Open, WideOpen, Close, CloseParen, Quote,
+    Infix, Hyphen, After, Prefix, Postfix, NonStarter, Combining,
+}
+
+fn break_class(c: char) -> BreakClass {
+    use self::BreakClass::*;
+    match c {
+        ' ' => Space,
+        '\n' => Newline,
+        '(' | '[' | '{' => Open,
+        '「' | '『' | '（' | '〈' | '《' | '【' | '〔' | '〖' | '〘' | '〚' => WideOpen,
+        ')' | ']' => CloseParen,
+        '}' | '!' | '?' | '/' | '、' | '。' | '，' | '．' | '」' | '』' | '）' | '〉' | '》' | '】'
+            | '〕' | '〗' | '〙' | '〛' | '！' | '？' => Close,
+        '"' | '\'' => Quote,
+        ',' | '.' | ':' | ';' => Infix,
+        '-' => Hyphen,
+        '|' => After,
+        '$' | '+' | '\\' | '£' | '€' | '¥' => Prefix,
+        '%' => Postfix,
+        '0'..='9' => Numeric,
+        'ぁ' | 'ぃ' | 'ぅ' | 'ぇ' | 'ぉ' | 'っ' | 'ゃ' | 'ゅ' | 'ょ' | 'ゎ' | 'ァ' | 'ィ' | 'ゥ' | 'ェ' | 'ォ'
+            | 'ッ' | 'ャ' | 'ュ' | 'ョ' | 'ヮ' | 'ヵ' | 'ヶ' | 'ー' | '々' | '〻' | 'ゝ' | 'ゞ' | 'ヽ' | 'ヾ'
+            | '・' | '：' | '；' => NonStarter,
+        c if char_width(c) == 2 => Ideographic,
+        _ => Alphabetic,
+    }
+}
+
+// Classes of the first and last character of `c` as written out. Escape sequences start with a
+// backslash, which matters for where the line can be broken around them.
+fn break_classes(c: char) -> (BreakClass, BreakClass) {
+    match c {
+        '\n' => (BreakClass::Newline, BreakClass::Alphabetic),
+        '\r' | '\t' | '\x07' | '\x08' | '\x0B' | '\x0C' =>
+            (BreakClass::Prefix, BreakClass::Alphabetic),
+        '"' => (BreakClass::Prefix, BreakClass::Quote),
+        // other control characters are written as they are and behave as combining marks
+        '\x00'..='\x1F' | '\x7F'..='\u{9F}' => (BreakClass::Combining, BreakClass::Combining),
+        c => (break_class(c), break_class(c)),
+    }
+}
+
+// Whether a line may be broken between a character ending with class `a` and one starting with
+// class `b`, with `last` being the class of the last non-space character before `b`.
+fn can_break(last: BreakClass, a: BreakClass, b: BreakClass) -> bool {
+    use self::BreakClass::*;
+    match (a, b) {
+        (_, Newline) | (_, Space) | (_, Close) | (_, CloseParen) | (_, Infix) | (_, Combining) => false,
+        (Space, _) => !(last == Open || last == WideOpen
+                        || (last == Close || last == CloseParen) && b == NonStarter),
+        (_, Quote) | (_, Hyphen) | (_, After) | (_, NonStarter) => false,
+        (Open, _) | (WideOpen, _) | (Quote, _) => false,
+        (_, WideOpen) => true,
+        (Alphabetic, b) | (Numeric, b) => b == Ideographic,
+        (Prefix, b) => b == Open || b == Prefix || b == Postfix,
+        (CloseParen, b) | (Postfix, b) | (Infix, b) => b != Alphabetic && b != Numeric,
+        (Hyphen, b) => b != Numeric,
+        (Ideographic, b) => b != Postfix,
+        _ => true,
+    }
+}
+
+fn escape(c: char) -> Option<&'static str> {
+    match c {
+        '\n' => Some(r"\n"),
+        '\r' => Some(r"\r"),
+        '\t' => Some(r"\t"),
+        '\x07' => Some(r"\a"),
+        '\x08' => Some(r"\b"),
+        '\x0B' => Some(r"\v"),
+        '\x0C' => Some(r"\f"),
+        '"' => Some("\\\""),
+        '\\' => Some(r"\\"),
+        _ => None,
+    }
+}
+
+/// Writer for PO files.
+///
+/// Writes units in the same layout as GNU gettext tools do, including wrapping of long strings,
+/// so catalogues processed by both produce no spurious differences. The output is always UTF-8.
+///
+/// The plural variants are ordered according to the `Language` and `Plural-Forms` fields of the
+/// header entry, which should therefore be written first. The header is optional in PO, but if it
+/// is written, its `Content-Type` is changed to declare UTF-8.
+pub struct PoWriter<W: Write> {
+    _writer: W,
+    _width: usize,
+    _plurals: Vec<Count>,
+    _first: bool,
+    _finished: bool,
+}
+
+impl<W: Write> PoWriter<W> {
+    pub fn new(writer: W) -> Self {
+        PoWriter {
+            _writer: writer,
+            _width: 79,
+            _plurals: PluralRule::default().categories(&LanguageRange::invariant()),
+            _first: true,
+            _finished: false,
+        }
+    }
+
+    /// Set the page width strings are wrapped to. The default is 79, like in gettext; 0 disables
+    /// wrapping, so strings are only split after newlines, like with `--no-wrap`.
+    pub fn set_width(&mut self, width: usize) {
+        self._width = if width == 0 { usize::MAX } else { width };
+    }
+
+    /// Get the wrapped writer back.
+    pub fn into_inner(self) -> W {
+        self._writer
+    }
+
+    fn write_po_unit(&mut self, unit: &Unit) -> io::Result<()> {
+        let width = self._width;
+        if unit.flags().contains(&Flag::NoWrap) {
+            self._width = usize::MAX;
+        }
+        let res = self.write_po_entry(unit);
+        self._width = width;
+        res
+    }
+
+    fn write_po_entry(&mut self, unit: &Unit) -> io::Result<()> {
+        if !self._first {
+            writeln!(self._writer)?;
+        }
+        self._first = false;
+
+        for note in unit.notes().iter().filter(|n| n.0 != Origin::Developer) {
+            self.write_comment("#", &note.1)?;
+        }
+        for note in unit.notes().iter().filter(|n| n.0 == Origin::Developer) {
+            self.write_comment("#.", &note.1)?;
+        }
+        self.write_locations(unit.locations())?;
+        self.write_flags(unit)?;
+
+        let (prefix, prev_prefix) = if unit.is_obsolete() { ("#~ ", "#~| ") } else { ("", "#| ") };
+        if let Some(ref ctx) = *unit.prev_context() {
+            self.write_msg(prev_prefix, "msgctxt", ctx)?;
+        }
+        self.write_source(prev_prefix, unit.prev_source())?;
+        if let Some(ref ctx) = *unit.context() {
+            self.write_msg(prefix, "msgctxt", ctx)?;
+        }
+        self.write_source(prefix, unit.source())?;
+        if unit.source().is_plural() {
+            for (i, c) in self._plurals.clone().iter().enumerate() {
+                let s = match *unit.target() {
+                    Message::Plural(ref map) => map.get(c).map(String::as_str).unwrap_or(""),
+                    _ => "",
+                };
+                self.write_msg(prefix, &format!("msgstr[{}]", i), s)?;
+            }
+        } else {
+            self.write_msg(prefix, "msgstr", unit.target().singular().unwrap_or(""))?;
+        }
+        Ok(())
+    }
+
+    // Write the flags in the order gettext uses.
+    fn write_flags(&mut self, unit: &Unit) -> io::Result<()> {
+        let mut flags = unit.flags().iter().collect::<Vec<_>>();
+        flags.sort_by_key(|f| match **f {
+            Flag::Format(ref lang) | Flag::NoFormat(ref lang) =>
+                (1, FORMAT_LANGUAGES.iter().position(|l| l == lang).unwrap_or(FORMAT_LANGUAGES.len())),
+            Flag::Range(..) => (2, 0),
+            Flag::NoWrap | Flag::Wrap => (3, 0),
+            Flag::Other(_) => (4, 0),
+        });
+        let mut flags = flags.into_iter().map(Flag::to_string).collect::<Vec<_>>();
+        if unit.state() == State::NeedsWork {
+            flags.insert(0, String::from("fuzzy"));
+        }
+        if !flags.is_empty() {
+            writeln!(self._writer, "#, {}", flags.join(", "))?;
+        }
+        Ok(())
+    }
+
+    fn write_comment(&mut self, kind: &str, text: &str) -> io::Result<()> {
+        for line in text.split('\n') {
+            if line.is_empty() {
+                writeln!(self._writer, "{}", kind)?;
+            } else {
+                writeln!(self._writer, "{} {}", kind, line)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_locations(&mut self, locations: &[String]) -> io::Result<()> {
+        let mut col = 0;
+        for loc in locations {
+            if col > 0 && col + 1 + loc.len() > self._width {
+                writeln!(self._writer)?;
+                col = 0;
+            }
+            if col == 0 {
+                write!(self._writer, "#:")?;
+                col = 2;
+            }
+            write!(self._writer, " {}", loc)?;
+            col += 1 + loc.len();
+        }
+        if col > 0 {
+            writeln!(self._writer)?;
+        }
+        Ok(())
+    }
+
+    fn write_source(&mut self, prefix: &str, source: &Message) -> io::Result<()> {
+        match *source {
+            Message::Empty => Ok(()),
+            Message::Singular(ref s) => self.write_msg(prefix, "msgid", s),
+            Message::Plural(ref map) => {
+                let mut forms = map.values();
+                let one = map.get(&Count::One).or_else(|| forms.next());
+                let other = map.get(&Count::Other).or_else(|| forms.last());
+                self.write_msg(prefix, "msgid", one.map(String::as_str).unwrap_or(""))?;
+                self.write_msg(prefix, "msgid_plural", other.map(String::as_str).unwrap_or(""))
+            }
+        }
+    }
+
+    // Write a string with keyword. Like in gettext, it goes on the same line as the keyword if it
+    // fits and does not contain newlines except at the end. Otherwise the first line is empty and
+    // the string follows split after newlines and wrapped at the page width.
+    fn write_msg(&mut self, prefix: &str, keyword: &str, value: &str) -> io::Result<()> {
+        let mut portions = Vec::new();
+        let mut rest = value;
+        while let Some(n) = rest.find('\n').filter(|&n| n + 1 < rest.len()) {
+            portions.push(&rest[..(n + 1)]);
+            rest = &rest[(n + 1)..];
+        }
+        portions.push(rest);
+
+        let first_col = prefix.len() + keyword.len() + 2;
+        if portions.len() == 1 && self.wrap(value, first_col).is_empty() {
+            return writeln!(self._writer, "{}{} \"{}\"", prefix, keyword, escape_str(value));
+        }
+
+        writeln!(self._writer, "{}{} \"\"", prefix, keyword)?;
+        for portion in portions {
+            let mut start = 0;
+            let breaks = self.wrap(portion, prefix.len() + 1);
+            for end in breaks.into_iter().chain(Some(portion.len())) {
+                writeln!(self._writer, "{}\"{}\"", prefix, escape_str(&portion[start..end]))?;
+                start = end;
+            }
+        }
+        Ok(())
+    }
+
+    // Find where to break the string so that each line fits in the page width, starting at given
+    // column and leaving space for the closing quote. Returns byte offsets of the breaks.
+    fn wrap(&self, s: &str, start_col: usize) -> Vec<usize> {
+        use self::BreakClass::*;
+        let max = self._width.saturating_sub(1);
+        let mut res = Vec::new();
+        let mut col = start_col;
+        let mut last_break = None;
+        let mut prev = None;
+        let mut word_start = true;
+        let mut last = Space;
+        for (i, c) in s.char_indices() {
+            // combining marks take the class of the preceding character, except after space
+            let (first, end) = match (break_classes(c), prev) {
+                ((Combining, _), Some(p)) if p != Space => (Combining, p),
+                ((Combining, _), _) => (Alphabetic, Alphabetic),
+                (classes, _) => classes,
+            };
+            // no break after hyphen at start of word, like in "-x"
+            let word_hyphen = word_start && prev == Some(Hyphen) && first == Alphabetic;
+            if prev.is_some_and(|p| can_break(last, p, first)) && !word_hyphen {
+                last_break = Some((i, col));
+            }
+            if end != Space {
+                last = end;
+            }
+            col += escape(c).map_or_else(|| char_width(c), str::len);
+            if col > max {
+                if let Some((b, b_col)) = last_break.take() {
+                    res.push(b);
+                    col = start_col + col - b_col;
+                }
+            }
+            // leading spaces are not a break opportunity
+            if prev.is_some() || c != ' ' {
+                word_start = prev.is_none_or(|p| p == Space);
+                prev = Some(end);
+            }
+        }
+        res
+    }
+}
+
+
+impl<W: Write> CatalogueWriter for PoWriter<W> {
+    fn write_header(&mut self, target_language: &LanguageRange, header: Option<&Unit>)
+            -> Result<(), Error> {
+        if !self._first {
+            return Err(Error::Sequence("header written after units"));
+        }
+        let mut unit = header.cloned().unwrap_or_default();
+        let text = match header {
+            Some(h) => h.target().singular().unwrap_or("").to_owned(),
+            None => DEFAULT_HEADER.to_owned(),
+        };
+        unit._source = Message::Singular(String::new());
+        unit._target = Message::Singular(header_text(&text, target_language));
+        self.write_unit(&unit)
+    }
+
+    /// Write a unit.
+    ///
+    /// If the unit is the header entry, plural rule for the following units is taken from it.
+    fn write_unit(&mut self, unit: &Unit) -> Result<(), Error> {
+        if self._finished {
+            return Err(Error::Sequence("unit written after finish"));
+        }
+        if unit.source().is_singular() && unit.source().is_blank() && !unit.is_obsolete() {
+            let mut lang = LanguageRange::invariant();
+            let mut rule = PluralRule::default();
+            for (key, val) in header_fields(unit.target().singular().unwrap_or("")) {
+                match key {
+                    "Language" => lang = parse_language(val),
+                    "Plural-Forms" => rule = PluralRule::parse(val)?,
+                    _ => (),
+                }
+            }
+            self._plurals = rule.categories(&lang);
+        } else if let Message::Plural(ref map) = *unit.target() {
+            if let Some(c) = map.keys().find(|c| !self._plurals.contains(c)) {
+                return Err(Error::Unsupported(
+                        format!("plural form {:?} not used by the catalogue plural rule", c)));
+            }
+        }
+        self.write_po_unit(unit).map_err(|e| Error::Io(0, e))
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        self._finished = true;
+        self._writer.flush().map_err(|e| Error::Io(0, e))
+    }
+}
+
+// Format string languages known to gettext, in the order it writes their flags.
+static FORMAT_LANGUAGES: &[&str] = &[
+    "c", "objc", "c++", "python", "python-brace", "java", "java-printf", "csharp", "javascript",
+    "scheme", "lisp", "elisp", "librep", "rust", "go", "ruby", "sh", "awk", "lua", "object-pascal",
+    "modula2", "d", "smalltalk", "qt", "qt-plural", "kde", "kde-kuit", "boost", "tcl", "perl",
+    "perl-brace", "php", "gcc-internal", "gfc-internal", "ycp",
+];
+
+static DEFAULT_HEADER: &str =
+    "MIME-Version: 1.0\nContent-Type: text/plain; charset=UTF-8\nContent-Transfer-Encoding: 8bit\n";
+
+// Header text with the Language field set, if not already present, and the charset changed to
+// UTF-8, which is what the writer produces.
+pub(crate) fn header_text(text: &str, lang: &LanguageRange) -> String {
+    let mut res = String::with_capacity(text.len() + 40);
+    let mut need_lang = *lang != LanguageRange::invariant()
+        && !header_fields(text).any(|(k, _)| k == "Language");
+    for line in text.split_terminator('\n') {
+        let key = line.split(':').next().unwrap_or("").trim();
+        if need_lang && ["MIME-Version", "Content-Type", "Content-Transfer-Encoding", "Plural-Forms"]
+                .contains(&key) {
+            res.push_str(&format!("Language: {}\n", lang.as_ref().replace('-', "_")));
+            need_lang = false;
+        }
+        if key == "Content-Type" {
+            res.push_str("Content-Type: text/plain; charset=UTF-8\n");
+        } else {
+            res.push_str(line);
+            res.push('\n');
+        }
+    }
+    if need_lang {
+        res.push_str(&format!("Language: {}\n", lang.as_ref().replace('-', "_")));
+    }
+    res
+}
+
+fn escape_str(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        match escape(c) {
+            Some(e) => res.push_str(e),
+            None => res.push(c),
+        }
+    }
+    res
 }
 
 #[cfg(test)]
 mod tests {
-    use ::CatalogueReader;
+    use ::{CatalogueReader, CatalogueWriter, Flag, State, UnitBuilder};
+    use ::Count::*;
+    use ::encoding_rs::ISO_8859_2;
     use ::locale_config::LanguageRange;
     use ::Message::*;
     use ::Origin::*;
-    use super::PoReader;
+    use super::{PoHeader, PoReader, PoWriter};
 
-    static SAMPLE_PO: &'static str = r###"
+    static SAMPLE_PO: &str = r###"
 msgid ""
 msgstr ""
 "Project-Id-Version: translate-storage test\n"
@@ -406,9 +1187,12 @@ msgstr ""
 
     #[test]
     fn integration_test() {
-        let mut reader = PoReader::new(SAMPLE_PO.as_ref());
+        let (sample, _, _) = ISO_8859_2.encode(SAMPLE_PO);
+        let mut reader = PoReader::new(sample.as_ref());
 
         assert_eq!(LanguageRange::new("cs").unwrap(), *reader.target_language());
+        assert_eq!(3, reader._plural_rule.nplurals());
+        assert_eq!(vec![One, Few, Other], reader._plurals);
         
         let u1 = reader.next().unwrap().unwrap();
         assert_eq!(None, *u1.context());
@@ -468,4 +1252,435 @@ msgstr ""
 
         assert!(reader.next().is_none());
     }
+
+    static PLURAL_PO: &str = r###"
+msgid ""
+msgstr ""
+"Language: pl\n"
+"Plural-Forms: nplurals=3; plural=(n==1 ? 0 : n%10>=2 && n%10<=4 && (n%100<10 || n%100>=20) ? 1 : 2);\n"
+
+#, fuzzy
+#| msgid "One file"
+#| msgid_plural "%d files"
+msgid "%d file"
+msgid_plural "%d files"
+msgstr[0] "%d plik"
+msgstr[1] "%d pliki"
+msgstr[2] "%d plików"
+
+msgid "%d line"
+msgid_plural "%d lines"
+msgstr[0] ""
+msgstr[1] ""
+msgstr[2] ""
+
+#~ msgid "%d byte"
+#~ msgid_plural "%d bytes"
+#~ msgstr[0] "%d bajt"
+#~ msgstr[1] "%d bajty"
+#~ msgstr[2] "%d bajtów"
+
+msgid "%d word"
+msgid_plural "%d words"
+msgstr[0] "%d słowo"
+msgstr[1] "%d słowa"
+
+msgid "Simple message"
+msgstr "Prosta wiadomość"
+"###;
+
+    #[test]
+    fn plurals() {
+        let mut reader = PoReader::new(PLURAL_PO.as_bytes());
+
+        let plural = |s: &[(::Count, &str)]| Plural(s.iter().map(|&(c, s)| (c, s.to_owned())).collect());
+
+        let u1 = reader.next().unwrap().unwrap();
+        assert_eq!(plural(&[(One, "%d file"), (Other, "%d files")]), *u1.source());
+        assert_eq!(plural(&[(One, "%d plik"), (Few, "%d pliki"), (Other, "%d plików")]), *u1.target());
+        assert_eq!(plural(&[(One, "One file"), (Other, "%d files")]), *u1.prev_source());
+        assert_eq!(::State::NeedsWork, u1.state());
+
+        let u2 = reader.next().unwrap().unwrap();
+        assert_eq!(plural(&[(One, ""), (Few, ""), (Other, "")]), *u2.target());
+        assert_eq!(::State::Empty, u2.state());
+
+        let u3 = reader.next().unwrap().unwrap();
+        assert_eq!(plural(&[(One, "%d byte"), (Other, "%d bytes")]), *u3.source());
+        assert_eq!(plural(&[(One, "%d bajt"), (Few, "%d bajty"), (Other, "%d bajtów")]), *u3.target());
+        assert!(u3.is_obsolete());
+
+        match reader.next() {
+            Some(Err(::Error::PluralForms(30, 3, 2))) => (),
+            x => panic!("Unexpected {:?}", x),
+        }
+
+        let u5 = reader.next().unwrap().unwrap();
+        assert_eq!(Singular("Prosta wiadomość".to_owned()), *u5.target());
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn charsets() {
+        let po = |charset, text| format!(
+            "msgid \"\"\nmsgstr \"\"\n\"Content-Type: text/plain; charset={}\\n\"\n\n\
+             msgid \"Message\"\nmsgstr \"{}\"\n", charset, text).into_bytes();
+        let first_target = |bytes: &[u8]| PoReader::new(bytes).next().unwrap().map(|u| u.target().clone());
+
+        let mut koi8 = po("KOI8-R", "");
+        koi8.truncate(koi8.len() - 2);
+        koi8.extend_from_slice(b"\xF3\xCF\xCF\xC2\xDD\xC5\xCE\xC9\xC5\"\n");
+        assert_eq!(Singular("Сообщение".to_owned()), first_target(&koi8).unwrap());
+
+        let mut euc_jp = po("EUC-JP", "");
+        euc_jp.truncate(euc_jp.len() - 2);
+        euc_jp.extend_from_slice(b"\xA5\xE1\xA5\xC3\xA5\xBB\xA1\xBC\xA5\xB8\"\n");
+        assert_eq!(Singular("メッセージ".to_owned()), first_target(&euc_jp).unwrap());
+
+        // no declaration means UTF-8
+        assert_eq!(Singular("Zpráva".to_owned()),
+                   first_target(b"msgid \"Message\"\nmsgstr \"Zpr\xC3\xA1va\"\n").unwrap());
+
+        let mut bad_utf8 = po("UTF-8", "");
+        bad_utf8.truncate(bad_utf8.len() - 2);
+        bad_utf8.extend_from_slice(b"Zpr\xE1va\"\n");
+        match first_target(&bad_utf8) {
+            Err(::Error::Decode(6, ref enc)) => assert_eq!("UTF-8", enc),
+            x => panic!("Unexpected {:?}", x),
+        }
+
+        match first_target(&po("NO-SUCH-CHARSET", "Zpráva")) {
+            Err(::Error::Decode(3, ref enc)) => assert_eq!("NO-SUCH-CHARSET", enc),
+            x => panic!("Unexpected {:?}", x),
+        }
+
+        // only the header entry declares the charset
+        let not_header = "msgid \"\"\nmsgstr \"Project-Id-Version: x\\n\"\n\n\
+                          msgid \"Content-Type: text/plain; charset=KOI8-R\\n\"\n\
+                          msgstr \"Zpráva\"\n";
+        assert_eq!(Singular("Zpráva".to_owned()), first_target(not_header.as_bytes()).unwrap());
+        let not_header = "msgid \"Message\"\nmsgstr \"Content-Type: text/plain; charset=KOI8-R\\n\"\n";
+        assert_eq!(Singular("Content-Type: text/plain; charset=KOI8-R\n".to_owned()),
+                   first_target(not_header.as_bytes()).unwrap());
+
+        // the declaration can be split, and the file can have CRLF line ends
+        let mut split = b"# Comment\r\nmsgid \"\"\r\nmsgstr \"\"\r\n\"Content-Type: text/plain; \"\r\n\
+                          \"charset=ISO-8859-2\\n\"\r\n\r\nmsgid \"Message\"\r\nmsgstr \"Zpr".to_vec();
+        split.extend_from_slice(b"\xE1va\"\r\n");
+        assert_eq!(Singular("Zpráva".to_owned()), first_target(&split).unwrap());
+        let bom = "\u{FEFF}# Comment\nmsgid \"\"\nmsgstr \"Content-Type: text/plain; charset=UTF-8\\n\"\n\n\
+                   msgid \"Message\"\nmsgstr \"Zpráva\"\n";
+        assert_eq!(Singular("Zpráva".to_owned()), first_target(bom.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn bad_plural_forms() {
+        let mut reader = PoReader::new(r#"
+msgid ""
+msgstr ""
+"Language: cs\n"
+"Plural-Forms: nplurals=3; plural=(n==1) ? 0 : 1 2;\n"
+
+msgid "Simple message"
+msgstr "Jednoduchá zpráva"
+"#.as_bytes());
+        match reader.next() {
+            Some(Err(::Error::Parse(2, Some(ref got), _))) => assert_eq!("2", got),
+            x => panic!("Unexpected {:?}", x),
+        }
+        assert!(reader.next().unwrap().is_ok());
+        assert!(reader.next().is_none());
+    }
+
+    static WRAPPED_PO: &str = r###"msgid ""
+msgstr ""
+"Project-Id-Version: translate-storage test\n"
+"Language: cs\n"
+"MIME-Version: 1.0\n"
+"Content-Type: text/plain; charset=UTF-8\n"
+"Content-Transfer-Encoding: 8bit\n"
+"Plural-Forms: nplurals=3; plural=(n==1) ? 0 : (n>=2 && n<=4) ? 1 : 2;\n"
+
+# Translator comment
+#. Extracted comment
+#: src/main.rs:42 src/main.rs:69 src/some/rather/long/path/to/a/module.rs:123
+#: src/lib.rs:1
+#, fuzzy
+#| msgctxt "menu"
+#| msgid "Open a \"file\""
+msgctxt "menu"
+msgid ""
+"Open a \"file\" or a directory, which is a rather long message that needs to "
+"be wrapped\n"
+msgstr "Otevřít \"soubor\"\tnebo adresář"
+
+msgid "%d file"
+msgid_plural "%d files"
+msgstr[0] "%d soubor"
+msgstr[1] "%d soubory"
+msgstr[2] "%d souborů"
+
+msgid ""
+"これは日本語のとても長いメッセージです。行の幅を超えると、単語の区切りがなく"
+"ても折り返されます。"
+msgstr ""
+
+#~ msgid ""
+#~ "Obsolete\n"
+#~ "message"
+#~ msgstr ""
+#~ "Zastaralá\n"
+#~ "zpráva"
+"###;
+
+    #[test]
+    fn quoted_comments_and_escapes() {
+        // Translator comment with a quoted string is not a message line, and all the escapes in
+        // a string are resolved, not just the first one.
+        let po = r#"# "Save" as in "Save file"
+msgid "Line one\nLine two\n"
+msgstr "Řádek jedna\nŘádek dva\n"
+"#;
+        let unit = PoReader::new(po.as_bytes()).next().unwrap().unwrap();
+        assert_eq!(vec![(Translator, "\"Save\" as in \"Save file\"".to_owned())], *unit.notes());
+        assert_eq!(Singular("Line one\nLine two\n".to_owned()), *unit.source());
+        assert_eq!(Singular("Řádek jedna\nŘádek dva\n".to_owned()), *unit.target());
+
+        let mut writer = PoWriter::new(Vec::new());
+        writer.write_unit(&unit).unwrap();
+        assert_eq!(r#"# "Save" as in "Save file"
+msgid ""
+"Line one\n"
+"Line two\n"
+msgstr ""
+"Řádek jedna\n"
+"Řádek dva\n"
+"#, String::from_utf8(writer.into_inner()).unwrap());
+    }
+
+    #[test]
+    fn write_po() {
+        let mut reader = PoReader::new(WRAPPED_PO.as_bytes());
+        let mut writer = PoWriter::new(Vec::new());
+        writer.write_header(&reader.target_language().clone(), reader.header_unit()).unwrap();
+        for unit in &mut reader {
+            writer.write_unit(&unit.unwrap()).unwrap();
+        }
+        writer.finish().unwrap();
+        assert_eq!(WRAPPED_PO, String::from_utf8(writer.into_inner()).unwrap());
+
+        let mut unit = reader.header_unit().unwrap().clone();
+        unit._target = Singular(
+            "First line\nSecond line, which is long enough to be wrapped at the default width\n".to_owned());
+        let mut writer = PoWriter::new(Vec::new());
+        writer.set_width(0);
+        writer.write_unit(&unit).unwrap();
+        assert_eq!("msgid \"\"\nmsgstr \"\"\n\"First line\\n\"\n\
+                    \"Second line, which is long enough to be wrapped at the default width\\n\"\n",
+                   String::from_utf8(writer.into_inner()).unwrap());
+    }
+
+    #[test]
+    fn wrap_word_initial_hyphen() {
+        let unit = ::Unit {
+            _source: Singular("Run the program with -verbose".to_owned()),
+            ..::Unit::default()
+        };
+        let mut writer = PoWriter::new(Vec::new());
+        writer.set_width(25);
+        writer.write_unit(&unit).unwrap();
+        assert_eq!("msgid \"\"\n\"Run the program with \"\n\"-verbose\"\nmsgstr \"\"\n",
+                   String::from_utf8(writer.into_inner()).unwrap());
+    }
+
+    #[test]
+    fn write_header() {
+        let (sample, _, _) = ISO_8859_2.encode(SAMPLE_PO);
+        let reader = PoReader::new(sample.as_ref());
+        let mut writer = PoWriter::new(Vec::new());
+        writer.write_header(reader.target_language(), reader.header_unit()).unwrap();
+        let out = String::from_utf8(writer.into_inner()).unwrap();
+        assert!(out.contains("\"Content-Type: text/plain; charset=UTF-8\\n\"\n"));
+        assert!(out.contains("\"Language: cs\\n\"\n"));
+
+        let mut writer = PoWriter::new(Vec::new());
+        writer.write_header(&LanguageRange::new("pt-BR").unwrap(), None).unwrap();
+        let unit = reader.header_unit().unwrap();
+        match writer.write_header(&LanguageRange::invariant(), Some(unit)) {
+            Err(::Error::Sequence(_)) => (),
+            x => panic!("Unexpected {:?}", x),
+        }
+        match writer.write_unit(&PoReader::new(PLURAL_PO.as_bytes()).next().unwrap().unwrap()) {
+            Err(::Error::Unsupported(_)) => (),
+            x => panic!("Unexpected {:?}", x),
+        }
+        assert_eq!("msgid \"\"\nmsgstr \"\"\n\"Language: pt_BR\\n\"\n\"MIME-Version: 1.0\\n\"\n\
+                    \"Content-Type: text/plain; charset=UTF-8\\n\"\n\
+                    \"Content-Transfer-Encoding: 8bit\\n\"\n",
+                   String::from_utf8(writer.into_inner()).unwrap());
+    }
+
+    #[test]
+    fn po_header() {
+        let (sample, _, _) = ISO_8859_2.encode(SAMPLE_PO);
+        let reader = PoReader::new(sample.as_ref());
+        assert_eq!(Some("translate-storage test"), reader.project());
+        assert_eq!(reader.header().po_revision_date(), reader.revision_date());
+        assert_eq!(None, reader.generator());
+        assert_eq!(Some("Czech"), reader.property("Language-Team"));
+        assert_eq!(9, reader.properties().len());
+        assert_eq!(None, reader.source_language());
+        let header = reader.header();
+        assert_eq!(Some("translate-storage test"), header.project_id_version());
+        assert_eq!(None, header.report_msgid_bugs_to());
+        assert_eq!("2017-04-24T21:39:00+02:00", header.po_revision_date().unwrap().to_rfc3339());
+        assert_eq!(None, header.pot_creation_date());
+        assert_eq!(Some("Jan Hudec <bulb@ucw.cz>"), header.last_translator());
+        assert_eq!(Some("Czech"), header.language_team());
+        assert_eq!(Some(LanguageRange::new("cs").unwrap()), header.language());
+        assert_eq!(Some("ISO-8859-2"), header.charset());
+        assert_eq!(3, header.plural_forms().unwrap().unwrap().nplurals());
+
+        let mut header = PoHeader::from_unit(PoReader::new(r#"# Czech translation.
+# Copyright (C) 2017 Jan Hudec
+#
+#, fuzzy
+msgid ""
+msgstr ""
+"Project-Id-Version: PACKAGE VERSION\n"
+"POT-Creation-Date: 2017-05-01 10:00+0000\n"
+"PO-Revision-Date: YEAR-MO-DA HO:MI+ZONE\n"
+"X-Generator: Poedit 2.0\n"
+"Content-Type: text/plain; charset=CHARSET\n"
+"X-Poedit-Basepath: ..\n"
+"#.as_bytes()).header_unit().unwrap());
+        assert_eq!(&["Czech translation.", "Copyright (C) 2017 Jan Hudec", ""], header.comments());
+        assert_eq!(None, header.charset());
+        assert_eq!(None, header.po_revision_date());
+        assert!(header.plural_forms().unwrap().is_none());
+        assert_eq!(vec![("X-Generator", "Poedit 2.0"), ("X-Poedit-Basepath", "..")],
+                   header.custom_fields().collect::<Vec<_>>());
+
+        let date = header.pot_creation_date().unwrap();
+        header.set_po_revision_date(&date);
+        header.set("Language", "cs");
+        header.set("Project-Id-Version", "0.1");
+        assert_eq!(Some("0.1".to_owned()), header.remove("Project-Id-Version"));
+        assert_eq!("POT-Creation-Date: 2017-05-01 10:00+0000\nPO-Revision-Date: 2017-05-01 10:00+0000\n\
+                    X-Generator: Poedit 2.0\nContent-Type: text/plain; charset=CHARSET\n\
+                    X-Poedit-Basepath: ..\nLanguage: cs\n", header.to_string());
+        assert_eq!(header, PoHeader::from_unit(&header.to_unit()));
+    }
+
+    #[test]
+    fn escapes() {
+        let po = r#"msgid "Escapes"
+msgstr "\a\b\f\v\t\r\n\"\\ \033[0m\x1B[0m\x41\101\1234\x123 \303\xA9"
+"#;
+        let unit = PoReader::new(po.as_bytes()).next().unwrap().unwrap();
+        let expected = "\x07\x08\x0C\x0B\t\r\n\"\\ \x1B[0m\x1B[0mAAS4# é";
+        assert_eq!(Singular(expected.to_owned()), *unit.target());
+
+        let mut writer = PoWriter::new(Vec::new());
+        writer.write_unit(&unit).unwrap();
+        assert_eq!("msgid \"Escapes\"\nmsgstr \"\"\n\"\\a\\b\\f\\v\\t\\r\\n\"\n\
+                    \"\\\"\\\\ \x1B[0m\x1B[0mAAS4# é\"\n",
+                   String::from_utf8(writer.into_inner()).unwrap());
+
+        for &(line, error) in &[
+                (r#"msgstr "a\?b""#, r"\? at column 10"),
+                (r#"msgstr "žluť\e""#, r"\e at column 13"),
+                (r#"msgstr "\xZZ""#, r"\x at column 9"),
+                (r#"  "a\""#, r"\ at column 5"),
+                (r#"msgstr "\xC3""#, "escaped bytes that are not UTF-8 at column 9"),
+                (r#"msgstr "ok \n\xC3 \xA9""#, "escaped bytes that are not UTF-8 at column 12")] {
+            match PoReader::new(format!("msgid \"x\"\n{}\n", line).as_bytes()).next() {
+                Some(Err(::Error::Parse(2, Some(ref e), _))) if e == error => (),
+                x => panic!("Unexpected {:?} for {}", x, line),
+            }
+        }
+
+        // escaped bytes are in the encoding of the file
+        let po = r#"msgid ""
+msgstr "Content-Type: text/plain; charset=ISO-8859-2\n"
+
+msgid "Escapes"
+msgstr "\xB9\251 \xE8"
+"#;
+        let mut reader = PoReader::new(po.as_bytes());
+        let unit = reader.next().unwrap().unwrap();
+        assert_eq!(Singular("šŠ č".to_owned()), *unit.target());
+    }
+
+    #[test]
+    fn flags() {
+        let po = r#"#, no-wrap, custom-flag, range: 1..5, python-format, fuzzy, no-c-format
+msgid "%s: a very long message that would certainly be wrapped without the no-wrap flag"
+msgstr ""
+
+#, wrap,  range:0..10 , rust-format
+msgid "x"
+msgstr "y"
+"#;
+        let mut reader = PoReader::new(po.as_bytes());
+        let u1 = reader.next().unwrap().unwrap();
+        assert_eq!(vec![Flag::NoWrap, Flag::Other("custom-flag".to_owned()), Flag::Range(1, 5),
+                        Flag::Format("python".to_owned()), Flag::NoFormat("c".to_owned())],
+                   *u1.flags());
+        assert_eq!(Some("python"), u1.format());
+        assert_eq!(::State::NeedsWork, u1.state());
+        let u2 = reader.next().unwrap().unwrap();
+        assert_eq!(vec![Flag::Wrap, Flag::Range(0, 10), Flag::Format("rust".to_owned())], *u2.flags());
+        assert_eq!(Flag::Other("range: 10..1".to_owned()), Flag::parse("range: 10..1"));
+
+        let mut writer = PoWriter::new(Vec::new());
+        writer.write_unit(&u1).unwrap();
+        writer.write_unit(&u2).unwrap();
+        assert_eq!(r#"#, fuzzy, no-c-format, python-format, range: 1..5, no-wrap, custom-flag
+msgid "%s: a very long message that would certainly be wrapped without the no-wrap flag"
+msgstr ""
+
+#, rust-format, range: 0..10, wrap
+msgid "x"
+msgstr "y"
+"#, String::from_utf8(writer.into_inner()).unwrap());
+    }
+
+    #[test]
+    fn write_built_units() {
+        let mut plural = ::std::collections::BTreeMap::new();
+        plural.insert(One, "%d file".to_owned());
+        plural.insert(Other, "%d files".to_owned());
+        let mut target = ::std::collections::BTreeMap::new();
+        target.insert(One, "%d soubor".to_owned());
+        target.insert(Other, "%d souborů".to_owned());
+        let u1 = UnitBuilder::new(Plural(plural))
+            .context("dialog")
+            .target(Plural(target))
+            .state(State::NeedsWork)
+            .note(Translator, "Check the plural")
+            .location("src/main.c:10")
+            .flag(Flag::Format("c".to_owned()))
+            .flag(Flag::Format("c".to_owned()))
+            .build().unwrap();
+        let u2 = UnitBuilder::new("Old").target("Starý").prev_source("Older").obsolete(true)
+            .build().unwrap();
+
+        let mut writer = PoWriter::new(Vec::new());
+        writer.write_unit(&u1).unwrap();
+        writer.write_unit(&u2).unwrap();
+        assert_eq!(r#"# Check the plural
+#: src/main.c:10
+#, fuzzy, c-format
+msgctxt "dialog"
+msgid "%d file"
+msgid_plural "%d files"
+msgstr[0] "%d soubor"
+msgstr[1] "%d souborů"
+
+#~| msgid "Older"
+#~ msgid "Old"
+#~ msgstr "Starý"
+"#, String::from_utf8(writer.into_inner()).unwrap());
+    }
 }
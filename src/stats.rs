@@ -0,0 +1,341 @@
+//! Catalogue statistics
+//!
+//! Counts of units and words by translation state, like `msgfmt --statistics` or `pocount` from
+//! [translate-toolkit][tt] report, for tracking the progress of translation. Statistics can also
+//! be broken down by location prefixes, e.g. per directory of the project, so progress of its
+//! parts can be tracked separately.
+//!
+//! ```
+//! use translate_storage::catalogue::Catalogue;
+//! use translate_storage::po::PoReader;
+//! use translate_storage::stats::Counter;
+//!
+//! let po = "#: src/ui/menu.rs:10\nmsgid \"Open %s\"\nmsgstr \"Otevřít %s\"\n\n\
+//!           #: src/cli.rs:5\nmsgid \"Print the help\"\nmsgstr \"\"\n";
+//! let cat = Catalogue::from_reader(PoReader::new(po.as_bytes())).unwrap();
+//! let stats = Counter::new().prefix("src/ui/").count_catalogue(&cat);
+//! assert_eq!(1, stats.total().translated().units);
+//! assert_eq!(3, stats.total().untranslated().source_words);
+//! assert_eq!(1, stats.prefix("src/ui/").unwrap().total().units);
+//! ```
+//!
+//! [tt]: http://toolkit.translatehouse.org/
+
+use catalogue::Catalogue;
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::AddAssign;
+use super::{CatalogueReader, Error, Message, State, Unit};
+
+lazy_static!{
+    // printf-style (including positional and python named), python/rust braces and shell-like
+    // variables.
+    static ref PLACEHOLDER_RE: Regex = Regex::new(
+        r"%(?:\d+\$|\([^)]*\))?[-+ #0']*(?:\*|\d+)?(?:\.(?:\*|\d+))?(?:hh|h|ll|l|L|q|j|z|t|I64|I32|I)?[diouxXeEfFgGaAcspnm%]|\{[^{}\s]*\}|\$\{?[A-Za-z_][A-Za-z0-9_]*\}?")
+        .unwrap();
+}
+
+/// Splits text to words for counting.
+///
+/// Any `Fn(&str) -> usize` is a tokenizer, so custom counting is easy to plug in.
+pub trait Tokenizer {
+    /// Number of words in the text.
+    fn count_words(&self, text: &str) -> usize;
+}
+
+impl<F: Fn(&str) -> usize> Tokenizer for F {
+    fn count_words(&self, text: &str) -> usize {
+        self(text)
+    }
+}
+
+/// Default tokenizer.
+///
+/// Words are separated by white space and must contain a letter or digit, so punctuation does not
+/// count. Placeholders, like `%s`, `%1$d`, `%(name)s`, `{0}` or `$HOME`, are skipped, as they are
+/// not translated.
+#[derive(Clone,Copy,Debug,Default)]
+pub struct Words;
+
+impl Tokenizer for Words {
+    fn count_words(&self, text: &str) -> usize {
+        PLACEHOLDER_RE.replace_all(text, " ")
+            .split_whitespace()
+            .filter(|w| w.chars().any(char::is_alphanumeric))
+            .count()
+    }
+}
+
+/// Number of units and of words in them.
+#[derive(Clone,Copy,Debug,Default,Eq,PartialEq)]
+pub struct Counts {
+    pub units: usize,
+    pub source_words: usize,
+    pub target_words: usize,
+}
+
+impl AddAssign for Counts {
+    fn add_assign(&mut self, other: Counts) {
+        self.units += other.units;
+        self.source_words += other.source_words;
+        self.target_words += other.target_words;
+    }
+}
+
+/// Counts of units by state.
+///
+/// Obsolete units are counted separately and not included in the counts by state.
+#[derive(Clone,Debug,Default,Eq,PartialEq)]
+pub struct Summary {
+    _states: BTreeMap<State, Counts>,
+    _obsolete: Counts,
+}
+
+impl Summary {
+    /// Counts of active units in given state.
+    pub fn state(&self, state: State) -> Counts {
+        self._states.get(&state).cloned().unwrap_or_default()
+    }
+
+    /// Counts of units in the `Final` state.
+    pub fn translated(&self) -> Counts { self.state(State::Final) }
+
+    /// Counts of units in the `NeedsWork` state, `fuzzy` in gettext terms.
+    pub fn fuzzy(&self) -> Counts { self.state(State::NeedsWork) }
+
+    /// Counts of units in the `Empty` state.
+    pub fn untranslated(&self) -> Counts { self.state(State::Empty) }
+
+    /// Counts of obsolete units.
+    pub fn obsolete(&self) -> Counts { self._obsolete }
+
+    /// Counts of all active units.
+    pub fn total(&self) -> Counts {
+        let mut total = Counts::default();
+        for c in self._states.values() {
+            total += *c;
+        }
+        total
+    }
+
+    fn add(&mut self, unit: &Unit, counts: Counts) {
+        if unit.is_obsolete() {
+            self._obsolete += counts;
+        } else {
+            *self._states.entry(unit.state()).or_default() += counts;
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    /// Format the unit counts like `msgfmt --statistics`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let plural = |n: usize, one: &'static str, other: &'static str| {
+            if n == 1 { one } else { other }
+        };
+        let translated = self.translated().units;
+        write!(f, "{} translated {}", translated, plural(translated, "message", "messages"))?;
+        let fuzzy = self.fuzzy().units;
+        if fuzzy > 0 {
+            write!(f, ", {} fuzzy {}", fuzzy, plural(fuzzy, "translation", "translations"))?;
+        }
+        let untranslated = self.untranslated().units;
+        if untranslated > 0 {
+            write!(f, ", {} untranslated {}", untranslated,
+                   plural(untranslated, "message", "messages"))?;
+        }
+        write!(f, ".")
+    }
+}
+
+/// Statistics of a catalogue.
+///
+/// Contains summary of all units and summaries of units with locations starting with each of
+/// the prefixes the [`Counter`] was configured with. A unit belongs to a prefix if any of its
+/// locations starts with it, so it can be counted under several prefixes.
+///
+/// [`Counter`]: struct.Counter.html
+#[derive(Clone,Debug,Default,Eq,PartialEq)]
+pub struct Statistics {
+    _total: Summary,
+    _prefixes: Vec<(String, Summary)>,
+}
+
+impl Statistics {
+    /// Summary of all units.
+    pub fn total(&self) -> &Summary { &self._total }
+
+    /// Summary of units under given location prefix, if the prefix was configured.
+    pub fn prefix(&self, prefix: &str) -> Option<&Summary> {
+        self._prefixes.iter().find(|p| p.0 == prefix).map(|p| &p.1)
+    }
+
+    /// Iterate over the prefixes with their summaries, in the order they were configured.
+    pub fn prefixes(&self) -> impl Iterator<Item = (&str, &Summary)> {
+        self._prefixes.iter().map(|p| (p.0.as_str(), &p.1))
+    }
+}
+
+/// Collects statistics of catalogues.
+///
+/// Words of all variants of plural messages are counted, as each has to be translated.
+#[derive(Clone,Debug,Default)]
+pub struct Counter<T: Tokenizer = Words> {
+    _tokenizer: T,
+    _prefixes: Vec<String>,
+}
+
+impl Counter<Words> {
+    /// Create counter with the default tokenizer and no prefixes.
+    pub fn new() -> Self {
+        Counter::default()
+    }
+}
+
+impl<T: Tokenizer> Counter<T> {
+    /// Create counter with given tokenizer and no prefixes.
+    pub fn with_tokenizer(tokenizer: T) -> Self {
+        Counter { _tokenizer: tokenizer, _prefixes: Vec::new() }
+    }
+
+    /// Add a location prefix to break the statistics down by.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self._prefixes.push(prefix.to_owned());
+        self
+    }
+
+    /// Count words in a message.
+    pub fn count_words(&self, message: &Message) -> usize {
+        match *message {
+            Message::Empty => 0,
+            Message::Singular(ref s) => self._tokenizer.count_words(s),
+            Message::Plural(ref map) => map.values().map(|s| self._tokenizer.count_words(s)).sum(),
+        }
+    }
+
+    fn add_unit(&self, stats: &mut Statistics, unit: &Unit) {
+        let counts = Counts {
+            units: 1,
+            source_words: self.count_words(unit.source()),
+            target_words: self.count_words(unit.target()),
+        };
+        stats._total.add(unit, counts);
+        for &mut (ref prefix, ref mut summary) in &mut stats._prefixes {
+            if unit.locations().iter().any(|l| l.starts_with(prefix.as_str())) {
+                summary.add(unit, counts);
+            }
+        }
+    }
+
+    /// Collect statistics of units in a catalogue.
+    pub fn count_catalogue(&self, catalogue: &Catalogue) -> Statistics {
+        self.count_units(catalogue)
+    }
+
+    /// Collect statistics of units from a reader. Fails on the first error from the reader.
+    pub fn count_reader<R: CatalogueReader>(&self, reader: R) -> Result<Statistics, Error> {
+        let mut stats = self.empty();
+        for unit in reader {
+            self.add_unit(&mut stats, &unit?);
+        }
+        Ok(stats)
+    }
+
+    /// Collect statistics of units.
+    pub fn count_units<'a, I: IntoIterator<Item = &'a Unit>>(&self, units: I) -> Statistics {
+        let mut stats = self.empty();
+        for unit in units {
+            self.add_unit(&mut stats, unit);
+        }
+        stats
+    }
+
+    // Statistics with summaries for all prefixes, so they are reported even if empty.
+    fn empty(&self) -> Statistics {
+        Statistics {
+            _total: Summary::default(),
+            _prefixes: self._prefixes.iter().map(|p| (p.clone(), Summary::default())).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::catalogue::Catalogue;
+    use ::po::PoReader;
+    use ::State;
+    use super::{Counter, Counts, Tokenizer, Words};
+
+    static SAMPLE_PO: &str = r###"msgid ""
+msgstr ""
+"Language: cs\n"
+"Content-Type: text/plain; charset=UTF-8\n"
+"Plural-Forms: nplurals=3; plural=(n==1) ? 0 : (n>=2 && n<=4) ? 1 : 2;\n"
+
+#: src/ui/menu.rs:10
+msgid "Open the file"
+msgstr "Otevřít soubor"
+
+#: src/ui/menu.rs:11 src/cli.rs:20
+#, fuzzy
+msgid "Save as…"
+msgstr "Uložit jako…"
+
+#: src/cli.rs:30
+#, c-format
+msgid "Cannot open %s: %s"
+msgstr ""
+
+#: src/ui/dialog.rs:5
+#, c-format
+msgid "%d file"
+msgid_plural "%d files"
+msgstr[0] "%d soubor"
+msgstr[1] "%d soubory"
+msgstr[2] "%d souborů"
+
+#~ msgid "Close"
+#~ msgstr "Zavřít"
+"###;
+
+    #[test]
+    fn words() {
+        assert_eq!(0, Words.count_words(""));
+        assert_eq!(3, Words.count_words("Open the file"));
+        assert_eq!(2, Words.count_words("Cannot open %s: %s"));
+        assert_eq!(3, Words.count_words("Hello, {name} — from %(app)s and ${HOME}!"));
+        assert_eq!(1, Words.count_words("%1$d%% done"));
+    }
+
+    #[test]
+    fn count() {
+        let cat = Catalogue::from_reader(PoReader::new(SAMPLE_PO.as_bytes())).unwrap();
+        let stats = Counter::new().prefix("src/ui/").prefix("src/cli").prefix("doc/")
+            .count_catalogue(&cat);
+        let total = stats.total();
+        assert_eq!(Counts { units: 2, source_words: 5, target_words: 5 }, total.translated());
+        assert_eq!(Counts { units: 1, source_words: 2, target_words: 2 }, total.fuzzy());
+        assert_eq!(Counts { units: 1, source_words: 2, target_words: 0 }, total.untranslated());
+        assert_eq!(Counts { units: 1, source_words: 1, target_words: 1 }, total.obsolete());
+        assert_eq!(4, total.total().units);
+        assert_eq!("2 translated messages, 1 fuzzy translation, 1 untranslated message.",
+                   total.to_string());
+
+        let ui = stats.prefix("src/ui/").unwrap();
+        assert_eq!(3, ui.total().units);
+        assert_eq!(1, ui.state(State::NeedsWork).units);
+        assert_eq!(2, stats.prefix("src/cli").unwrap().total().units);
+        assert_eq!(0, stats.prefix("doc/").unwrap().total().units);
+        assert!(stats.prefix("src/").is_none());
+        assert_eq!(vec!["src/ui/", "src/cli", "doc/"],
+                   stats.prefixes().map(|p| p.0).collect::<Vec<_>>());
+
+        let from_reader = Counter::new().prefix("src/ui/").prefix("src/cli").prefix("doc/")
+            .count_reader(PoReader::new(SAMPLE_PO.as_bytes())).unwrap();
+        assert_eq!(stats, from_reader);
+
+        let chars = Counter::with_tokenizer(|s: &str| s.chars().count()).count_catalogue(&cat);
+        assert_eq!(8, chars.total().fuzzy().source_words);
+    }
+}
@@ -0,0 +1,394 @@
+//! Runtime lookup of translations
+//!
+//! [`Translator`] provides the lookup functions of the gettext runtime over a catalogue loaded
+//! from PO or MO file, or an in-memory [`Catalogue`].
+//!
+//! ```
+//! use translate_storage::po::PoReader;
+//! use translate_storage::translator::Translator;
+//!
+//! let po = r#"msgid ""
+//! msgstr ""
+//! "Language: cs\n"
+//! "Plural-Forms: nplurals=3; plural=(n==1) ? 0 : (n>=2 && n<=4) ? 1 : 2;\n"
+//!
+//! msgid "Open"
+//! msgstr "Otevřít"
+//!
+//! msgid "%d file"
+//! msgid_plural "%d files"
+//! msgstr[0] "%d soubor"
+//! msgstr[1] "%d soubory"
+//! msgstr[2] "%d souborů"
+//! "#;
+//! let tr = Translator::from_reader(PoReader::new(po.as_bytes())).unwrap();
+//! assert_eq!("Otevřít", tr.gettext("Open"));
+//! assert_eq!("Close", tr.gettext("Close"));
+//! assert_eq!("%d soubory", tr.ngettext("%d file", "%d files", 3));
+//! assert_eq!("%d souborů", tr.ngettext("%d file", "%d files", 5));
+//! ```
+//!
+//! Translations for several languages are walked in order by a [`Chain`], usually built by
+//! negotiation in the [`locale`] module.
+//!
+//! [`Translator`]: struct.Translator.html
+//! [`Chain`]: struct.Chain.html
+//! [`locale`]: ../locale/index.html
+//! [`Catalogue`]: ../catalogue/struct.Catalogue.html
+
+use catalogue::Catalogue;
+use locale_config::LanguageRange;
+use plural::PluralRule;
+use po;
+use std::collections::HashMap;
+use super::{CatalogueReader, Count, Error, Message, Unit};
+
+/// Translations of one catalogue, for lookup at runtime.
+///
+/// Only final translations are used, like in compiled catalogues; for untranslated and fuzzy
+/// units, as well as units not in the catalogue, the functions return the source. Plural
+/// variants are selected by the plural rule of the catalogue, mapped to the [`Count`]
+/// categories.
+///
+/// [`Count`]: ../enum.Count.html
+#[derive(Clone,Debug)]
+pub struct Translator {
+    _language: LanguageRange<'static>,
+    _plural_rule: PluralRule,
+    _plurals: Vec<Count>,
+    _messages: HashMap<String, Message>,
+    _contexts: HashMap<String, HashMap<String, Message>>,
+}
+
+impl Translator {
+    /// Create translator without any translations.
+    pub fn new(language: LanguageRange<'static>, plural_rule: PluralRule) -> Self {
+        Translator {
+            _plurals: plural_rule.categories(&language),
+            _language: language,
+            _plural_rule: plural_rule,
+            _messages: HashMap::new(),
+            _contexts: HashMap::new(),
+        }
+    }
+
+    /// Load translations from a reader. Fails on the first error from the reader, or if the
+    /// `Plural-Forms` property is invalid.
+    pub fn from_reader<R: CatalogueReader>(mut reader: R) -> Result<Self, Error> {
+        let rule = reader.property("Plural-Forms").map(PluralRule::parse).transpose()?;
+        let mut tr = Translator::new(reader.target_language().clone(), rule.unwrap_or_default());
+        for unit in &mut reader {
+            tr.add(&unit?);
+        }
+        Ok(tr)
+    }
+
+    /// Load translations from a catalogue. Fails if the `Plural-Forms` header field is invalid.
+    pub fn from_catalogue(catalogue: &Catalogue) -> Result<Self, Error> {
+        let rule = catalogue.header().plural_forms()?;
+        let mut tr = Translator::new(catalogue.target_language().clone(), rule.unwrap_or_default());
+        for unit in catalogue {
+            tr.add(unit);
+        }
+        Ok(tr)
+    }
+
+    /// Load translations from a static table, as generated by the [`build`] module.
+    ///
+    /// The messages are tuples of context, source (singular source for plural messages) and
+    /// translations, one for singular message and the plural variants in order of the plural
+    /// rule index, like in MO file. Fails if `plural_forms` is invalid; empty means the default
+    /// rule.
+    ///
+    /// [`build`]: ../build/index.html
+    pub fn from_table(language: &str, plural_forms: &str,
+                      messages: &[(Option<&str>, &str, &[&str])]) -> Result<Self, Error> {
+        let rule = Some(plural_forms).filter(|s| !s.is_empty()).map(PluralRule::parse).transpose()?;
+        let mut tr = Translator::new(po::parse_language(language), rule.unwrap_or_default());
+        for &(context, msgid, translations) in messages {
+            let target = match *translations {
+                [] => continue,
+                [singular] => Message::Singular(singular.to_owned()),
+                _ => Message::Plural(tr._plurals.iter().cloned()
+                    .zip(translations.iter().map(|s| (*s).to_owned())).collect()),
+            };
+            let messages = match context {
+                Some(ctx) => tr._contexts.entry(ctx.to_owned()).or_default(),
+                None => &mut tr._messages,
+            };
+            messages.insert(msgid.to_owned(), target);
+        }
+        Ok(tr)
+    }
+
+    /// Add translation from a unit, if it is translated and not obsolete.
+    pub fn add(&mut self, unit: &Unit) {
+        if !unit.is_translated() || unit.is_obsolete() || unit.target().is_blank() {
+            return;
+        }
+        let source = unit.source().key().to_owned();
+        let messages = match *unit.context() {
+            Some(ref ctx) => self._contexts.entry(ctx.clone()).or_default(),
+            None => &mut self._messages,
+        };
+        messages.insert(source, unit.target().clone());
+    }
+
+    pub fn language(&self) -> &LanguageRange<'static> { &self._language }
+
+    pub fn plural_rule(&self) -> &PluralRule { &self._plural_rule }
+
+    /// Number of translated messages.
+    pub fn len(&self) -> usize {
+        self._messages.len() + self._contexts.values().map(HashMap::len).sum::<usize>()
+    }
+
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    /// Plural category for number `n` according to the catalogue plural rule.
+    pub fn category(&self, n: u64) -> Count {
+        self._plurals.get(self._plural_rule.index(n)).cloned().unwrap_or(Count::Other)
+    }
+
+    fn message(&self, context: Option<&str>, msgid: &str) -> Option<&Message> {
+        match context {
+            Some(ctx) => self._contexts.get(ctx)?.get(msgid),
+            None => self._messages.get(msgid),
+        }
+    }
+
+    /// Find translation of a singular message, if the catalogue has it.
+    pub fn find(&self, context: Option<&str>, msgid: &str) -> Option<&str> {
+        match *self.message(context, msgid)? {
+            Message::Singular(ref s) => Some(s),
+            ref m => Some(m.key()).filter(|s| !s.is_empty()),
+        }
+    }
+
+    /// Find translation of a plural message for number `n`, if the catalogue has it.
+    ///
+    /// The variant is selected by the plural category of `n`; if the translation does not have
+    /// that variant, or it is empty, there is no translation.
+    pub fn find_plural(&self, context: Option<&str>, msgid: &str, n: u64) -> Option<&str> {
+        match *self.message(context, msgid)? {
+            Message::Plural(ref map) => map.get(&self.category(n)).map(String::as_str),
+            Message::Singular(ref s) => Some(s.as_str()),
+            Message::Empty => None,
+        }.filter(|s| !s.is_empty())
+    }
+
+    /// Translate a message.
+    pub fn gettext<'a>(&'a self, msgid: &'a str) -> &'a str {
+        self.find(None, msgid).unwrap_or(msgid)
+    }
+
+    /// Translate a plural message for number `n`.
+    ///
+    /// Without translation, `msgid` is returned for 1 and `msgid_plural` for other numbers.
+    pub fn ngettext<'a>(&'a self, msgid: &'a str, msgid_plural: &'a str, n: u64) -> &'a str {
+        self.npgettext_opt(None, msgid, msgid_plural, n)
+    }
+
+    /// Translate a message in context.
+    pub fn pgettext<'a>(&'a self, context: &str, msgid: &'a str) -> &'a str {
+        self.find(Some(context), msgid).unwrap_or(msgid)
+    }
+
+    /// Translate a plural message in context for number `n`.
+    pub fn npgettext<'a>(&'a self, context: &str, msgid: &'a str, msgid_plural: &'a str, n: u64)
+            -> &'a str {
+        self.npgettext_opt(Some(context), msgid, msgid_plural, n)
+    }
+
+    fn npgettext_opt<'a>(&'a self, context: Option<&str>, msgid: &'a str, msgid_plural: &'a str,
+                         n: u64) -> &'a str {
+        self.find_plural(context, msgid, n)
+            .unwrap_or(if n == 1 { msgid } else { msgid_plural })
+    }
+}
+
+/// Fallback chain of translators.
+///
+/// Each message is looked up in the translators in order, and the first one that has it
+/// translates it, with its own plural rule. Messages none of them has fall back to the source.
+#[derive(Clone,Debug,Default)]
+pub struct Chain {
+    _translators: Vec<Translator>,
+}
+
+impl Chain {
+    pub fn new(translators: Vec<Translator>) -> Self {
+        Chain { _translators: translators }
+    }
+
+    /// Append translator with the lowest priority.
+    pub fn push(&mut self, translator: Translator) { self._translators.push(translator); }
+
+    pub fn translators(&self) -> &[Translator] { &self._translators }
+
+    pub fn is_empty(&self) -> bool { self._translators.is_empty() }
+
+    /// Find translation of a singular message in the first translator that has it.
+    pub fn find(&self, context: Option<&str>, msgid: &str) -> Option<&str> {
+        self._translators.iter().filter_map(|t| t.find(context, msgid)).next()
+    }
+
+    /// Find translation of a plural message for number `n` in the first translator that has it.
+    pub fn find_plural(&self, context: Option<&str>, msgid: &str, n: u64) -> Option<&str> {
+        self._translators.iter().filter_map(|t| t.find_plural(context, msgid, n)).next()
+    }
+
+    /// Translate a message.
+    pub fn gettext<'a>(&'a self, msgid: &'a str) -> &'a str {
+        self.find(None, msgid).unwrap_or(msgid)
+    }
+
+    /// Translate a plural message for number `n`.
+    pub fn ngettext<'a>(&'a self, msgid: &'a str, msgid_plural: &'a str, n: u64) -> &'a str {
+        self.find_plural(None, msgid, n).unwrap_or(if n == 1 { msgid } else { msgid_plural })
+    }
+
+    /// Translate a message in context.
+    pub fn pgettext<'a>(&'a self, context: &str, msgid: &'a str) -> &'a str {
+        self.find(Some(context), msgid).unwrap_or(msgid)
+    }
+
+    /// Translate a plural message in context for number `n`.
+    pub fn npgettext<'a>(&'a self, context: &str, msgid: &'a str, msgid_plural: &'a str, n: u64)
+            -> &'a str {
+        self.find_plural(Some(context), msgid, n).unwrap_or(if n == 1 { msgid } else { msgid_plural })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::catalogue::Catalogue;
+    use ::locale_config::LanguageRange;
+    use ::mo::{MoReader, MoWriter};
+    use ::po::PoReader;
+    use ::{CatalogueReader, CatalogueWriter, Count};
+    use super::{Chain, Translator};
+
+    static SAMPLE_PO: &str = r###"msgid ""
+msgstr ""
+"Language: cs\n"
+"Content-Type: text/plain; charset=UTF-8\n"
+"Plural-Forms: nplurals=3; plural=(n==1) ? 0 : (n>=2 && n<=4) ? 1 : 2;\n"
+
+msgid "Open"
+msgstr "Otevřít"
+
+msgctxt "menu"
+msgid "Open"
+msgstr "Otevřít…"
+
+#, fuzzy
+msgid "Save"
+msgstr "Uložit"
+
+msgid "Quit"
+msgstr ""
+
+msgid "%d file"
+msgid_plural "%d files"
+msgstr[0] "%d soubor"
+msgstr[1] "%d soubory"
+msgstr[2] "%d souborů"
+
+msgctxt "disk"
+msgid "%d sector"
+msgid_plural "%d sectors"
+msgstr[0] "%d sektor"
+msgstr[1] ""
+msgstr[2] "%d sektorů"
+
+#~ msgid "Close"
+#~ msgstr "Zavřít"
+"###;
+
+    fn check(tr: &Translator) {
+        assert_eq!(4, tr.len());
+        assert_eq!(Count::Few, tr.category(4));
+        assert_eq!("Otevřít", tr.gettext("Open"));
+        assert_eq!("Otevřít…", tr.pgettext("menu", "Open"));
+        assert_eq!("Open", tr.pgettext("toolbar", "Open"));
+        assert_eq!("Save", tr.gettext("Save"));
+        assert_eq!("Quit", tr.gettext("Quit"));
+        assert_eq!("Close", tr.gettext("Close"));
+        assert_eq!("%d soubor", tr.ngettext("%d file", "%d files", 1));
+        assert_eq!("%d soubory", tr.ngettext("%d file", "%d files", 2));
+        assert_eq!("%d souborů", tr.ngettext("%d file", "%d files", 0));
+        assert_eq!("%d dog", tr.ngettext("%d dog", "%d dogs", 1));
+        assert_eq!("%d dogs", tr.ngettext("%d dog", "%d dogs", 2));
+        assert_eq!("%d sektorů", tr.npgettext("disk", "%d sector", "%d sectors", 10));
+        assert_eq!("%d sectors", tr.npgettext("disk", "%d sector", "%d sectors", 3));
+        assert_eq!(None, tr.find(None, "Save"));
+    }
+
+    #[test]
+    fn translate_po() {
+        check(&Translator::from_reader(PoReader::new(SAMPLE_PO.as_bytes())).unwrap());
+    }
+
+    #[test]
+    fn translate_catalogue() {
+        let cat = Catalogue::from_reader(PoReader::new(SAMPLE_PO.as_bytes())).unwrap();
+        check(&Translator::from_catalogue(&cat).unwrap());
+    }
+
+    #[test]
+    fn translate_mo() {
+        let mut po = PoReader::new(SAMPLE_PO.as_bytes());
+        let mut writer = MoWriter::new(Vec::new());
+        writer.write_header(&LanguageRange::new("cs").unwrap(), po.header_unit()).unwrap();
+        for unit in &mut po {
+            writer.write_unit(&unit.unwrap()).unwrap();
+        }
+        writer.finish().unwrap();
+        let mo = writer.into_inner();
+        check(&Translator::from_reader(MoReader::new(&mo[..])).unwrap());
+    }
+
+    #[test]
+    fn translate_table() {
+        let tr = Translator::from_table(
+            "cs", "nplurals=3; plural=(n==1) ? 0 : (n>=2 && n<=4) ? 1 : 2;",
+            &[(None, "Open", &["Otevřít"]),
+              (Some("menu"), "Open", &["Otevřít…"]),
+              (None, "%d file", &["%d soubor", "%d soubory", "%d souborů"]),
+              (Some("disk"), "%d sector", &["%d sektor", "", "%d sektorů"])]).unwrap();
+        check(&tr);
+        assert!(Translator::from_table("cs", "nplurals=3", &[]).is_err());
+    }
+
+    #[test]
+    fn chain() {
+        let cs = Translator::from_reader(PoReader::new(SAMPLE_PO.as_bytes())).unwrap();
+        let sk = Translator::from_reader(PoReader::new(r#"msgid ""
+msgstr ""
+"Language: sk\n"
+"Plural-Forms: nplurals=3; plural=(n==1) ? 0 : (n>=2 && n<=4) ? 1 : 2;\n"
+
+msgid "Open"
+msgstr "Otvoriť"
+
+msgid "Quit"
+msgstr "Ukončiť"
+
+msgctxt "disk"
+msgid "%d sector"
+msgid_plural "%d sectors"
+msgstr[0] "%d sektor"
+msgstr[1] "%d sektory"
+msgstr[2] "%d sektorov"
+"#.as_bytes())).unwrap();
+        let chain = Chain::new(vec![cs, sk]);
+        assert_eq!("Otevřít", chain.gettext("Open"));
+        assert_eq!("Ukončiť", chain.gettext("Quit"));
+        assert_eq!("Save", chain.gettext("Save"));
+        assert_eq!("%d sektorů", chain.npgettext("disk", "%d sector", "%d sectors", 5));
+        assert_eq!("%d sektory", chain.npgettext("disk", "%d sector", "%d sectors", 2));
+        assert_eq!("%d dogs", chain.ngettext("%d dog", "%d dogs", 2));
+        assert_eq!("Open", Chain::default().gettext("Open"));
+    }
+}
@@ -0,0 +1,1645 @@
+//! Handling of [XML Localisation Interchange File Format][XLIFF]
+//!
+//! XLIFF is an OASIS standard for exchanging translations between tools. Version 1.2 is read and
+//! written the way [translate-toolkit][tt] writes it when converting from PO (`po2xliff`), so notes,
+//! locations, contexts and plurals survive the conversion.
+//!
+//! Versions 2.0 and 2.1 have a different structure and are handled by separate `Xliff2Reader` and
+//! `Xliff2Writer`. There is no established mapping of gettext features to them, so the writer
+//! stores them using the metadata module and the reader reads them back from there.
+//!
+//! [XLIFF]: http://docs.oasis-open.org/xliff/v1.2/os/xliff-core.html
+//! [tt]: http://toolkit.translatehouse.org/
+
+use chrono::{DateTime,FixedOffset};
+use locale_config::LanguageRange;
+use std::collections::BTreeMap;
+use std::io::{Cursor,Read,Write};
+use xml::common::Position;
+use xml::attribute::OwnedAttribute;
+use xml::reader::{EventReader,ParserConfig,XmlEvent};
+use super::{CatalogueReader,CatalogueWriter,Count,Error,Message,Origin,State,Unit};
+use super::plural::{self,PluralRule};
+use super::po::{header_fields,header_text,parse_language,PoHeader};
+
+// Element of the document, for the parts that are collected before converting them to units.
+#[derive(Debug)]
+struct Element {
+    name: String,
+    attrs: Vec<(String, String)>,
+    children: Vec<Node>,
+}
+
+#[derive(Debug)]
+enum Node {
+    Element(Element),
+    Text(String),
+}
+
+impl Element {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs.iter().find(|a| a.0 == name).map(|a| a.1.as_str())
+    }
+
+    fn elements<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> + 'a {
+        self.children.iter().filter_map(move |n| match *n {
+            Node::Element(ref e) if e.name == name => Some(e),
+            _ => None,
+        })
+    }
+
+    fn element<'a>(&'a self, name: &'a str) -> Option<&'a Element> {
+        self.elements(name).next()
+    }
+
+    // Text content, including content of any inline elements.
+    fn text(&self) -> String {
+        let mut res = String::new();
+        self.append_text(&mut res);
+        res
+    }
+
+    fn append_text(&self, res: &mut String) {
+        for n in &self.children {
+            match *n {
+                Node::Element(ref e) => e.append_text(res),
+                Node::Text(ref t) => res.push_str(t),
+            }
+        }
+    }
+}
+
+// Source of XML events, with errors converted and elements collected on request.
+struct Events(EventReader<Cursor<Vec<u8>>>);
+
+impl Events {
+    fn new(bytes: Vec<u8>) -> Self {
+        let config = ParserConfig::new()
+            .whitespace_to_characters(true)
+            .cdata_to_characters(true)
+            .ignore_comments(true);
+        Events(config.create_reader(Cursor::new(bytes)))
+    }
+
+    fn line(&self) -> usize {
+        self.0.position().row as usize + 1
+    }
+
+    fn next(&mut self) -> Result<XmlEvent, Error> {
+        self.0.next().map_err(|e| Error::Xml(e.position().row as usize + 1, e.msg().to_owned()))
+    }
+
+    // Collect the element, whose start tag was just read, up to its end tag.
+    fn read_element(&mut self, name: String, attrs: Vec<(String, String)>) -> Result<Element, Error> {
+        let mut stack = vec![Element { name, attrs, children: Vec::new() }];
+        loop {
+            match self.next()? {
+                XmlEvent::StartElement { name, attributes, .. } => stack.push(Element {
+                    name: name.local_name,
+                    attrs: local_attrs(attributes),
+                    children: Vec::new(),
+                }),
+                XmlEvent::EndElement { .. } => {
+                    let elem = stack.pop().unwrap();
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(Node::Element(elem)),
+                        None => return Ok(elem),
+                    }
+                }
+                XmlEvent::Characters(text) => stack.last_mut().unwrap().children.push(Node::Text(text)),
+                _ => (),
+            }
+        }
+    }
+}
+
+fn local_attrs(attributes: Vec<OwnedAttribute>) -> Vec<(String, String)> {
+    attributes.into_iter().map(|a| (a.name.local_name, a.value)).collect()
+}
+
+/// Reader for XLIFF 1.2 files.
+///
+/// Trans-units from all `<file>` elements are returned, but the languages are taken from the
+/// first one. Plural messages are read from groups with `restype="x-gettext-plurals"`; the plural
+/// categories are taken from the gettext header unit if there is one, otherwise from the target
+/// language.
+pub struct XliffReader {
+    _events: Events,
+    _next_unit: Option<Result<Unit, Error>>,
+    _failed: Option<Error>,
+    _header_unit: Option<Unit>,
+    _header: PoHeader,
+    _files: usize,
+    _source_language: Option<LanguageRange<'static>>,
+    _target_language: LanguageRange<'static>,
+    _project: Option<String>,
+    _date: Option<DateTime<FixedOffset>>,
+    _generator: Option<String>,
+    _plural_rule: Option<PluralRule>,
+    _done: bool,
+}
+
+impl XliffReader {
+    pub fn new<R: Read>(mut reader: R) -> Self {
+        let mut bytes = Vec::new();
+        let failed = reader.read_to_end(&mut bytes).err().map(|e| Error::Io(0, e));
+        let mut res = XliffReader {
+            _events: Events::new(bytes),
+            _next_unit: None,
+            _failed: failed,
+            _header_unit: None,
+            _header: PoHeader::default(),
+            _files: 0,
+            _source_language: None,
+            _target_language: LanguageRange::invariant(),
+            _project: None,
+            _date: None,
+            _generator: None,
+            _plural_rule: None,
+            _done: false,
+        };
+        if res._failed.is_none() {
+            res._next_unit = res.next_unit();
+        }
+        res
+    }
+
+    // The header unit has the header text as source too, like po2xliff writes it, but it is read
+    // with blank source like from PO.
+    fn parse_po_header(&mut self, mut unit: Unit, line: usize) {
+        unit._source = Message::Singular(String::new());
+        for (key, val) in header_fields(unit._target.singular().unwrap_or("")) {
+            if key == "Plural-Forms" {
+                match PluralRule::parse(val) {
+                    Ok(rule) => self._plural_rule = Some(rule),
+                    Err(Error::Parse(_, got, exp)) => self._failed = Some(Error::Parse(line, got, exp)),
+                    Err(e) => self._failed = Some(e),
+                }
+            }
+        }
+        self._header = PoHeader::from_unit(&unit);
+        self._header_unit = Some(unit);
+    }
+
+    fn next_unit(&mut self) -> Option<Result<Unit, Error>> {
+        if self._done {
+            return None;
+        }
+        match self.parse_unit() {
+            Ok(None) => None,
+            Ok(Some(u)) => Some(Ok(u)),
+            Err(e) => {
+                // the document can't be read any further after malformed XML
+                self._done = is!(e => Error::Xml(..));
+                Some(Err(e))
+            }
+        }
+    }
+
+    fn parse_unit(&mut self) -> Result<Option<Unit>, Error> {
+        loop {
+            match self._events.next()? {
+                XmlEvent::StartElement { name, attributes, .. } => {
+                    let attrs = local_attrs(attributes);
+                    match name.local_name.as_str() {
+                        "file" => {
+                            self._files += 1;
+                            if self._files == 1 {
+                                let lang = |n| attrs.iter().find(|a| a.0 == n)
+                                    .map(|a| parse_language(&a.1));
+                                self._target_language = lang("target-language")
+                                    .unwrap_or_else(LanguageRange::invariant);
+                                self._source_language = lang("source-language");
+                            }
+                            if self._project.is_none() {
+                                self._project = joined_attrs(&attrs, "product-name", "product-version");
+                            }
+                            if let Some((_, date)) = attrs.iter().find(|a| a.0 == "date") {
+                                self._date = self._date.or_else(|| DateTime::parse_from_rfc3339(date).ok());
+                            }
+                        }
+                        "tool" if self._generator.is_none() => {
+                            self._generator = joined_attrs(&attrs, "tool-name", "tool-version");
+                        }
+                        "trans-unit" => {
+                            let line = self._events.line();
+                            let elem = self._events.read_element(name.local_name, attrs)?;
+                            let unit = self.make_unit(&elem, line)?;
+                            if elem.attr("restype") == Some("x-gettext-domain-header")
+                                    && self._header_unit.is_none() {
+                                self.parse_po_header(unit, line);
+                                continue;
+                            }
+                            return Ok(Some(unit));
+                        }
+                        "group" if attrs.iter().any(|a| a.0 == "restype" && a.1 == "x-gettext-plurals") => {
+                            let line = self._events.line();
+                            let elem = self._events.read_element(name.local_name, attrs)?;
+                            return self.make_plural_unit(&elem, line).map(Some);
+                        }
+                        _ => (),
+                    }
+                }
+                XmlEvent::EndDocument => return Ok(None),
+                _ => (),
+            }
+        }
+    }
+
+    fn make_unit(&self, elem: &Element, line: usize) -> Result<Unit, Error> {
+        let source = elem.element("source").ok_or_else(|| Error::Parse(line, None, vec!["<source>"]))?;
+        let target = elem.element("target");
+        let mut unit = Unit {
+            _source: Message::Singular(source.text()),
+            _target: target.map_or(Message::Empty, |t| Message::Singular(t.text())),
+            _state: unit_state(elem, target),
+            _line: line,
+            ..Unit::default()
+        };
+        read_annotations(elem, &mut unit);
+        Ok(unit)
+    }
+
+    fn make_plural_unit(&self, group: &Element, line: usize) -> Result<Unit, Error> {
+        let forms = group.elements("trans-unit").collect::<Vec<_>>();
+        if forms.is_empty() {
+            return Err(Error::Parse(line, None, vec!["<trans-unit>"]));
+        }
+        let plurals = match self._plural_rule {
+            Some(ref rule) => rule.categories(&self._target_language),
+            None => plural::language_categories(&self._target_language, forms.len()),
+        };
+        check_plural_forms(line, plurals.len(), forms.len())?;
+
+        let mut source = BTreeMap::new();
+        let mut target = BTreeMap::new();
+        for (i, form) in forms.iter().enumerate() {
+            let s = form.element("source").ok_or_else(|| Error::Parse(line, None, vec!["<source>"]))?;
+            if i == 0 {
+                source.insert(Count::One, s.text());
+            }
+            if i == forms.len() - 1 {
+                source.insert(Count::Other, s.text());
+            }
+            if let (Some(&c), Some(t)) = (plurals.get(i), form.element("target")) {
+                target.insert(c, t.text());
+            }
+        }
+
+        let mut unit = Unit { _source: Message::Plural(source), _line: line, ..Unit::default() };
+        if !target.is_empty() {
+            for &c in &plurals {
+                target.entry(c).or_insert_with(String::new);
+            }
+            unit._target = Message::Plural(target);
+            unit._state = unit_state(forms[0], forms[0].element("target"));
+        }
+        read_annotations(group, &mut unit);
+        read_annotations(forms[0], &mut unit);
+        Ok(unit)
+    }
+}
+
+// Check the number of forms of a plural group against the number of plural forms. Languages with
+// one plural form still have the group with both singular and plural source, like from po2xliff.
+fn check_plural_forms(line: usize, nplurals: usize, forms: usize) -> Result<(), Error> {
+    if forms == nplurals || (nplurals < 2 && forms == 2) {
+        Ok(())
+    } else {
+        Err(Error::PluralForms(line, nplurals, forms))
+    }
+}
+
+// State from the `state` attribute of the target, if given, or the `approved` attribute.
+fn unit_state(elem: &Element, target: Option<&Element>) -> State {
+    let target = match target {
+        Some(t) if !t.text().is_empty() => t,
+        _ => return State::Empty,
+    };
+    match target.attr("state") {
+        Some("translated") | Some("signed-off") | Some("final") => State::Final,
+        Some(_) => State::NeedsWork,
+        None if elem.attr("approved") == Some("no") => State::NeedsWork,
+        None => State::Final,
+    }
+}
+
+// Read notes, locations and context. translate-toolkit writes the comments both as notes and
+// as `po-entry` context groups, so the later are only used if there are no notes.
+fn read_annotations(elem: &Element, unit: &mut Unit) {
+    let mut notes = Vec::new();
+    for note in elem.elements("note") {
+        let origin = match note.attr("from") {
+            Some("developer") | Some("programmer") => Origin::Developer,
+            None | Some("translator") | Some("po-translator") => Origin::Translator,
+            Some(tag) => Origin::Tag(tag.to_owned()),
+        };
+        notes.push((origin, note.text()));
+    }
+    let mut entry_notes = Vec::new();
+    for group in elem.elements("context-group") {
+        let mut file = None;
+        let mut line = None;
+        for context in group.elements("context") {
+            match context.attr("context-type") {
+                Some("sourcefile") => file = Some(context.text()),
+                Some("linenumber") => line = Some(context.text()),
+                Some("x-gettext-msgctxt") | Some("x-po-msgctxt") => unit._context = Some(context.text()),
+                Some("x-po-autocomment") => entry_notes.push((Origin::Developer, context.text())),
+                Some("x-po-trancomment") => entry_notes.push((Origin::Translator, context.text())),
+                _ => (),
+            }
+        }
+        if group.attr("purpose") == Some("location") {
+            match (file, line) {
+                (Some(f), Some(l)) => unit._locations.push(format!("{}:{}", f, l)),
+                (Some(f), None) => unit._locations.push(f),
+                _ => (),
+            }
+        }
+    }
+    unit._notes.extend(if notes.is_empty() { entry_notes } else { notes });
+}
+
+impl Iterator for XliffReader {
+    type Item = Result<Unit, Error>;
+    fn next(&mut self) -> Option<Result<Unit, Error>> {
+        if let Some(e) = self._failed.take() {
+            return Some(Err(e));
+        }
+
+        self._next_unit.as_ref()?;
+
+        let mut res = self.next_unit();
+        ::std::mem::swap(&mut res, &mut self._next_unit);
+        res
+    }
+}
+
+impl CatalogueReader for XliffReader {
+    fn target_language(&self) -> &LanguageRange<'static> {
+        &self._target_language
+    }
+
+    /// Get the gettext header unit, if the file was converted from PO and has one.
+    ///
+    /// It is the unit with `restype="x-gettext-domain-header"`. It is not returned by the
+    /// iterator, but is needed to write the catalogue back to PO.
+    fn header_unit(&self) -> Option<&Unit> {
+        self._header_unit.as_ref()
+    }
+
+    /// Get the source language, from the `source-language` attribute of the first file.
+    fn source_language(&self) -> Option<&LanguageRange<'static>> {
+        self._source_language.as_ref()
+    }
+
+    /// Get the project from the `product-name` and `product-version` attributes, or from the
+    /// gettext header.
+    fn project(&self) -> Option<&str> {
+        self._project.as_deref().or_else(|| self.property("Project-Id-Version"))
+    }
+
+    /// Get the date from the `date` attribute, or from the gettext header.
+    fn revision_date(&self) -> Option<DateTime<FixedOffset>> {
+        self._date.or_else(|| self.property("PO-Revision-Date").and_then(::po::parse_date))
+    }
+
+    /// Get the generator from the `<tool>` element in the file header, or from the gettext
+    /// header.
+    fn generator(&self) -> Option<&str> {
+        self._generator.as_deref().or_else(|| self.property("X-Generator"))
+    }
+
+    fn properties(&self) -> Vec<(&str, &str)> {
+        self._header.fields().collect()
+    }
+}
+
+// Value of the name attribute, followed by the version attribute if present.
+fn joined_attrs(attrs: &[(String, String)], name: &str, version: &str) -> Option<String> {
+    let attr = |n| attrs.iter().find(|a| a.0 == n).map(|a| a.1.as_str());
+    attr(name).map(|n| match attr(version) {
+        Some(v) => format!("{} {}", n, v),
+        None => n.to_owned(),
+    })
+}
+
+// Escape text for use in content or attribute value. Characters XML can't represent at all are
+// rejected.
+fn escape_xml(s: &str) -> Result<String, Error> {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            '\r' => res.push_str("&#13;"),
+            '\t' | '\n' => res.push(c),
+            c if c < ' ' || c == '\u{FFFE}' || c == '\u{FFFF}' =>
+                return Err(Error::Unsupported(format!("character U+{:04X} in XML", c as u32))),
+            c => res.push(c),
+        }
+    }
+    Ok(res)
+}
+
+// Categories of plural forms to write for a unit, in order, and the singular and plural source.
+// Without a plural rule, the forms are those the unit has.
+fn plural_forms<'a>(unit: &'a Unit, plurals: Option<&Vec<Count>>)
+        -> Result<(Vec<Count>, &'a str, &'a str), Error> {
+    let target = unpack!(*unit.target() => Message::Plural(ref map) => map);
+    let plurals = match (plurals, target) {
+        (Some(plurals), _) => plurals.clone(),
+        (None, Some(map)) => map.keys().cloned().collect(),
+        (None, None) => vec![Count::One, Count::Other],
+    };
+    if let Some(c) = target.and_then(|map| map.keys().find(|c| !plurals.contains(c))) {
+        return Err(Error::Unsupported(
+                format!("plural form {:?} not used by the catalogue plural rule", c)));
+    }
+    let source = unpack!(*unit.source() => Message::Plural(ref map) => map).unwrap();
+    let one = source.get(&Count::One).or_else(|| source.values().next());
+    let other = source.get(&Count::Other).or_else(|| source.values().last());
+    Ok((plurals, one.map_or("", String::as_str), other.map_or("", String::as_str)))
+}
+
+/// Writer for XLIFF 1.2 files.
+///
+/// Writes a single `<file>` in the layout translate-toolkit's `po2xliff` produces. The gettext
+/// header is a trans-unit with `restype="x-gettext-domain-header"` and the header text as both
+/// source and target, approved unless the header is fuzzy. Plural messages become groups with
+/// `restype="x-gettext-plurals"` with a trans-unit for each plural form, ordered by the
+/// `Plural-Forms` of the header unit if given, or by the categories present otherwise. For
+/// languages with one plural form, the group still has a second trans-unit with the plural source
+/// and no target. Units that need work are marked with `approved="no"` and
+/// `state="needs-review-translation"`.
+///
+/// XLIFF has no place for obsolete units and previous source, so those are skipped.
+pub struct XliffWriter<W: Write> {
+    _writer: W,
+    _original: String,
+    _source_language: LanguageRange<'static>,
+    _plurals: Option<Vec<Count>>,
+    _next_id: usize,
+    _started: bool,
+    _finished: bool,
+}
+
+impl<W: Write> XliffWriter<W> {
+    pub fn new(writer: W) -> Self {
+        XliffWriter {
+            _writer: writer,
+            _original: "messages".to_owned(),
+            _source_language: LanguageRange::new("en-US").unwrap().into_static(),
+            _plurals: None,
+            _next_id: 1,
+            _started: false,
+            _finished: false,
+        }
+    }
+
+    /// Set the `original` attribute of the file, the name of the catalogue it was converted
+    /// from. The default is `messages`.
+    pub fn set_original(&mut self, original: &str) {
+        self._original = original.to_owned();
+    }
+
+    /// Set the source language. The default is `en-US`, like in translate-toolkit.
+    pub fn set_source_language(&mut self, lang: &LanguageRange) {
+        self._source_language = lang.clone().into_static();
+    }
+
+    /// Get the wrapped writer back.
+    pub fn into_inner(self) -> W {
+        self._writer
+    }
+
+    fn next_id(&mut self) -> usize {
+        self._next_id += 1;
+        self._next_id - 1
+    }
+
+    fn write_trans_unit(&mut self, indent: &str, id: &str, attrs: &str, source: &str,
+                        target: Option<&str>, state: State) -> Result<(), Error> {
+        let (approved, target_state) = match state {
+            State::Final => (" approved=\"yes\"", " state=\"translated\""),
+            State::NeedsWork => (" approved=\"no\"", " state=\"needs-review-translation\""),
+            State::Empty => ("", " state=\"new\""),
+        };
+        let mut out = format!("{}<trans-unit id=\"{}\"{}{} xml:space=\"preserve\">\n",
+                              indent, escape_xml(id)?, attrs, approved);
+        out.push_str(&format!("{}  <source>{}</source>\n", indent, escape_xml(source)?));
+        if let Some(t) = target {
+            out.push_str(&format!("{}  <target{}>{}</target>\n", indent, target_state, escape_xml(t)?));
+        }
+        self._writer.write_all(out.as_bytes()).map_err(|e| Error::Io(0, e))
+    }
+
+    fn write_annotations(&mut self, indent: &str, unit: &Unit) -> Result<(), Error> {
+        let mut out = String::new();
+        for loc in unit.locations() {
+            let (file, line) = match loc.rfind(':') {
+                Some(n) if n + 1 < loc.len() && loc[(n + 1)..].bytes().all(|b| b.is_ascii_digit()) =>
+                    (&loc[..n], Some(&loc[(n + 1)..])),
+                _ => (loc.as_str(), None),
+            };
+            out.push_str(&format!("{}  <context-group name=\"po-reference\" purpose=\"location\">\n\
+                                   {}    <context context-type=\"sourcefile\">{}</context>\n",
+                                  indent, indent, escape_xml(file)?));
+            if let Some(line) = line {
+                out.push_str(&format!("{}    <context context-type=\"linenumber\">{}</context>\n",
+                                      indent, line));
+            }
+            out.push_str(&format!("{}  </context-group>\n", indent));
+        }
+        if let Some(ref ctx) = *unit.context() {
+            out.push_str(&format!("{}  <context-group name=\"x-gettext\" purpose=\"information\">\n\
+                                   {}    <context context-type=\"x-gettext-msgctxt\">{}</context>\n\
+                                   {}  </context-group>\n",
+                                  indent, indent, escape_xml(ctx)?, indent));
+        }
+        for note in unit.notes() {
+            let from = match note.0 {
+                Origin::Developer => "developer",
+                Origin::Translator => "translator",
+                Origin::Tag(ref tag) => tag,
+            };
+            out.push_str(&format!("{}  <note from=\"{}\">{}</note>\n",
+                                  indent, escape_xml(from)?, escape_xml(&note.1)?));
+        }
+        self._writer.write_all(out.as_bytes()).map_err(|e| Error::Io(0, e))
+    }
+
+    fn write_plural_unit(&mut self, unit: &Unit) -> Result<(), Error> {
+        let (plurals, one, other) = plural_forms(unit, self._plurals.as_ref())?;
+        let target = unpack!(*unit.target() => Message::Plural(ref map) => map);
+        let id = self.next_id();
+        writeln!(self._writer, "      <group id=\"{}\" restype=\"x-gettext-plurals\">", id)
+            .map_err(|e| Error::Io(0, e))?;
+        for i in 0..plurals.len().max(2) {
+            let form = plurals.get(i).and_then(|c| target.map(|map| map.get(c).map_or("", String::as_str)));
+            let source = if i == 0 { one } else { other };
+            self.write_trans_unit("        ", &format!("{}[{}]", id, i), "", source, form, unit.state())?;
+            if i == 0 {
+                self.write_annotations("        ", unit)?;
+            }
+            writeln!(self._writer, "        </trans-unit>").map_err(|e| Error::Io(0, e))?;
+        }
+        writeln!(self._writer, "      </group>").map_err(|e| Error::Io(0, e))
+    }
+}
+
+impl<W: Write> CatalogueWriter for XliffWriter<W> {
+    fn write_header(&mut self, target_language: &LanguageRange, header: Option<&Unit>)
+            -> Result<(), Error> {
+        if self._started {
+            return Err(Error::Sequence("header written twice"));
+        }
+        self._started = true;
+        write!(self._writer, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                              <xliff xmlns=\"urn:oasis:names:tc:xliff:document:1.2\" version=\"1.2\">\n  \
+                              <file original=\"{}\" source-language=\"{}\" datatype=\"po\" \
+                              target-language=\"{}\">\n    <body>\n",
+               escape_xml(&self._original)?, self._source_language, target_language)
+            .map_err(|e| Error::Io(0, e))?;
+
+        if let Some(header) = header {
+            let text = header_text(header.target().singular().unwrap_or(""), target_language);
+            for (key, val) in header_fields(&text) {
+                if key == "Plural-Forms" {
+                    self._plurals = Some(PluralRule::parse(val)?.categories(target_language));
+                }
+            }
+            let state = match header.state() {
+                State::NeedsWork => State::NeedsWork,
+                _ => State::Final,
+            };
+            let id = self.next_id().to_string();
+            self.write_trans_unit("      ", &id, " restype=\"x-gettext-domain-header\"", &text, Some(&text),
+                                  state)?;
+            self.write_annotations("      ", header)?;
+            writeln!(self._writer, "      </trans-unit>").map_err(|e| Error::Io(0, e))?;
+        }
+        Ok(())
+    }
+
+    fn write_unit(&mut self, unit: &Unit) -> Result<(), Error> {
+        if !self._started {
+            return Err(Error::Sequence("unit written before header"));
+        }
+        if self._finished {
+            return Err(Error::Sequence("unit written after finish"));
+        }
+        if unit.is_obsolete() {
+            return Ok(());
+        }
+        if unit.source().is_plural() {
+            return self.write_plural_unit(unit);
+        }
+        let id = self.next_id().to_string();
+        let target = unit.target().singular().filter(|_| !unit.target().is_blank());
+        let source = unit.source().singular().unwrap_or("");
+        self.write_trans_unit("      ", &id, "", source, target, unit.state())?;
+        self.write_annotations("      ", unit)?;
+        writeln!(self._writer, "      </trans-unit>").map_err(|e| Error::Io(0, e))
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        if !self._started {
+            return Err(Error::Sequence("finish called before header"));
+        }
+        if !self._finished {
+            self._finished = true;
+            write!(self._writer, "    </body>\n  </file>\n</xliff>\n").map_err(|e| Error::Io(0, e))?;
+        }
+        self._writer.flush().map_err(|e| Error::Io(0, e))
+    }
+}
+
+/// Reader for XLIFF 2.0 and 2.1 files.
+///
+/// Each `<file>` is a separate catalogue. Iterating returns units of the current file; call
+/// `next_file` to continue with the following one. The languages are given by the `srcLang` and
+/// `trgLang` attributes of the document. Segments of a unit are joined into a single message.
+///
+/// Plural messages, gettext header fields, contexts and locations are read from the places
+/// `Xliff2Writer` puts them.
+pub struct Xliff2Reader {
+    _events: Events,
+    _next_unit: Option<Result<Unit, Error>>,
+    _failed: Option<Error>,
+    _done: bool,
+    _in_file: bool,
+    _file_id: String,
+    _header_unit: Option<Unit>,
+    _header: PoHeader,
+    _source_language: Option<LanguageRange<'static>>,
+    _target_language: LanguageRange<'static>,
+    _plural_rule: Option<PluralRule>,
+}
+
+impl Xliff2Reader {
+    pub fn new<R: Read>(mut reader: R) -> Self {
+        let mut bytes = Vec::new();
+        let failed = reader.read_to_end(&mut bytes).err().map(|e| Error::Io(0, e));
+        let mut res = Xliff2Reader {
+            _events: Events::new(bytes),
+            _next_unit: None,
+            _done: failed.is_some(),
+            _failed: failed,
+            _in_file: false,
+            _file_id: String::new(),
+            _header_unit: None,
+            _header: PoHeader::default(),
+            _source_language: None,
+            _target_language: LanguageRange::invariant(),
+            _plural_rule: None,
+        };
+        res.next_file();
+        res
+    }
+
+    /// Skip the rest of the current file and start reading the next one.
+    ///
+    /// Returns false if there are no more files.
+    pub fn next_file(&mut self) -> bool {
+        while self._in_file && self.next_unit().is_some() {}
+        self._next_unit = None;
+        self._header_unit = None;
+        self._header = PoHeader::default();
+        self._plural_rule = None;
+        while !self._done && !self._in_file {
+            match self._events.next() {
+                Ok(XmlEvent::StartElement { name, attributes, .. }) => {
+                    let attrs = local_attrs(attributes);
+                    let attr = |n| attrs.iter().find(|a| a.0 == n).map(|a| a.1.as_str());
+                    match name.local_name.as_str() {
+                        "xliff" => {
+                            self._source_language = attr("srcLang").map(parse_language);
+                            self._target_language = attr("trgLang")
+                                .map_or_else(LanguageRange::invariant, parse_language);
+                        }
+                        "file" => {
+                            self._file_id = attr("id").unwrap_or("").to_owned();
+                            self._in_file = true;
+                        }
+                        _ => (),
+                    }
+                }
+                Ok(XmlEvent::EndDocument) => self._done = true,
+                Ok(_) => (),
+                Err(e) => {
+                    self._failed = Some(e);
+                    self._done = true;
+                }
+            }
+        }
+        if self._in_file {
+            self._next_unit = self.next_unit();
+        }
+        self._in_file
+    }
+
+    /// Get the `id` of the current file.
+    pub fn file_id(&self) -> &str {
+        &self._file_id
+    }
+
+    fn next_unit(&mut self) -> Option<Result<Unit, Error>> {
+        if self._done || !self._in_file {
+            return None;
+        }
+        match self.parse_unit() {
+            Ok(None) => None,
+            Ok(Some(u)) => Some(Ok(u)),
+            Err(e) => {
+                // the document can't be read any further after malformed XML
+                self._done = is!(e => Error::Xml(..));
+                Some(Err(e))
+            }
+        }
+    }
+
+    fn parse_unit(&mut self) -> Result<Option<Unit>, Error> {
+        loop {
+            match self._events.next()? {
+                XmlEvent::StartElement { name, attributes, .. } => {
+                    let attrs = local_attrs(attributes);
+                    let line = self._events.line();
+                    let plurals = attrs.iter().any(|a| a.0 == "type" && a.1 == "x-gettext:plurals");
+                    match name.local_name.as_str() {
+                        "unit" => {
+                            let elem = self._events.read_element(name.local_name, attrs)?;
+                            let (source, target, state) = segments(&elem, line)?;
+                            let mut unit = Unit {
+                                _source: Message::Singular(source),
+                                _target: target.map_or(Message::Empty, Message::Singular),
+                                _state: state,
+                                _line: line,
+                                ..Unit::default()
+                            };
+                            read_annotations2(&elem, &mut unit);
+                            return Ok(Some(unit));
+                        }
+                        "group" if plurals => {
+                            let elem = self._events.read_element(name.local_name, attrs)?;
+                            return self.make_plural_unit(&elem, line).map(Some);
+                        }
+                        "group" => (),
+                        "metadata" => {
+                            let elem = self._events.read_element(name.local_name, attrs)?;
+                            self.read_header(&elem)?;
+                        }
+                        "notes" => {
+                            let elem = self._events.read_element(name.local_name, attrs)?;
+                            if let Some(ref mut header) = self._header_unit {
+                                read_notes2(&elem, header);
+                            }
+                        }
+                        _ => { self._events.read_element(name.local_name, attrs)?; }
+                    }
+                }
+                XmlEvent::EndElement { ref name } if name.local_name == "file" => {
+                    self._in_file = false;
+                    return Ok(None);
+                }
+                XmlEvent::EndDocument => {
+                    self._done = true;
+                    return Ok(None);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn read_header(&mut self, metadata: &Element) -> Result<(), Error> {
+        let mut text = String::new();
+        let header = |g: &&Element| g.attr("category") == Some("x-gettext-header");
+        for group in metadata.elements("metaGroup").filter(header) {
+            for meta in group.elements("meta") {
+                text.push_str(&format!("{}: {}\n", meta.attr("type").unwrap_or(""), meta.text()));
+            }
+        }
+        if text.is_empty() {
+            return Ok(());
+        }
+        for (key, val) in header_fields(&text) {
+            if key == "Plural-Forms" {
+                self._plural_rule = Some(PluralRule::parse(val)?);
+            }
+        }
+        let unit = Unit {
+            _source: Message::Singular(String::new()),
+            _target: Message::Singular(text),
+            _state: State::Final,
+            ..Unit::default()
+        };
+        self._header = PoHeader::from_unit(&unit);
+        self._header_unit = Some(unit);
+        Ok(())
+    }
+
+    fn make_plural_unit(&self, group: &Element, line: usize) -> Result<Unit, Error> {
+        let forms = group.elements("unit").map(|u| segments(u, line)).collect::<Result<Vec<_>, _>>()?;
+        if forms.is_empty() {
+            return Err(Error::Parse(line, None, vec!["<unit>"]));
+        }
+        let plurals = match self._plural_rule {
+            Some(ref rule) => rule.categories(&self._target_language),
+            None => plural::language_categories(&self._target_language, forms.len()),
+        };
+        check_plural_forms(line, plurals.len(), forms.len())?;
+
+        let mut source = BTreeMap::new();
+        let mut target = BTreeMap::new();
+        source.insert(Count::One, forms[0].0.clone());
+        source.insert(Count::Other, forms[forms.len() - 1].0.clone());
+        for (c, form) in plurals.iter().zip(&forms) {
+            if let Some(ref t) = form.1 {
+                target.insert(*c, t.clone());
+            }
+        }
+        let mut unit = Unit { _source: Message::Plural(source), _line: line, ..Unit::default() };
+        if !target.is_empty() {
+            for &c in &plurals {
+                target.entry(c).or_insert_with(String::new);
+            }
+            unit._target = Message::Plural(target);
+            unit._state = forms.iter().take(plurals.len()).map(|f| f.2).min().unwrap();
+        }
+        read_annotations2(group, &mut unit);
+        Ok(unit)
+    }
+}
+
+// Source, target and state of a unit, joined from all its segments.
+fn segments(unit: &Element, line: usize) -> Result<(String, Option<String>, State), Error> {
+    let mut source = String::new();
+    let mut target = None;
+    let mut state = State::Final;
+    let mut any = false;
+    for n in &unit.children {
+        let seg = match *n {
+            Node::Element(ref e) if e.name == "segment" || e.name == "ignorable" => e,
+            _ => continue,
+        };
+        any = true;
+        let s = seg.element("source").ok_or_else(|| Error::Parse(line, None, vec!["<source>"]))?;
+        source.push_str(&s.text());
+        if let Some(t) = seg.element("target") {
+            target.get_or_insert_with(String::new).push_str(&t.text());
+        }
+        let done = ["translated", "reviewed", "final"].iter().any(|&s| seg.attr("state") == Some(s));
+        if seg.name == "segment" && !done {
+            state = State::NeedsWork;
+        }
+    }
+    if !any {
+        return Err(Error::Parse(line, None, vec!["<segment>"]));
+    }
+    if target.as_ref().is_none_or(String::is_empty) {
+        state = State::Empty;
+    }
+    Ok((source, target, state))
+}
+
+// Read notes, and context and locations from the `x-gettext` metadata group.
+fn read_annotations2(elem: &Element, unit: &mut Unit) {
+    for metadata in elem.elements("metadata") {
+        for group in metadata.elements("metaGroup").filter(|g| g.attr("category") == Some("x-gettext")) {
+            for meta in group.elements("meta") {
+                match meta.attr("type") {
+                    Some("msgctxt") => unit._context = Some(meta.text()),
+                    Some("location") => unit._locations.push(meta.text()),
+                    _ => (),
+                }
+            }
+        }
+    }
+    for notes in elem.elements("notes") {
+        read_notes2(notes, unit);
+    }
+}
+
+fn read_notes2(notes: &Element, unit: &mut Unit) {
+    for note in notes.elements("note") {
+        let origin = match note.attr("category") {
+            Some("developer") => Origin::Developer,
+            None | Some("translator") => Origin::Translator,
+            Some(tag) => Origin::Tag(tag.to_owned()),
+        };
+        unit._notes.push((origin, note.text()));
+    }
+}
+
+impl Iterator for Xliff2Reader {
+    type Item = Result<Unit, Error>;
+    fn next(&mut self) -> Option<Result<Unit, Error>> {
+        if let Some(e) = self._failed.take() {
+            return Some(Err(e));
+        }
+
+        self._next_unit.as_ref()?;
+
+        let mut res = self.next_unit();
+        ::std::mem::swap(&mut res, &mut self._next_unit);
+        res
+    }
+}
+
+impl CatalogueReader for Xliff2Reader {
+    fn target_language(&self) -> &LanguageRange<'static> {
+        &self._target_language
+    }
+
+    /// Get the gettext header unit of the current file, if it has one.
+    ///
+    /// It is made from the `x-gettext-header` metadata of the file and its notes. It is not
+    /// returned by the iterator, but is needed to write the catalogue back to PO.
+    fn header_unit(&self) -> Option<&Unit> {
+        self._header_unit.as_ref()
+    }
+
+    /// Get the source language, from the `srcLang` attribute.
+    fn source_language(&self) -> Option<&LanguageRange<'static>> {
+        self._source_language.as_ref()
+    }
+
+    /// Get the properties of the current file, from its `x-gettext-header` metadata.
+    fn properties(&self) -> Vec<(&str, &str)> {
+        self._header.fields().collect()
+    }
+}
+
+/// Writer for XLIFF 2.0 files.
+///
+/// Writes the catalogue as a single `<file>`, which XLIFF 2.1 tools read as well. What XLIFF has
+/// no place for is stored using the metadata module: gettext header fields in a `metaGroup` with
+/// category `x-gettext-header` of the file, and contexts and locations in a `metaGroup` with
+/// category `x-gettext` of each unit. Plural messages become groups with type
+/// `x-gettext:plurals` with a unit for each plural form, and at least two units so that both
+/// sources are kept. Final units have segment state `translated`, other units `initial`.
+///
+/// Obsolete units and previous source are skipped.
+pub struct Xliff2Writer<W: Write> {
+    _writer: W,
+    _original: Option<String>,
+    _source_language: LanguageRange<'static>,
+    _plurals: Option<Vec<Count>>,
+    _next_id: usize,
+    _started: bool,
+    _finished: bool,
+}
+
+impl<W: Write> Xliff2Writer<W> {
+    pub fn new(writer: W) -> Self {
+        Xliff2Writer {
+            _writer: writer,
+            _original: None,
+            _source_language: LanguageRange::new("en-US").unwrap().into_static(),
+            _plurals: None,
+            _next_id: 1,
+            _started: false,
+            _finished: false,
+        }
+    }
+
+    /// Set the `original` attribute of the file, the name of the catalogue it was converted
+    /// from. It is not written by default.
+    pub fn set_original(&mut self, original: &str) {
+        self._original = Some(original.to_owned());
+    }
+
+    /// Set the source language. The default is `en-US`, like in translate-toolkit.
+    pub fn set_source_language(&mut self, lang: &LanguageRange) {
+        self._source_language = lang.clone().into_static();
+    }
+
+    /// Get the wrapped writer back.
+    pub fn into_inner(self) -> W {
+        self._writer
+    }
+
+    fn next_id(&mut self) -> usize {
+        self._next_id += 1;
+        self._next_id - 1
+    }
+
+    fn write_segment(&mut self, indent: &str, source: &str, target: Option<&str>, state: State)
+            -> Result<(), Error> {
+        let state = if state == State::Final { "translated" } else { "initial" };
+        let mut out = format!("{}<segment state=\"{}\">\n{}  <source>{}</source>\n",
+                              indent, state, indent, escape_xml(source)?);
+        if let Some(t) = target {
+            out.push_str(&format!("{}  <target>{}</target>\n", indent, escape_xml(t)?));
+        }
+        out.push_str(&format!("{}</segment>\n", indent));
+        self._writer.write_all(out.as_bytes()).map_err(|e| Error::Io(0, e))
+    }
+
+    fn write_annotations(&mut self, indent: &str, unit: &Unit) -> Result<(), Error> {
+        let mut out = String::new();
+        if unit.context().is_some() || !unit.locations().is_empty() {
+            out.push_str(&format!("{}<mda:metadata>\n{}  <mda:metaGroup category=\"x-gettext\">\n",
+                                  indent, indent));
+            let metas = unit.context().iter().map(|c| ("msgctxt", c))
+                .chain(unit.locations().iter().map(|l| ("location", l)));
+            for (kind, value) in metas {
+                out.push_str(&format!("{}    <mda:meta type=\"{}\">{}</mda:meta>\n",
+                                      indent, kind, escape_xml(value)?));
+            }
+            out.push_str(&format!("{}  </mda:metaGroup>\n{}</mda:metadata>\n", indent, indent));
+        }
+        if !unit.notes().is_empty() {
+            out.push_str(&format!("{}<notes>\n", indent));
+            for note in unit.notes() {
+                let category = match note.0 {
+                    Origin::Developer => "developer",
+                    Origin::Translator => "translator",
+                    Origin::Tag(ref tag) => tag,
+                };
+                out.push_str(&format!("{}  <note category=\"{}\">{}</note>\n",
+                                      indent, escape_xml(category)?, escape_xml(&note.1)?));
+            }
+            out.push_str(&format!("{}</notes>\n", indent));
+        }
+        self._writer.write_all(out.as_bytes()).map_err(|e| Error::Io(0, e))
+    }
+
+    fn write_plural_unit(&mut self, unit: &Unit) -> Result<(), Error> {
+        let (plurals, one, other) = plural_forms(unit, self._plurals.as_ref())?;
+        let target = unpack!(*unit.target() => Message::Plural(ref map) => map);
+        let id = self.next_id();
+        writeln!(self._writer, "    <group id=\"g{}\" type=\"x-gettext:plurals\">", id)
+            .map_err(|e| Error::Io(0, e))?;
+        self.write_annotations("      ", unit)?;
+        for i in 0..plurals.len().max(2) {
+            let form = plurals.get(i).and_then(|c| target.map(|map| map.get(c).map_or("", String::as_str)));
+            writeln!(self._writer, "      <unit id=\"u{}-{}\">", id, i).map_err(|e| Error::Io(0, e))?;
+            self.write_segment("        ", if i == 0 { one } else { other }, form, unit.state())?;
+            writeln!(self._writer, "      </unit>").map_err(|e| Error::Io(0, e))?;
+        }
+        writeln!(self._writer, "    </group>").map_err(|e| Error::Io(0, e))
+    }
+}
+
+impl<W: Write> CatalogueWriter for Xliff2Writer<W> {
+    fn write_header(&mut self, target_language: &LanguageRange, header: Option<&Unit>)
+            -> Result<(), Error> {
+        if self._started {
+            return Err(Error::Sequence("header written twice"));
+        }
+        self._started = true;
+        let original = match self._original {
+            Some(ref o) => format!(" original=\"{}\"", escape_xml(o)?),
+            None => String::new(),
+        };
+        write!(self._writer, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                              <xliff xmlns=\"urn:oasis:names:tc:xliff:document:2.0\" \
+                              xmlns:mda=\"urn:oasis:names:tc:xliff:metadata:2.0\" version=\"2.0\" \
+                              srcLang=\"{}\" trgLang=\"{}\">\n  \
+                              <file id=\"f1\"{}>\n",
+               self._source_language, target_language, original)
+            .map_err(|e| Error::Io(0, e))?;
+
+        if let Some(header) = header {
+            let text = header_text(header.target().singular().unwrap_or(""), target_language);
+            let mut out = String::from("    <mda:metadata>\n      \
+                                        <mda:metaGroup category=\"x-gettext-header\">\n");
+            for (key, val) in header_fields(&text) {
+                if key == "Plural-Forms" {
+                    self._plurals = Some(PluralRule::parse(val)?.categories(target_language));
+                }
+                out.push_str(&format!("        <mda:meta type=\"{}\">{}</mda:meta>\n",
+                                      escape_xml(key)?, escape_xml(val)?));
+            }
+            out.push_str("      </mda:metaGroup>\n    </mda:metadata>\n");
+            self._writer.write_all(out.as_bytes()).map_err(|e| Error::Io(0, e))?;
+            let notes = Unit { _notes: header.notes().clone(), ..Unit::default() };
+            self.write_annotations("    ", &notes)?;
+        }
+        Ok(())
+    }
+
+    fn write_unit(&mut self, unit: &Unit) -> Result<(), Error> {
+        if !self._started {
+            return Err(Error::Sequence("unit written before header"));
+        }
+        if self._finished {
+            return Err(Error::Sequence("unit written after finish"));
+        }
+        if unit.is_obsolete() {
+            return Ok(());
+        }
+        if unit.source().is_plural() {
+            return self.write_plural_unit(unit);
+        }
+        let id = self.next_id();
+        writeln!(self._writer, "    <unit id=\"u{}\">", id).map_err(|e| Error::Io(0, e))?;
+        self.write_annotations("      ", unit)?;
+        let target = unit.target().singular().filter(|_| !unit.target().is_blank());
+        self.write_segment("      ", unit.source().singular().unwrap_or(""), target, unit.state())?;
+        writeln!(self._writer, "    </unit>").map_err(|e| Error::Io(0, e))
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        if !self._started {
+            return Err(Error::Sequence("finish called before header"));
+        }
+        if !self._finished {
+            self._finished = true;
+            write!(self._writer, "  </file>\n</xliff>\n").map_err(|e| Error::Io(0, e))?;
+        }
+        self._writer.flush().map_err(|e| Error::Io(0, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{CatalogueReader, CatalogueWriter};
+    use ::Count::*;
+    use ::locale_config::LanguageRange;
+    use ::Message::*;
+    use ::Origin::*;
+    use ::po::{PoReader, PoWriter};
+    use super::{Xliff2Reader, Xliff2Writer, XliffReader, XliffWriter};
+
+    static SAMPLE_XLIFF: &str = r###"<?xml version="1.0" encoding="utf-8"?>
+<xliff version="1.2" xmlns="urn:oasis:names:tc:xliff:document:1.2">
+  <file original="test.po" source-language="en-US" datatype="po" target-language="cs"
+        product-name="test" product-version="1.0" date="2017-04-24T21:39:00+02:00">
+    <header>
+      <tool tool-id="tt" tool-name="Translate Toolkit" tool-version="2.2"/>
+    </header>
+    <body>
+      <trans-unit id="1" restype="x-gettext-domain-header" approved="no" xml:space="preserve">
+        <source>Language: cs
+Plural-Forms: nplurals=3; plural=(n==1) ? 0 : (n&gt;=2 &amp;&amp; n&lt;=4) ? 1 : 2;
+</source>
+        <target>Language: cs
+Plural-Forms: nplurals=3; plural=(n==1) ? 0 : (n&gt;=2 &amp;&amp; n&lt;=4) ? 1 : 2;
+</target>
+      </trans-unit>
+      <trans-unit id="Simple message" approved="yes" xml:space="preserve">
+        <source>Simple message</source>
+        <target state="translated">Jednoduchá zpráva</target>
+      </trans-unit>
+      <trans-unit id="Changed message" approved="no" xml:space="preserve">
+        <source>Changed message</source>
+        <target state="needs-review-translation">Změněná
+zpráva</target>
+        <context-group name="po-reference" purpose="location">
+          <context context-type="sourcefile">Location</context>
+          <context context-type="linenumber">42</context>
+        </context-group>
+        <context-group name="po-entry" purpose="information">
+          <context context-type="x-po-autocomment">Extracted comment</context>
+        </context-group>
+        <context-group name="x-gettext" purpose="information">
+          <context context-type="x-gettext-msgctxt">ConTeXt</context>
+        </context-group>
+        <note from="developer">Extracted comment</note>
+        <note from="po-translator">Translator comment</note>
+      </trans-unit>
+      <trans-unit id="Untranslated message" xml:space="preserve">
+        <source>Untranslated <g id="1">message</g></source>
+      </trans-unit>
+      <group restype="x-gettext-plurals">
+        <trans-unit id="%d file[0]" approved="yes" xml:space="preserve">
+          <source>%d file</source>
+          <target>%d soubor</target>
+          <context-group name="po-entry" purpose="information">
+            <context context-type="x-po-trancomment">Only in context group</context>
+          </context-group>
+        </trans-unit>
+        <trans-unit id="%d file[1]" approved="yes" xml:space="preserve">
+          <source>%d files</source>
+          <target>%d soubory</target>
+        </trans-unit>
+        <trans-unit id="%d file[2]" approved="yes" xml:space="preserve">
+          <source>%d files</source>
+          <target><![CDATA[%d souborů]]></target>
+        </trans-unit>
+      </group>
+    </body>
+  </file>
+</xliff>
+"###;
+
+    #[test]
+    fn integration_test() {
+        let mut reader = XliffReader::new(SAMPLE_XLIFF.as_bytes());
+
+        assert_eq!(LanguageRange::new("cs").unwrap(), *reader.target_language());
+        assert_eq!(LanguageRange::new("en-US").unwrap(), *reader.source_language().unwrap());
+        assert!(reader.header_unit().unwrap().target().singular().unwrap().starts_with("Language: cs\n"));
+        assert!(reader.header_unit().unwrap().source().is_blank());
+        assert_eq!(Some("test 1.0"), reader.project());
+        assert_eq!("2017-04-24T21:39:00+02:00", reader.revision_date().unwrap().to_rfc3339());
+        assert_eq!(Some("Translate Toolkit 2.2"), reader.generator());
+        let keys = reader.properties().into_iter().map(|p| p.0).collect::<Vec<_>>();
+        assert_eq!(vec!["Language", "Plural-Forms"], keys);
+        assert_eq!(Some("cs"), reader.property("Language"));
+
+        let u1 = reader.next().unwrap().unwrap();
+        assert_eq!(Singular("Simple message".to_owned()), *u1.source());
+        assert_eq!(Singular("Jednoduchá zpráva".to_owned()), *u1.target());
+        assert_eq!(::State::Final, u1.state());
+
+        let u2 = reader.next().unwrap().unwrap();
+        assert_eq!(Some("ConTeXt".to_owned()), *u2.context());
+        assert_eq!(Singular("Změněná\nzpráva".to_owned()), *u2.target());
+        assert_eq!(::State::NeedsWork, u2.state());
+        assert_eq!(vec!["Location:42".to_owned()], *u2.locations());
+        assert_eq!(vec![(Developer, "Extracted comment".to_owned()),
+                        (Translator, "Translator comment".to_owned())], *u2.notes());
+
+        let u3 = reader.next().unwrap().unwrap();
+        assert_eq!(Singular("Untranslated message".to_owned()), *u3.source());
+        assert_eq!(Empty, *u3.target());
+        assert_eq!(::State::Empty, u3.state());
+
+        let u4 = reader.next().unwrap().unwrap();
+        let plural = |s: &[(::Count, &str)]| Plural(s.iter().map(|&(c, s)| (c, s.to_owned())).collect());
+        assert_eq!(plural(&[(One, "%d file"), (Other, "%d files")]), *u4.source());
+        assert_eq!(plural(&[(One, "%d soubor"), (Few, "%d soubory"), (Other, "%d souborů")]), *u4.target());
+        assert_eq!(vec![(Translator, "Only in context group".to_owned())], *u4.notes());
+        assert_eq!(::State::Final, u4.state());
+
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn errors() {
+        let mut reader = XliffReader::new(r#"<xliff version="1.2">
+<file target-language="de"><body>
+<trans-unit id="1"><target>Ziel</target></trans-unit>
+<trans-unit id="2"><source>Source</source>
+</body></file></xliff>"#.as_bytes());
+        match reader.next() {
+            Some(Err(::Error::Parse(3, None, ref exp))) => assert_eq!(vec!["<source>"], *exp),
+            x => panic!("Unexpected {:?}", x),
+        }
+        match reader.next() {
+            Some(Err(::Error::Xml(5, _))) => (),
+            x => panic!("Unexpected {:?}", x),
+        }
+    }
+
+    #[test]
+    fn invalid_plural_forms() {
+        let xliff = SAMPLE_XLIFF.replace("<target>Language: cs\nPlural-Forms: nplurals=3; plural=(n==1) ?",
+                                         "<target>Language: cs\nPlural-Forms: nplurals=3; plural=(n==1 ?");
+        let mut reader = XliffReader::new(xliff.as_bytes());
+        match reader.next() {
+            Some(Err(::Error::Parse(9, _, _))) => (),
+            x => panic!("Unexpected {:?}", x),
+        }
+        assert_eq!("Simple message", reader.next().unwrap().unwrap().source().singular().unwrap());
+    }
+
+    #[test]
+    fn languages_of_first_file() {
+        let mut reader = XliffReader::new(r#"<xliff version="1.2">
+<file source-language="en" target-language="de"><body>
+<trans-unit id="1"><source>Source</source><target>Quelle</target></trans-unit>
+</body></file>
+<file source-language="fr" target-language="cs"><body>
+<trans-unit id="2"><source>Source</source><target>Zdroj</target></trans-unit>
+</body></file></xliff>"#.as_bytes());
+        assert_eq!(2, reader.by_ref().filter(|u| u.is_ok()).count());
+        assert_eq!(LanguageRange::new("en").unwrap(), *reader.source_language().unwrap());
+        assert_eq!(LanguageRange::new("de").unwrap(), *reader.target_language());
+
+        let mut reader = XliffReader::new(r#"<xliff version="1.2">
+<file target-language="de"><body>
+<trans-unit id="1"><source>Source</source><target>Quelle</target></trans-unit>
+</body></file></xliff>"#.as_bytes());
+        assert!(reader.next().unwrap().is_ok());
+        assert_eq!(None, reader.source_language());
+    }
+
+    #[test]
+    fn one_plural_form() {
+        let xliff = |forms: &str| format!(r#"<xliff version="1.2">
+<file source-language="en" target-language="ja"><body>
+<trans-unit id="1" restype="x-gettext-domain-header">
+<source>Plural-Forms: nplurals=1; plural=0;
+</source>
+<target>Plural-Forms: nplurals=1; plural=0;
+</target>
+</trans-unit>
+<group id="2" restype="x-gettext-plurals">{}</group>
+</body></file></xliff>"#, forms);
+        let mut source = ::std::collections::BTreeMap::new();
+        source.insert(One, "%d file".to_owned());
+        source.insert(Other, "%d files".to_owned());
+
+        let unit = XliffReader::new(xliff(r#"
+<trans-unit id="2[0]"><source>%d file</source><target>%d ファイル</target></trans-unit>
+<trans-unit id="2[1]"><source>%d files</source></trans-unit>"#).as_bytes())
+            .next().unwrap().unwrap();
+        assert_eq!(Plural(source), *unit.source());
+        assert_eq!(Plural(vec![(Other, "%d ファイル".to_owned())].into_iter().collect()), *unit.target());
+
+        let unit = XliffReader::new(xliff(r#"
+<trans-unit id="2[0]"><source>%d files</source><target>%d ファイル</target></trans-unit>"#).as_bytes())
+            .next().unwrap().unwrap();
+        assert_eq!(Some("%d files"), unpack!(*unit.source() => Plural(ref m) => m[&Other].as_str()));
+        assert_eq!("%d files", unit.source().key());
+
+        match XliffReader::new(xliff(&r#"<trans-unit id="2[0]"><source>x</source></trans-unit>"#
+                                     .repeat(3)).as_bytes()).next() {
+            Some(Err(::Error::PluralForms(9, 1, 3))) => (),
+            x => panic!("Unexpected {:?}", x),
+        }
+    }
+
+    #[test]
+    fn one_plural_form_round_trip() {
+        let po = r#"msgid ""
+msgstr ""
+"Language: ja\n"
+"Plural-Forms: nplurals=1; plural=0;\n"
+
+msgid "%d file"
+msgid_plural "%d files"
+msgstr[0] "%d ファイル"
+
+msgid "%d dir"
+msgid_plural "%d dirs"
+msgstr[0] ""
+"#;
+        let mut reader = PoReader::new(po.as_bytes());
+        let units = (&mut reader).collect::<Result<Vec<_>, _>>().unwrap();
+        let mut writer = XliffWriter::new(Vec::new());
+        writer.write_header(reader.target_language(), reader.header_unit()).unwrap();
+        for unit in &units {
+            writer.write_unit(unit).unwrap();
+        }
+        writer.finish().unwrap();
+        let xliff = String::from_utf8(writer.into_inner()).unwrap();
+        assert!(xliff.contains("<source>%d files</source>\n        </trans-unit>"), "{}", xliff);
+
+        let mut reader = XliffReader::new(xliff.as_bytes());
+        let mut writer = PoWriter::new(Vec::new());
+        writer.write_header(reader.target_language(), reader.header_unit()).unwrap();
+        for unit in &mut reader {
+            writer.write_unit(&unit.unwrap()).unwrap();
+        }
+        writer.finish().unwrap();
+        let round_trip = String::from_utf8(writer.into_inner()).unwrap();
+        assert_eq!(po, round_trip);
+    }
+
+    #[test]
+    fn one_plural_form_xliff2() {
+        let xliff = |forms: &str| format!(r#"<xliff xmlns="urn:oasis:names:tc:xliff:document:2.0"
+    xmlns:mda="urn:oasis:names:tc:xliff:metadata:2.0" version="2.0" srcLang="en" trgLang="ja">
+<file id="f1">
+<mda:metadata><mda:metaGroup category="x-gettext-header">
+<mda:meta type="Plural-Forms">nplurals=1; plural=0;</mda:meta>
+</mda:metaGroup></mda:metadata>
+<group id="g1" type="x-gettext:plurals">{}</group>
+</file></xliff>"#, forms);
+        let mut source = ::std::collections::BTreeMap::new();
+        source.insert(One, "%d file".to_owned());
+        source.insert(Other, "%d files".to_owned());
+
+        let unit = Xliff2Reader::new(xliff(r#"
+<unit id="u1-0"><segment state="translated"><source>%d file</source><target>%d ファイル</target></segment></unit>
+<unit id="u1-1"><segment state="translated"><source>%d files</source></segment></unit>"#).as_bytes())
+            .next().unwrap().unwrap();
+        assert_eq!(Plural(source), *unit.source());
+        assert_eq!(Plural(vec![(Other, "%d ファイル".to_owned())].into_iter().collect()), *unit.target());
+        assert_eq!(::State::Final, unit.state());
+
+        let unit = Xliff2Reader::new(xliff(r#"
+<unit id="u1-0"><segment><source>%d files</source><target>%d ファイル</target></segment></unit>"#).as_bytes())
+            .next().unwrap().unwrap();
+        assert_eq!(Some("%d files"), unpack!(*unit.source() => Plural(ref m) => m[&Other].as_str()));
+    }
+
+    #[test]
+    fn one_plural_form_round_trip_xliff2() {
+        let po = r#"msgid ""
+msgstr ""
+"Language: ja\n"
+"Plural-Forms: nplurals=1; plural=0;\n"
+
+msgid "%d file"
+msgid_plural "%d files"
+msgstr[0] "%d ファイル"
+"#;
+        let mut reader = PoReader::new(po.as_bytes());
+        let units = (&mut reader).collect::<Result<Vec<_>, _>>().unwrap();
+        let mut writer = Xliff2Writer::new(Vec::new());
+        writer.write_header(reader.target_language(), reader.header_unit()).unwrap();
+        for unit in &units {
+            writer.write_unit(unit).unwrap();
+        }
+        writer.finish().unwrap();
+        let xliff = String::from_utf8(writer.into_inner()).unwrap();
+        assert!(xliff.contains("<source>%d files</source>\n        </segment>"), "{}", xliff);
+
+        let unit = Xliff2Reader::new(xliff.as_bytes()).next().unwrap().unwrap();
+        assert_eq!(units[0].source(), unit.source());
+        assert_eq!(units[0].target(), unit.target());
+        assert_eq!(units[0].state(), unit.state());
+    }
+
+    #[test]
+    fn write_xliff() {
+        let mut reader = XliffReader::new(SAMPLE_XLIFF.as_bytes());
+        let units = (&mut reader).collect::<Result<Vec<_>, _>>().unwrap();
+        let mut writer = XliffWriter::new(Vec::new());
+        writer.set_original("test.po");
+        writer.write_header(reader.target_language(), reader.header_unit()).unwrap();
+        for u in &units {
+            writer.write_unit(u).unwrap();
+        }
+        writer.finish().unwrap();
+        let out = String::from_utf8(writer.into_inner()).unwrap();
+        assert!(out.contains("<trans-unit id=\"3\" approved=\"no\" xml:space=\"preserve\">\n        \
+                              <source>Changed message</source>\n        \
+                              <target state=\"needs-review-translation\">Změněná\nzpráva</target>\n"));
+        assert!(out.contains("<group id=\"5\" restype=\"x-gettext-plurals\">\n        \
+                              <trans-unit id=\"5[0]\" approved=\"yes\" xml:space=\"preserve\">\n"));
+
+        assert!(out.contains("<trans-unit id=\"1\" restype=\"x-gettext-domain-header\" approved=\"no\" \
+                              xml:space=\"preserve\">\n        <source>Language: cs\n"));
+
+        let mut reader2 = XliffReader::new(out.as_bytes());
+        assert_eq!(reader.target_language(), reader2.target_language());
+        assert_eq!(reader.header_unit().unwrap().target(), reader2.header_unit().unwrap().target());
+        for u in &units {
+            let u2 = reader2.next().unwrap().unwrap();
+            assert_eq!((u.context(), u.source(), u.target(), u.state()),
+                       (u2.context(), u2.source(), u2.target(), u2.state()));
+            assert_eq!((u.notes(), u.locations()), (u2.notes(), u2.locations()));
+        }
+        assert!(reader2.next().is_none());
+
+        let mut writer = XliffWriter::new(Vec::new());
+        match writer.write_unit(&units[0]) {
+            Err(::Error::Sequence(_)) => (),
+            x => panic!("Unexpected {:?}", x),
+        }
+    }
+
+    static SAMPLE_XLIFF2: &str = r###"<?xml version="1.0" encoding="UTF-8"?>
+<xliff xmlns="urn:oasis:names:tc:xliff:document:2.0" xmlns:mda="urn:oasis:names:tc:xliff:metadata:2.0"
+       version="2.1" srcLang="en" trgLang="pl">
+  <file id="messages">
+    <mda:metadata>
+      <mda:metaGroup category="x-gettext-header">
+        <mda:meta type="Language">pl</mda:meta>
+        <mda:meta type="Plural-Forms">nplurals=3; plural=(n==1 ? 0 : n%10&gt;=2 &amp;&amp; n%10&lt;=4 &amp;&amp; (n%100&lt;10 || n%100&gt;=20) ? 1 : 2);</mda:meta>
+      </mda:metaGroup>
+    </mda:metadata>
+    <notes>
+      <note>Copyright notice</note>
+    </notes>
+    <unit id="u1">
+      <mda:metadata>
+        <mda:metaGroup category="x-gettext">
+          <mda:meta type="msgctxt">menu</mda:meta>
+          <mda:meta type="location">src/main.rs:42</mda:meta>
+        </mda:metaGroup>
+      </mda:metadata>
+      <notes>
+        <note category="developer">Extracted comment</note>
+      </notes>
+      <segment state="translated">
+        <source>First sentence. </source>
+        <target>Pierwsze zdanie. </target>
+      </segment>
+      <ignorable>
+        <source>
+</source>
+      </ignorable>
+      <segment id="s2" state="reviewed">
+        <source>Second sentence.</source>
+        <target>Drugie zdanie.</target>
+      </segment>
+    </unit>
+    <group id="g1" type="x-gettext:plurals">
+      <unit id="u2-0">
+        <segment state="initial">
+          <source>%d file</source>
+          <target>%d plik</target>
+        </segment>
+      </unit>
+      <unit id="u2-1">
+        <segment>
+          <source>%d files</source>
+          <target>%d pliki</target>
+        </segment>
+      </unit>
+      <unit id="u2-2">
+        <segment>
+          <source>%d files</source>
+          <target>%d plików</target>
+        </segment>
+      </unit>
+    </group>
+    <unit id="u3">
+      <segment>
+        <source>Untranslated</source>
+      </segment>
+    </unit>
+  </file>
+  <file id="other">
+    <unit id="u1">
+      <segment state="final">
+        <source>Other file</source>
+        <target>Inny plik</target>
+      </segment>
+    </unit>
+  </file>
+</xliff>
+"###;
+
+    #[test]
+    fn read_xliff2() {
+        let mut reader = Xliff2Reader::new(SAMPLE_XLIFF2.as_bytes());
+        assert_eq!(LanguageRange::new("pl").unwrap(), *reader.target_language());
+        assert_eq!(LanguageRange::new("en").unwrap(), *reader.source_language().unwrap());
+        assert_eq!("messages", reader.file_id());
+        let header = reader.header_unit().unwrap();
+        assert!(header.target().singular().unwrap().starts_with("Language: pl\nPlural-Forms: nplurals=3;"));
+        assert_eq!(vec![(Translator, "Copyright notice".to_owned())], *header.notes());
+        assert_eq!(Some("pl"), reader.property("Language"));
+
+        let u1 = reader.next().unwrap().unwrap();
+        assert_eq!(Some("menu".to_owned()), *u1.context());
+        assert_eq!(Singular("First sentence. \nSecond sentence.".to_owned()), *u1.source());
+        assert_eq!(Singular("Pierwsze zdanie. Drugie zdanie.".to_owned()), *u1.target());
+        assert_eq!(::State::Final, u1.state());
+        assert_eq!(vec!["src/main.rs:42".to_owned()], *u1.locations());
+        assert_eq!(vec![(Developer, "Extracted comment".to_owned())], *u1.notes());
+
+        let u2 = reader.next().unwrap().unwrap();
+        let plural = |s: &[(::Count, &str)]| Plural(s.iter().map(|&(c, s)| (c, s.to_owned())).collect());
+        assert_eq!(plural(&[(One, "%d file"), (Other, "%d files")]), *u2.source());
+        assert_eq!(plural(&[(One, "%d plik"), (Few, "%d pliki"), (Other, "%d plików")]), *u2.target());
+        assert_eq!(::State::NeedsWork, u2.state());
+
+        let u3 = reader.next().unwrap().unwrap();
+        assert_eq!(Empty, *u3.target());
+        assert_eq!(::State::Empty, u3.state());
+        assert!(reader.next().is_none());
+
+        assert!(reader.next_file());
+        assert_eq!("other", reader.file_id());
+        assert!(reader.header_unit().is_none());
+        assert_eq!(Singular("Inny plik".to_owned()), *reader.next().unwrap().unwrap().target());
+        assert!(reader.next().is_none());
+        assert!(!reader.next_file());
+    }
+
+    #[test]
+    fn xliff2_without_source_language() {
+        let mut reader = Xliff2Reader::new(r#"<xliff xmlns="urn:oasis:names:tc:xliff:document:2.0"
+    version="2.0" trgLang="de">
+<file id="f"><unit id="1"><segment>
+<source>Source</source><target>Quelle</target>
+</segment></unit></file>
+</xliff>"#.as_bytes());
+        assert!(reader.next().unwrap().is_ok());
+        assert_eq!(None, reader.source_language());
+        assert_eq!(LanguageRange::new("de").unwrap(), *reader.target_language());
+    }
+
+    #[test]
+    fn write_xliff2() {
+        let mut reader = Xliff2Reader::new(SAMPLE_XLIFF2.as_bytes());
+        let units = (&mut reader).collect::<Result<Vec<_>, _>>().unwrap();
+        let mut writer = Xliff2Writer::new(Vec::new());
+        writer.set_source_language(reader.source_language().unwrap());
+        writer.write_header(reader.target_language(), reader.header_unit()).unwrap();
+        for u in &units {
+            writer.write_unit(u).unwrap();
+        }
+        writer.finish().unwrap();
+        let out = String::from_utf8(writer.into_inner()).unwrap();
+        assert!(out.contains("<xliff xmlns=\"urn:oasis:names:tc:xliff:document:2.0\" \
+                              xmlns:mda=\"urn:oasis:names:tc:xliff:metadata:2.0\" version=\"2.0\" \
+                              srcLang=\"en\" trgLang=\"pl\">\n"));
+        assert!(out.contains("    <group id=\"g2\" type=\"x-gettext:plurals\">\n      \
+                              <unit id=\"u2-0\">\n        <segment state=\"initial\">\n"));
+
+        let mut reader2 = Xliff2Reader::new(out.as_bytes());
+        assert_eq!(reader.header_unit().unwrap().target(), reader2.header_unit().unwrap().target());
+        assert_eq!(reader.header_unit().unwrap().notes(), reader2.header_unit().unwrap().notes());
+        for u in &units {
+            let u2 = reader2.next().unwrap().unwrap();
+            assert_eq!((u.context(), u.source(), u.target(), u.state()),
+                       (u2.context(), u2.source(), u2.target(), u2.state()));
+            assert_eq!((u.notes(), u.locations()), (u2.notes(), u2.locations()));
+        }
+        assert!(reader2.next().is_none());
+    }
+}